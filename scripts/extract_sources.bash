@@ -1,8 +1,9 @@
-# 1: pkgbuild name to enter
+# 1: pkgbuild name to enter, 2: target arch, host arch if not set
 LIBRARY="${LIBRARY:-/usr/share/makepkg}"
 source "${LIBRARY}/"util.sh
 source "${LIBRARY}/"source.sh
 source_makepkg_config
+CARCH="${2:-${CARCH}}"
 source "$1"/PKGBUILD
 SRCDEST="$1"
 HOLDVER=1
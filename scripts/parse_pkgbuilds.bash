@@ -2,6 +2,7 @@ LIBRARY="${LIBRARY:-/usr/share/makepkg}"
 source "${LIBRARY}/"util.sh
 source "${LIBRARY}/"source.sh
 source_makepkg_config
+CARCH="${1:-${CARCH}}" # 1: target arch, host arch if not set
 dump_array_with_optional_arch() { #1: var name, 2: report name
   declare -n array="$1"
   declare -n array_arch="$1_${CARCH}"
@@ -10,7 +11,36 @@ dump_array_with_optional_arch() { #1: var name, 2: report name
     echo "$2:${item}"
   done
 }
-while read -r line; do
+dump_package_vars() { #1: package function, 2: pkgname
+  unset depends provides
+  eval $(declare -f "$1" | sed --quiet 's/ \+\(depends=.\+\);/\1/p; s/ \+\(provides=.\+\);/\1/p')
+  dump_array_with_optional_arch depends dep_"$2"
+  dump_array_with_optional_arch provides provide_"$2"
+}
+# Each entry is the PKGBUILD name then either #ID of an env already sent, or a
+# new env as KEY=VALUE items ended by an empty one, each terminated by NUL
+_arb_kvs=()
+_arb_env_start=()
+_arb_env_len=()
+while IFS= read -r -d '' line; do
+  IFS= read -r -d '' _arb_kv
+  if [[ "${_arb_kv}" == '#'* ]]; then
+    _arb_id="${_arb_kv#'#'}"
+  else
+    _arb_id="${#_arb_env_start[@]}"
+    _arb_env_start+=("${#_arb_kvs[@]}")
+    while [[ "${_arb_kv}" ]]; do
+      _arb_kvs+=("${_arb_kv}")
+      IFS= read -r -d '' _arb_kv
+    done
+    _arb_env_len+=($(( ${#_arb_kvs[@]} - _arb_env_start[_arb_id] )))
+  fi
+  _arb_env=()
+  _arb_start="${_arb_env_start[_arb_id]}"
+  for _arb_kv in "${_arb_kvs[@]:_arb_start:_arb_env_len[_arb_id]}"; do
+    export "${_arb_kv}"
+    _arb_env+=("${_arb_kv%%=*}")
+  done
   source ./"${line}"
   echo "[PKGBUILD]"
   pkgbase="${pkgbase:-${pkgname}}"
@@ -18,28 +48,66 @@ while read -r line; do
   for item in "${pkgname[@]}"; do
     echo "name:${item}"
   done
+  for item in "${arch[@]}"; do
+    echo "arch:${item}"
+  done
   dump_array_with_optional_arch depends dep
   dump_array_with_optional_arch makedepends makedep
+  dump_array_with_optional_arch checkdepends checkdep
   dump_array_with_optional_arch provides provide
-  dump_array_with_optional_arch source source
+  get_all_sources_for_arch 'all_sources'
+  for item in "${all_sources[@]}"; do
+    echo "source:$(get_filename "${item}")"
+    protocol=$(get_protocol "${item}")
+    echo "protocol:${protocol}"
+    url=$(get_url "${item}")
+    case "${protocol}" in
+      bzr)
+        if [[ $url != bzr+ssh* ]]; then
+          url=${url#bzr+}
+        fi
+        url=${url%%#*}
+        ;;
+      fossil)
+        url=${url#fossil+}
+        url=${url%%#*}
+        url=${url%%\?*}
+        ;;
+      git)
+        url=${url#git+}
+        url=${url%%#*}
+        url=${url%%\?*}
+        ;;
+      hg)
+        url=${url#hg+}
+        url=${url%%#*}
+        ;;
+      svn)
+        if [[ $url != svn+ssh* ]]; then
+          url=${url#svn+}
+        fi
+        url=${url%%#*}
+        ;;
+    esac
+    echo "url:${url}"
+  done
   for integ in {ck,md5,sha{1,224,256,384,512},b2}; do
     dump_array_with_optional_arch "${integ}"sums "${integ}"
   done
   echo -n "pkgver_func:"
   if [[ $(type -t pkgver) == 'function' ]]; then echo y; else echo n; fi
-  unset -f pkgver
-  unset depends provides
-  eval $(declare -f package | sed --quiet 's/ \+\(depends=.\+\);/\1/p; s/ \+\(provides=.\+\);/\1/p')
-  dump_array_with_optional_arch depends dep_"${pkgbase}"
-  dump_array_with_optional_arch provides provide_"${pkgbase}"
+  if [[ $(type -t package) == 'function' ]]; then
+    dump_package_vars package "${pkgname}"
+  fi
   for item in "${pkgname[@]}"; do
-    unset depends provides
-    eval $(declare -f package_"${item}" | sed --quiet 's/ \+\(depends=.\+\);/\1/p; s/ \+\(provides=.\+\);/\1/p')
-    dump_array_with_optional_arch depends dep_"${item}"
-    dump_array_with_optional_arch provides provide_"${item}"
+    if [[ $(type -t package_"${item}") == 'function' ]]; then
+      dump_package_vars package_"${item}" "${item}"
+    fi
   done
-  unset pkgbase pkgname {depends,makedepends,provides,source}{,_"${CARCH}"}
+  unset -f pkgver package "${pkgname[@]/#/package_}"
+  unset "${_arb_env[@]}"
+  unset pkgbase pkgname arch {depends,makedepends,checkdepends,provides,source}{,_"${CARCH}"}
   for _integ in {ck,md5,sha{1,224,256,384,512},b2}; do
     unset "${_integ}sums" "${_integ}sums_${CARCH}"
   done
-done
\ No newline at end of file
+done
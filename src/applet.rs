@@ -1,2 +1,3 @@
 mod arb;
 
+pub(crate) use arb::main as arb;
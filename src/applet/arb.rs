@@ -0,0 +1,457 @@
+// The arb applet, i.e. the builder itself. The pipeline is split into stages
+// sync -> fetch -> build, each of them could be run alone as a subcommand,
+// and running without any subcommand runs all of them in one go
+
+use std::{
+        collections::HashMap,
+        path::Path,
+    };
+
+use crate::{
+        build,
+        bundle,
+        config::{
+            self,
+            Command,
+        },
+        filesystem,
+        hook,
+        identity,
+        pkgbuild::{
+            PkgbuildDefaults,
+            PKGBUILDs,
+        },
+        plan::Plan,
+        publish,
+        report::Report,
+        repo,
+        root::BaseConfigs,
+        sign,
+        source::{
+            self,
+            git::Gmr,
+            FetchSettings,
+        },
+        state,
+    };
+
+struct Settings {
+    actual_identity: crate::identity::IdentityActual,
+    pkgbuilds_config: HashMap<String, config::Pkgbuild>,
+    basepkgs: Vec<String>,
+    proxy: Option<source::Proxy>,
+    holdpkg: bool,
+    holdgit: bool,
+    urlcache: bool,
+    holdurl: bool,
+    skipint: bool,
+    nobuild: bool,
+    noclean: bool,
+    nonet: bool,
+    retry_failed: bool,
+    gmr: Option<String>,
+    mirrors: source::Mirrors,
+    dephash_strategy: config::DepHashStrategy,
+    sign: Option<sign::Signer>,
+    home_binds: Vec<String>,
+    env: HashMap<String, String>,
+    makepkg_args: Vec<String>,
+    check: bool,
+    repo: Option<config::Repo>,
+    publish: Option<config::Publish>,
+    hooks: Vec<config::Hook>,
+    arches: HashMap<String, config::Arch>,
+    pacman: config::Pacman,
+    makepkg: config::Makepkg,
+    command: Option<Command>,
+    terminal: bool
+}
+
+fn log_setup(target: env_logger::Target) {
+    env_logger::Builder::from_env(
+        env_logger::Env::default().filter_or(
+            "ARB_LOG_LEVEL", "info")
+        ).target(target).init();
+}
+
+fn prepare() -> Result<Settings, &'static str> {
+    let arg: config::Arg = clap::Parser::parse();
+    // Keep stdout clean for commands printing results to it
+    log_setup(match arg.command {
+        Some(Command::Status | Command::List | Command::Plan { .. }) =>
+            env_logger::Target::Stderr,
+        _ => env_logger::Target::Stdout,
+    });
+    let mut config: config::Config = serde_yaml::from_reader(
+        std::fs::File::open(&arg.config).or_else(
+        |e|{
+            log::error!("Failed to open config file '{}': {}", arg.config, e);
+            Err("Failed to open config file")
+        })?)
+    .or_else(
+    |e|{
+        log::error!("Failed to parse YAML: {}", e);
+        Err("Failed to parse YAML config")
+    })?;
+    if let Some(pkgext) = &config.makepkg.pkgext {
+        if ! repo::supports_pkgext(pkgext) {
+            log::error!("Unsupported makepkg pkgext '{}', only '.pkg.tar' \
+                optionally with '.gz', '.xz' or '.zst' is supported", pkgext);
+            return Err("Unsupported makepkg pkgext")
+        }
+    }
+    let actual_identity = if arg.rootless || config.rootless {
+        if arg.drop.is_some() {
+            log::warn!("Ignored --drop as we're running rootless");
+        }
+        identity::IdentityActual::new_rootless_and_drop()
+    } else {
+        identity::IdentityActual::new_and_drop(arg.drop.as_deref())
+    }.or_else(|_|Err("Failed to get actual identity"))?;
+    if ! arg.build.is_empty() {
+        if let Some(Command::Clean) = arg.command {
+            log::error!("Cleaning with only some packages would remove all \
+                sources and packages of the others");
+            return Err("Refused to clean with --build")
+        }
+        log::warn!("Only build the following packages: {:?}", arg.build);
+        config.pkgbuilds.retain(|name, _|arg.build.contains(name));
+    }
+    let proxy = source::Proxy::from_str_usize(
+        arg.proxy.as_deref().or(config.proxy.as_deref()),
+        match arg.proxy_after {
+            Some(proxy_after) => proxy_after,
+            None => match config.proxy_after {
+                Some(proxy_after) => proxy_after,
+                None => 0,
+            },
+        });
+    let sign = match arg.sign.or(config.sign) {
+        Some(key) => Some(sign::Signer::new(&key, config.sign_key.as_deref())
+            .or(Err("Failed to prepare signer"))?),
+        None => None,
+    };
+    // Stages after sync work on PKGBUILDs as they are, and those after fetch
+    // also on sources as they are, so the pipeline could be run piece by piece
+    let (holdpkg, holdsrc) = match arg.command {
+        Some(Command::Fetch) => (true, false),
+        Some(Command::Build | Command::Clean | Command::Status |
+            Command::List | Command::Plan { .. }) => (true, true),
+        _ => (false, false),
+    };
+    let noclean = match arg.command {
+        Some(Command::Clean) => false,
+        Some(Command::Status | Command::List | Command::Plan { .. }) => true,
+        _ => !arg.build.is_empty() || arg.noclean || config.noclean,
+    };
+    Ok(Settings {
+        actual_identity,
+        pkgbuilds_config: config.pkgbuilds,
+        basepkgs: config.basepkgs,
+        proxy,
+        holdpkg: holdpkg || arg.holdpkg || config.holdpkg,
+        holdgit: holdsrc || arg.holdgit || config.holdgit,
+        urlcache: arg.urlcache || config.urlcache,
+        holdurl: holdsrc || arg.holdurl || config.holdurl,
+        skipint: arg.skipint || config.skipint,
+        nobuild: arg.nobuild || config.nobuild,
+        noclean,
+        nonet: arg.nonet || config.nonet,
+        retry_failed: arg.retry_failed,
+        gmr: arg.gmr.or(config.gmr),
+        mirrors: source::Mirrors::from_config(&config.mirrors)
+            .or(Err("Invalid mirrors"))?,
+        dephash_strategy: config.dephash_strategy,
+        sign,
+        home_binds: config.home_binds,
+        env: config.env,
+        makepkg_args: config.makepkg_args,
+        check: config.check,
+        repo: config.repo,
+        publish: config.publish,
+        hooks: config.hooks,
+        arches: config.arches,
+        pacman: config.pacman,
+        makepkg: config.makepkg,
+        command: arg.command,
+        terminal: is_terminal::is_terminal(std::io::stdout())
+    })
+}
+
+impl Settings {
+    /// The target arches, only the host arch if none configured
+    fn target_arches(&self) -> Vec<Option<&str>> {
+        if self.arches.is_empty() {
+            return vec![None]
+        }
+        let mut arches: Vec<Option<&str>> = self.arches.keys().map(
+            |arch|Some(arch.as_str())).collect();
+        arches.sort_unstable();
+        arches
+    }
+
+    fn base_configs(&self) -> BaseConfigs<'_> {
+        BaseConfigs {
+            arches: &self.arches,
+            pacman: &self.pacman,
+            makepkg: &self.makepkg,
+        }
+    }
+
+    fn pkgbuild_defaults(&self) -> PkgbuildDefaults<'_> {
+        PkgbuildDefaults {
+            home_binds: &self.home_binds,
+            env: &self.env,
+            makepkg_args: &self.makepkg_args,
+            check: self.check,
+            arches: &self.arches,
+        }
+    }
+
+    fn fetch_settings<'a>(&'a self, gmr: Option<&'a Gmr>)
+        -> FetchSettings<'a>
+    {
+        FetchSettings {
+            holdgit: self.holdgit,
+            urlcache: self.urlcache,
+            holdurl: self.holdurl,
+            skipint: self.skipint,
+            noclean: self.noclean,
+            proxy: self.proxy.as_ref(),
+            gmr,
+            mirrors: &self.mirrors,
+            terminal: self.terminal,
+        }
+    }
+}
+
+/// Sync PKGBUILDs repos only
+fn sync(settings: &Settings, gmr: Option<&Gmr>)
+    -> Result<PKGBUILDs, &'static str>
+{
+    PKGBUILDs::from_config_healthy(
+        &settings.pkgbuilds_config, settings.holdpkg,
+        settings.noclean, settings.proxy.as_ref(),
+        gmr, &settings.pkgbuild_defaults(), settings.terminal
+    ).or(Err("Failed to prepare PKGBUILDs list"))
+}
+
+fn fetch(settings: &Settings, gmr: Option<&Gmr>) -> Result<(), &'static str> {
+    sync(settings, gmr)?.fetch(
+        &settings.actual_identity, &settings.fetch_settings(gmr)
+    ).or(Err("Failed to fetch sources"))
+}
+
+/// Sync, fetch and fill pkgids, without building anything
+fn resolve(settings: &Settings, gmr: Option<&Gmr>)
+    -> Result<PKGBUILDs, &'static str>
+{
+    let mut pkgbuilds = sync(settings, gmr)?;
+    let r = pkgbuilds.resolve(
+        &settings.actual_identity, &settings.fetch_settings(gmr),
+        settings.base_configs(), &settings.dephash_strategy,
+        settings.retry_failed
+    ).or(Err("Failed to resolve pkgids"));
+    // Only sources of PKGBUILDs with pkgver() were extracted for it
+    if Path::new("build").exists() {
+        let _ = filesystem::remove_dir_all_try_best("build");
+    }
+    r.and(Ok(pkgbuilds))
+}
+
+/// Sync, fetch and build, pkgbuilds is set once they're synced
+fn build_stages(
+    settings: &Settings, gmr: Option<&Gmr>, state: &mut state::StateDb,
+    pkgbuilds: &mut Option<PKGBUILDs>
+) -> Result<(), &'static str>
+{
+    let pkgbuilds = pkgbuilds.insert(sync(settings, gmr)?);
+    let roots = pkgbuilds.prepare_sources(
+        &settings.actual_identity, &settings.basepkgs,
+        &settings.fetch_settings(gmr), settings.base_configs(),
+        &settings.dephash_strategy, settings.retry_failed
+        ).or_else(|_|Err("Failed to prepare sources"))?;
+    let r = build::maybe_build(pkgbuilds,
+        roots, &settings.actual_identity, settings.nobuild, settings.nonet,
+         settings.sign.as_ref(), state);
+    let _ = std::fs::remove_dir("build");
+    pkgbuilds.link_pkgs();
+    let arches = settings.target_arches();
+    if ! settings.noclean {
+        pkgbuilds.clean_pkgdir(&arches);
+    }
+    let r_repo = match &settings.repo {
+        Some(repo) => arches.iter().try_for_each(|arch|repo::update(
+            repo, &settings.actual_identity, settings.sign.as_ref(),
+            &filesystem::pkgs_dir(*arch))),
+        None => Ok(()),
+    };
+    // Old assets are only pruned if the old packages are also cleaned locally
+    let r_publish = match &settings.publish {
+        Some(publish) if r_repo.is_ok() =>
+            publish::publish(publish, ! settings.noclean,
+                settings.repo.as_ref().map(|repo|repo.name.as_str()),
+                &arches),
+        _ => Ok(()),
+    };
+    if r.is_err() {
+        Err("Failed to build")
+    } else if r_repo.is_err() {
+        Err("Failed to update repo DB")
+    } else if r_publish.is_err() {
+        Err("Failed to publish")
+    } else {
+        Ok(())
+    }
+}
+
+/// Run all stages, then record the run and write its report, even if any of
+/// the stages failed
+fn build(settings: &Settings, gmr: Option<&Gmr>) -> Result<(), &'static str> {
+    filesystem::reset_pkgs_links(&settings.target_arches())
+        .or(Err("Failed to reset pkgs links"))?;
+    let mut state = state::StateDb::open()
+        .or(Err("Failed to open state DB"))?;
+    let mut pkgbuilds = None;
+    let r = build_stages(settings, gmr, &mut state, &mut pkgbuilds);
+    let r_state = state.finish_run(
+        if r.is_ok() {
+            state::Outcome::Success
+        } else {
+            state::Outcome::Failure
+        });
+    let (r_report, r_hooks) =
+        match Report::new(&state, pkgbuilds.as_ref(), r.err())
+    {
+        Ok(report) => (report.write(), hook::fire_run_end(&settings.hooks,
+            &settings.actual_identity, &state, &report,
+            &settings.target_arches())),
+        Err(e) => (Err(e), Ok(())),
+    };
+    if r.is_err() {
+        r
+    } else if r_state.is_err() {
+        Err("Failed to record run into state DB")
+    } else if r_report.is_err() {
+        Err("Failed to write run report")
+    } else if r_hooks.is_err() {
+        Err("Failed to run hooks")
+    } else {
+        Ok(())
+    }
+}
+
+/// Remove unused sources and outdated packages, and relink `latest` of each
+/// arch as links to the removed ones would be dangling
+fn clean(settings: &Settings, gmr: Option<&Gmr>) -> Result<(), &'static str> {
+    let pkgbuilds = resolve(settings, gmr)?;
+    let arches = settings.target_arches();
+    for arch in arches.iter() {
+        let dir = filesystem::pkgs_dir(*arch);
+        let latest = dir.join("latest");
+        filesystem::remove_dir_allow_non_existing(&latest)
+            .and(filesystem::create_dir_allow_existing(&dir))
+            .and(filesystem::create_dir_allow_existing(&latest))
+            .or(Err("Failed to reset latest pkgs links"))?;
+    }
+    pkgbuilds.link_pkgs();
+    pkgbuilds.clean_pkgdir(&arches);
+    Ok(())
+}
+
+/// Print what would be (re)built and why
+fn status(settings: &Settings, gmr: Option<&Gmr>) -> Result<(), &'static str> {
+    let pkgbuilds = resolve(settings, gmr)?;
+    let state = state::StateDb::open()
+        .or(Err("Failed to open state DB"))?;
+    for pkgbuild in pkgbuilds.0.iter() {
+        if pkgbuild.need_build {
+            println!("{}: build '{}', {}", pkgbuild.key(), pkgbuild.pkgid,
+                state.reason(pkgbuild))
+        } else if pkgbuild.built() {
+            println!("{}: up to date '{}'", pkgbuild.key(), pkgbuild.pkgid)
+        } else {
+            println!("{}: skip '{}', failed all tries before, use \
+                --retry-failed to retry", pkgbuild.key(), pkgbuild.pkgid)
+        }
+    }
+    Ok(())
+}
+
+/// Print pkgids and their packages, the package files if they're built, or
+/// the package names if not
+fn list(settings: &Settings, gmr: Option<&Gmr>) -> Result<(), &'static str> {
+    let pkgbuilds = resolve(settings, gmr)?;
+    for pkgbuild in pkgbuilds.0.iter() {
+        if pkgbuild.built() {
+            println!("{}", pkgbuild.pkgid);
+            for package in repo::list_packages(pkgbuild.pkgdir())
+                .or(Err("Failed to list packages"))?
+            {
+                println!("    {}", package)
+            }
+        } else {
+            println!("{} (not built)", pkgbuild.pkgid);
+            for name in pkgbuild.names() {
+                println!("    {}", name)
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Print the plan of what would be built and why, as text or JSON
+fn plan(settings: &Settings, gmr: Option<&Gmr>, json: bool)
+    -> Result<(), &'static str>
+{
+    let pkgbuilds = resolve(settings, gmr)?;
+    let state = state::StateDb::open()
+        .or(Err("Failed to open state DB"))?;
+    let plan = Plan::new(&pkgbuilds, &state)
+        .or(Err("Failed to plan PKGBUILDs"))?;
+    if json {
+        plan.print_json().or(Err("Failed to print plan"))
+    } else {
+        plan.print_text();
+        Ok(())
+    }
+}
+
+fn export(settings: &Settings, gmr: Option<&Gmr>, bundle: &str)
+    -> Result<(), &'static str>
+{
+    let mut pkgbuilds =
+        PKGBUILDs::from_config_healthy(
+            &settings.pkgbuilds_config, settings.holdpkg,
+            true, settings.proxy.as_ref(),
+            gmr, &settings.pkgbuild_defaults(), settings.terminal
+        ).or(Err("Failed to prepare PKGBUILDs list"))?;
+    let paths = pkgbuilds.bundle_paths(
+        &settings.actual_identity, &settings.fetch_settings(gmr)
+        ).or(Err("Failed to prepare sources"))?;
+    bundle::export(&paths, Path::new(bundle))
+        .or(Err("Failed to export bundle"))
+}
+
+fn work(mut settings: Settings) -> Result<(), &'static str> {
+    let gmr = settings.gmr.as_deref().map(Gmr::init);
+    let gmr = gmr.as_ref();
+    filesystem::create_layout().or(Err("Failed to create layout"))?;
+    match settings.command.take() {
+        None | Some(Command::Build) => build(&settings, gmr),
+        Some(Command::Sync) => sync(&settings, gmr).and(Ok(())),
+        Some(Command::Fetch) => fetch(&settings, gmr),
+        Some(Command::Clean) => clean(&settings, gmr),
+        Some(Command::Status) => status(&settings, gmr),
+        Some(Command::List) => list(&settings, gmr),
+        Some(Command::Plan { json }) => plan(&settings, gmr, json),
+        Some(Command::Export { bundle }) => export(&settings, gmr, &bundle),
+        Some(Command::Import { bundle }) =>
+            bundle::import(Path::new(&bundle))
+                .or(Err("Failed to import bundle")),
+    }
+}
+
+pub(crate) fn main() -> Result<(), &'static str> {
+    work(prepare()?)
+}
@@ -1,32 +1,36 @@
 mod builder;
 mod dir;
 
-use crate::error::Result;
+use crate::{
+        error::Result,
+        sign::Signer,
+        state::StateDb,
+    };
 
 pub(crate) fn maybe_build(
     pkgbuilds: &crate::pkgbuild::PKGBUILDs,
-    root: Option<crate::root::BaseRoot>,
+    roots: Option<Vec<crate::root::BaseRoot>>,
     actual_identity: &crate::identity::IdentityActual,
     nobuild: bool,
     nonet: bool,
-    sign: Option<&str>
+    sign: Option<&Signer>,
+    state: &mut StateDb
 ) -> Result<()>
 {
-    if let Some(_root) = root {
+    if let Some(_roots) = roots {
         if nobuild {
             return Ok(())
         }
-        match crate::depend::split_pkgbuilds(pkgbuilds) {
-            Ok(layers) => {
-                for layer in layers {
-                    builder::build_any_needed_layer(
-                        &layer, &actual_identity, nonet, sign)?
-
-                }
+        let graph = match crate::depend::graph_pkgbuilds(pkgbuilds) {
+            Ok(graph) => graph,
+            Err(_) => {
+                log::warn!("Failed to resolve dependencies among PKGBUILDs, \
+                    building all of them without order");
+                pkgbuilds.0.iter().map(|pkgbuild|(pkgbuild, vec![])).collect()
             },
-            Err(_) => builder::build_any_needed(
-                        &pkgbuilds, &actual_identity, nonet, sign)?,
-        }
+        };
+        builder::build_any_needed(
+            graph, &actual_identity, nonet, sign, state)?
     }
     Ok(())
 }
\ No newline at end of file
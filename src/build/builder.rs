@@ -1,4 +1,6 @@
 use std::{
+        collections::HashSet,
+        io::Write,
         path::PathBuf,
         process::{
             Child,
@@ -20,14 +22,17 @@ use crate::{
             LogFile,
             LogType,
         },
-        pkgbuild::{
-            PKGBUILD,
-            PKGBUILDs,
-        },
+        pkgbuild::PKGBUILD,
         root::{
             OverlayRoot,
             BootstrappingOverlayRoot,
         },
+        sign::Signer,
+        state::{
+            FailureMarker,
+            Outcome,
+            StateDb,
+        },
     };
 
 enum RootState {
@@ -73,17 +78,23 @@ struct Builder<'a> {
     root_state: RootState,
     build_state: BuildState,
     log_path: PathBuf,
+    nonet: bool,
+    start: Option<String>,
+    /// Other PKGBUILDs that must be built before this could start, their
+    /// packages are installed into our root
+    wants: Vec<&'a PKGBUILD>,
 }
 
 impl <'a> Builder<'a> {
     const BUILD_MAX_TRIES: usize = 3;
-    fn from_pkgbuild(pkgbuild: &'a PKGBUILD, actual_identity: &IdentityActual)
-        -> Result<Self>
+    fn from_pkgbuild(pkgbuild: &'a PKGBUILD, actual_identity: &IdentityActual,
+        nonet: bool, wants: Vec<&'a PKGBUILD>
+    ) -> Result<Self>
     {
-        let builddir = BuildDir::new(&pkgbuild.base)?;
+        let builddir = BuildDir::new(pkgbuild.key())?;
         let temp_pkgdir = pkgbuild.get_temp_pkgdir()?;
         let command = pkgbuild.get_build_command(
-            actual_identity, &temp_pkgdir)?;
+            actual_identity, &temp_pkgdir, nonet)?;
         let build_state = if pkgbuild.extracted {
             BuildState::Extracted
         } else {
@@ -98,6 +109,9 @@ impl <'a> Builder<'a> {
             root_state: RootState::default(),
             build_state,
             log_path: PathBuf::new(),
+            nonet: pkgbuild.nonet(nonet),
+            start: None,
+            wants,
         })
     }
 
@@ -105,20 +119,20 @@ impl <'a> Builder<'a> {
         match self.pkgbuild.extractor_source(actual_identity) {
             Ok(child) => {
                 log::info!("Start extracting for pkgbuild '{}'",
-                    &self.pkgbuild.base);
+                    self.pkgbuild.key());
                 self.build_state = BuildState::Extracting { child };
                 Ok(())
             },
             Err(e) => {
                 log::error!("Failed to get extractor for pkgbuild\
-                 '{}'", &self.pkgbuild.base);
+                 '{}'", self.pkgbuild.key());
                 Err(e)
             },
         }
     }
 
     fn step_build(&mut self,  heavy_load: bool, actual_identity: &IdentityActual,
-        sign: Option<&str>, jobs: &mut usize ) -> Result<()>
+        sign: Option<&Signer>, jobs: &mut usize ) -> Result<()>
     {
         match &mut self.build_state {
             BuildState::None =>
@@ -135,11 +149,11 @@ impl <'a> Builder<'a> {
                             if let Some(0) = code {
                                 log::info!(
                                     "Successfully extracted source for \
-                                    pkgbuild '{}'", &self.pkgbuild.base);
+                                    pkgbuild '{}'", self.pkgbuild.key());
                                 self.build_state = BuildState::Extracted;
                             } else {
                                 log::error!("Failed to extract source for \
-                                    pkgbuild '{}'", &self.pkgbuild.base);
+                                    pkgbuild '{}'", self.pkgbuild.key());
                                 return Err(Error::BadChild { pid: None, code })
                             }
                         },
@@ -153,24 +167,35 @@ impl <'a> Builder<'a> {
                 },
             BuildState::Extracted =>
                 if ! heavy_load {
-                    let log_file = LogFile::new(
+                    let mut log_file = LogFile::new(
                         LogType::Build, &self.pkgbuild.pkgid)?;
                     self.log_path = log_file.path.clone();
+                    if self.nonet {
+                        if let Err(e) = writeln!(log_file.file,
+                            "==> Building in a network namespace with only \
+                            loopback, any connection to outside would fail")
+                        {
+                            log::error!("Failed to write to build log: {}", e);
+                            return Err(e.into())
+                        }
+                    }
                     let child = match 
                         log_file.set_command(&mut self.command)?.spawn()
                     {
                         Ok(child) => child,
                         Err(e) => {
                             log::error!("Failed to spawn builder for '{}': {}",
-                                &self.pkgbuild.base, e);
+                                self.pkgbuild.key(), e);
                             return Err(e.into())
                         },
                     };
                     self.build_state = BuildState::Building { child };
                     self.tries += 1;
                     *jobs += 1;
-                    log::info!("Start building '{}', try {} of {}",
-                        &self.pkgbuild.base, self.tries, Self::BUILD_MAX_TRIES);
+                    log::info!("Start building '{}'{}, try {} of {}",
+                        self.pkgbuild.key(),
+                        if self.nonet { " without network" } else { "" },
+                        self.tries, Self::BUILD_MAX_TRIES);
                 },
             BuildState::Building { child } =>
                 match child.try_wait() {
@@ -183,15 +208,24 @@ impl <'a> Builder<'a> {
                             if let Some(0) = r.code() {
                                 self.pkgbuild.finish_build(actual_identity,
                                     &self.temp_pkgdir, sign)?;
+                                FailureMarker::clear(&self.pkgbuild.pkgid)?;
                                 log::info!("Successfully built '{}'",
-                                    &self.pkgbuild.base);
+                                    self.pkgbuild.key());
                                 self.build_state = BuildState::Built;
                             } else {
                                 log::error!("Failed to build '{}'",
-                                    &self.pkgbuild.base);
+                                    self.pkgbuild.key());
+                                if self.nonet {
+                                    log::error!("'{}' was built without \
+                                        network, if its build() really needs \
+                                        network, set `network: true` for it",
+                                        self.pkgbuild.key());
+                                }
                                 if self.tries >= Self::BUILD_MAX_TRIES {
                                     log::error!("Max retries exceeded for '{}'",
-                                        &self.pkgbuild.base);
+                                        self.pkgbuild.key());
+                                    FailureMarker::mark(&self.pkgbuild.pkgid,
+                                        self.tries, Some(&self.log_path))?;
                                     return Err(Error::BuildFailure)
                                 }
                                 // Only needed when we want to re-extract
@@ -229,23 +263,26 @@ impl <'a> Builder<'a> {
     }
 
     fn step(&mut self, heavy_load: bool, actual_identity: &IdentityActual,
-            nonet: bool, sign: Option<&str>, jobs: &mut usize ) -> Result<()>
+            nonet: bool, sign: Option<&Signer>, jobs: &mut usize ) -> Result<()>
     {
         match &mut self.root_state {
             RootState::None => if ! heavy_load {
+                if self.start.is_none() {
+                    self.start = crate::state::now().ok();
+                }
                 match self.pkgbuild.get_bootstrapping_overlay_root(
-                    actual_identity, nonet)
+                    actual_identity, nonet, &self.wants)
                 {
                     Ok(bootstrapping_root) => {
                         log::info!("Start chroot bootstrapping for pkgbuild '{}'",
-                            &self.pkgbuild.base);
+                            self.pkgbuild.key());
                         self.root_state = RootState::Boostrapping {
                             bootstrapping_root };
                         *jobs += 1;
                     },
                     Err(e) => {
                         log::error!("Failed to get chroot bootstrapper for \
-                            pkgbuild '{}'", &self.pkgbuild.base);
+                            pkgbuild '{}'", self.pkgbuild.key());
                         return Err(e)
                     },
                 }
@@ -271,12 +308,13 @@ impl <'a> Builder<'a> {
                                         self.root_state =
                                             RootState::Bootstrapped { root };
                                         log::info!("Chroot bootstrapped for \
-                                            pkgbuild '{}'", &self.pkgbuild.base);
+                                            pkgbuild '{}'",
+                                            self.pkgbuild.key());
                                     },
                                     Err(e) => {
                                         log::error!("Failed to bootstrap chroot \
                                             for pkgbuild '{}'",
-                                            &self.pkgbuild.base);
+                                            self.pkgbuild.key());
                                         return Err(e)
                                     },
                                 }
@@ -337,49 +375,37 @@ struct Builders<'a> {
     builders: Vec<Builder<'a>>,
     actual_identity: &'a IdentityActual,
     nonet: bool,
-    sign: Option<&'a str>
+    sign: Option<&'a Signer>,
+    state: &'a mut StateDb,
 }
 
 impl<'a> Builders<'a> {
-    fn from_pkgbuilds(
-        pkgbuilds: &'a PKGBUILDs, actual_identity: &'a IdentityActual,
-        nonet: bool, sign: Option<&'a str>
-    ) -> Result<Self>
+    /// Create builders for PKGBUILDs that need build. Wanted PKGBUILDs that
+    /// don't need build are either already built or failed before, the latter
+    /// are returned to let their dependents be skipped.
+    fn from_graph(
+        graph: Vec<(&'a PKGBUILD, Vec<&'a PKGBUILD>)>,
+        actual_identity: &'a IdentityActual, nonet: bool,
+        sign: Option<&'a Signer>, state: &'a mut StateDb
+    ) -> Result<(Self, HashSet<&'a str>)>
     {
         BuildDir::prepare()?;
         let mut builders = vec![];
-        for pkgbuild in pkgbuilds.0.iter() {
+        let mut failed = HashSet::new();
+        for (pkgbuild, wants) in graph {
             if ! pkgbuild.need_build {
                 continue
             }
-            match Builder::from_pkgbuild(pkgbuild, actual_identity) {
-                Ok(builder) => builders.push(builder),
-                Err(e) => {
-                    log::error!("Failed to create builder for pkgbuild");
-                    return Err(e)
-                },
-            }
-        }
-        Ok(Self {
-            builders,
-            actual_identity,
-            nonet,
-            sign,
-        })
-    }
-
-    fn from_pkgbuild_layer(
-        pkgbuild_layer: &Vec<&'a PKGBUILD>, actual_identity: &'a IdentityActual,
-        nonet: bool, sign: Option<&'a str>
-    ) -> Result<Self>
-    {
-        BuildDir::prepare()?;
-        let mut builders = vec![];
-        for pkgbuild in pkgbuild_layer.iter() {
-            if ! pkgbuild.need_build {
-                continue
+            for want in wants.iter() {
+                if ! want.need_build &&
+                    FailureMarker::read(&want.pkgid).is_some()
+                {
+                    failed.insert(want.key());
+                }
             }
-            match Builder::from_pkgbuild(pkgbuild, actual_identity) {
+            match Builder::from_pkgbuild(
+                pkgbuild, actual_identity, nonet, wants)
+            {
                 Ok(builder) => builders.push(builder),
                 Err(e) => {
                     log::error!("Failed to create builder for pkgbuild: {}", e);
@@ -387,15 +413,18 @@ impl<'a> Builders<'a> {
                 },
             }
         }
-        Ok(Self {
+        Ok((Self {
             builders,
             actual_identity,
             nonet,
             sign,
-        })
+            state,
+        }, failed))
     }
 
-    fn work(&mut self)  -> Result<()>
+    /// Step all builders until all are finished, a builder only starts after
+    /// all PKGBUILDs it wants are built, and is skipped if any of them failed
+    fn work(&mut self, mut failed: HashSet<&'a str>)  -> Result<()>
     {
         let cpuinfo = match procfs::CpuInfo::new() {
             Ok(cpuinfo) => cpuinfo,
@@ -407,33 +436,70 @@ impl<'a> Builders<'a> {
         let cores = cpuinfo.num_cores();
         let mut r = Ok(());
         let mut jobs = 0;
+        let mut built = HashSet::new();
         loop {
             // let jobs_last = jobs;
-            let mut finished = None;
+            let mut finished = vec![];
             for (id, builder) in
                 self.builders.iter_mut().enumerate()
             {
+                if let Some(want) = builder.wants.iter().find(|want|
+                    failed.contains(want.key()))
+                {
+                    log::error!("Skipped PKGBUILD '{}' as its dependency \
+                        '{}' failed", builder.pkgbuild.key(), want.key());
+                    finished.push((id, Outcome::Skipped, Some(format!(
+                        "dependency '{}' failed", want.key()))));
+                    continue
+                }
+                if ! builder.wants.iter().all(|want|
+                    ! want.need_build || built.contains(want.key()))
+                {
+                    continue
+                }
                 let heavy_load = check_heavy_load(jobs, cores);
                 match builder.step(heavy_load, self.actual_identity, self.nonet,
                                     self.sign, &mut jobs)
                 {
                     Ok(_) => if let BuildState::Built = builder.build_state {
-                        finished = Some(id);
-                        break
+                        finished.push((id, Outcome::Success, None))
                     },
                     Err(e) => {
                         r = Err(e);
-                        finished = Some(id);
+                        finished.push((id, Outcome::Failure, None))
                     },
                 }
                 if heavy_load {
                     sleep(Duration::from_secs(1))
                 }
             }
-            if let Some(id) = finished {
+            // Descending so swap_remove won't move a builder yet to remove
+            for (id, outcome, reason) in finished.into_iter().rev() {
                 let builder = self.builders.swap_remove(id);
                 log::info!("Finished builder for PKGBUILD '{}'",
-                    &builder.pkgbuild.base);
+                    builder.pkgbuild.key());
+                let key = builder.pkgbuild.key();
+                if let Outcome::Success = outcome {
+                    built.insert(key);
+                } else {
+                    failed.insert(key);
+                    if r.is_ok() {
+                        r = Err(Error::BuildFailure)
+                    }
+                }
+                let log_path = if builder.log_path.as_os_str().is_empty() {
+                    None
+                } else {
+                    Some(builder.log_path.as_path())
+                };
+                if let Err(e) = self.state.record_build(builder.pkgbuild,
+                    builder.start.clone(), builder.tries, log_path, outcome,
+                    reason)
+                {
+                    log::error!("Failed to record build of '{}' into state \
+                        DB", builder.pkgbuild.key());
+                    r = Err(e)
+                }
             }
             if self.builders.is_empty() {
                 break
@@ -456,21 +522,11 @@ impl<'a> Builders<'a> {
 }
 
 pub(super) fn build_any_needed(
-    pkgbuilds: &PKGBUILDs,  actual_identity: &IdentityActual,
-    nonet: bool, sign: Option<&str>
+    graph: Vec<(&PKGBUILD, Vec<&PKGBUILD>)>, actual_identity: &IdentityActual,
+    nonet: bool, sign: Option<&Signer>, state: &mut StateDb
 ) -> Result<()>
 {
-    Builders::from_pkgbuilds(pkgbuilds, actual_identity, nonet, sign)?
-        .work()?;
-    Ok(())
+    let (mut builders, failed) = Builders::from_graph(
+        graph, actual_identity, nonet, sign, state)?;
+    builders.work(failed)
 }
-
-pub(super) fn build_any_needed_layer(
-    pkgbuild_layer: &Vec<&PKGBUILD>,  actual_identity: &IdentityActual,
-    nonet: bool, sign: Option<&str>
-) -> Result<()>
-{
-    Builders::from_pkgbuild_layer(pkgbuild_layer, actual_identity, nonet, sign)?
-        .work()?;
-    Ok(())
-}
\ No newline at end of file
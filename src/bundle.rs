@@ -0,0 +1,197 @@
+// Bundles of cached PKGBUILDs and sources, to prepare them on a builder with
+// network and build on another without. A bundle is a plain tar archive if
+// its path ends with `.tar`, or a directory otherwise, either way with the
+// same `sources/[kind]/[name]` layout as ours
+
+use std::{
+        collections::BTreeSet,
+        fs::File,
+        path::{
+            Component,
+            Path,
+            PathBuf,
+        },
+    };
+
+use crate::{
+        error::{
+            Error,
+            Result
+        },
+        filesystem::{
+            clone_tree,
+            remove_dir_all_try_best,
+        },
+    };
+
+fn is_archive(bundle: &Path) -> bool {
+    bundle.extension().map(|extension|extension == "tar").unwrap_or_default()
+}
+
+/// The `sources/[kind]/[name]` prefix of a path in the bundle, only if the
+/// kind is one we also maintain
+fn top_of(path: &Path) -> Option<PathBuf> {
+    let mut components = path.components();
+    let mut top = PathBuf::new();
+    for expected in [Some("sources"), None, None] {
+        match components.next() {
+            Some(Component::Normal(name)) => {
+                if let Some(expected) = expected {
+                    if name != expected {
+                        return None
+                    }
+                }
+                top.push(name)
+            },
+            _ => return None,
+        }
+    }
+    match top.parent() {
+        Some(kind) if kind.is_dir() => Some(top),
+        _ => None,
+    }
+}
+
+fn remove_existing(top: &Path) -> Result<()> {
+    match top.symlink_metadata() {
+        Ok(metadata) => if metadata.is_dir() {
+            remove_dir_all_try_best(top)
+        } else {
+            std::fs::remove_file(top).map_err(|e|{
+                log::error!("Failed to remove existing '{}': {}",
+                    top.display(), e);
+                Error::IoError(e)
+            })
+        },
+        Err(_) => Ok(()),
+    }
+}
+
+fn export_archive(paths: &[PathBuf], bundle: &Path) -> Result<()> {
+    let file = File::create(bundle).map_err(|e|{
+        log::error!("Failed to create bundle '{}': {}", bundle.display(), e);
+        Error::IoError(e)
+    })?;
+    let mut builder = tar::Builder::new(file);
+    builder.follow_symlinks(false);
+    for path in paths.iter() {
+        let r = if path.is_dir() {
+            builder.append_dir_all(path, path)
+        } else {
+            builder.append_path(path)
+        };
+        if let Err(e) = r {
+            log::error!("Failed to append '{}' to bundle '{}': {}",
+                path.display(), bundle.display(), e);
+            return Err(Error::IoError(e))
+        }
+    }
+    builder.into_inner().map_err(|e|{
+        log::error!("Failed to finish bundle '{}': {}", bundle.display(), e);
+        Error::IoError(e)
+    })?;
+    Ok(())
+}
+
+fn export_dir(paths: &[PathBuf], bundle: &Path) -> Result<()> {
+    for path in paths.iter() {
+        clone_tree(path, bundle.join(path))?
+    }
+    Ok(())
+}
+
+/// Export the caches at paths (relative, under `sources`) into the bundle
+pub(crate) fn export(paths: &[PathBuf], bundle: &Path) -> Result<()> {
+    log::info!("Exporting {} caches into bundle '{}'",
+        paths.len(), bundle.display());
+    if is_archive(bundle) {
+        export_archive(paths, bundle)
+    } else {
+        export_dir(paths, bundle)
+    }?;
+    log::info!("Exported {} caches into bundle '{}'",
+        paths.len(), bundle.display());
+    Ok(())
+}
+
+fn open_archive(bundle: &Path) -> Result<tar::Archive<File>> {
+    match File::open(bundle) {
+        Ok(file) => Ok(tar::Archive::new(file)),
+        Err(e) => {
+            log::error!("Failed to open bundle '{}': {}", bundle.display(), e);
+            Err(Error::IoError(e))
+        },
+    }
+}
+
+fn import_archive(bundle: &Path) -> Result<usize> {
+    let map_err = |e|{
+        log::error!("Failed to read bundle '{}': {}", bundle.display(), e);
+        Error::IoError(e)
+    };
+    // Existing caches are replaced as a whole, not merged, as e.g. stale
+    // loose refs in a git repo would shadow the packed ones from the bundle
+    let mut tops = BTreeSet::new();
+    let mut archive = open_archive(bundle)?;
+    for entry in archive.entries().map_err(map_err)? {
+        let entry = entry.map_err(map_err)?;
+        let path = entry.path().map_err(map_err)?;
+        match top_of(&path) {
+            Some(top) => { tops.insert(top); },
+            None => log::warn!("Ignored unexpected entry '{}' in bundle",
+                        path.display()),
+        }
+    }
+    for top in tops.iter() {
+        remove_existing(top)?
+    }
+    let mut archive = open_archive(bundle)?;
+    for entry in archive.entries().map_err(map_err)? {
+        let mut entry = entry.map_err(map_err)?;
+        if top_of(&entry.path().map_err(map_err)?).is_none() {
+            continue
+        }
+        entry.unpack_in(".").map_err(map_err)?;
+    }
+    Ok(tops.len())
+}
+
+fn import_dir(bundle: &Path) -> Result<usize> {
+    let mut count = 0;
+    let parent = bundle.join("sources");
+    for kind in std::fs::read_dir(&parent).map_err(|e|{
+        log::error!("Failed to read dir '{}': {}", parent.display(), e);
+        Error::IoError(e)
+    })? {
+        let kind = kind.map_err(Error::IoError)?;
+        for name in std::fs::read_dir(kind.path()).map_err(Error::IoError)? {
+            let name = name.map_err(Error::IoError)?;
+            let path = name.path();
+            let top = match path.strip_prefix(bundle).ok().and_then(top_of) {
+                Some(top) => top,
+                None => {
+                    log::warn!("Ignored unexpected entry '{}' in bundle",
+                        path.display());
+                    continue
+                },
+            };
+            remove_existing(&top)?;
+            clone_tree(&path, &top)?;
+            count += 1
+        }
+    }
+    Ok(count)
+}
+
+/// Import a bundle into `sources`, replacing existing caches with the same
+/// names
+pub(crate) fn import(bundle: &Path) -> Result<()> {
+    log::info!("Importing bundle '{}'", bundle.display());
+    let count = if is_archive(bundle) {
+        import_archive(bundle)
+    } else {
+        import_dir(bundle)
+    }?;
+    log::info!("Imported {} caches from bundle '{}'", count, bundle.display());
+    Ok(())
+}
@@ -3,7 +3,18 @@ mod pacman;
 mod file;
 
 pub(crate) use arg::Arg;
+pub(crate) use arg::Command;
 pub(crate) use pacman::Config as PacmanConfig;
+pub(crate) use file::Arch;
 pub(crate) use file::Config;
 pub(crate) use file::DepHashStrategy;
-pub(crate) use file::Pkgbuild;
\ No newline at end of file
+pub(crate) use file::Hook;
+pub(crate) use file::HookAction;
+pub(crate) use file::HookEvent;
+pub(crate) use file::Makepkg;
+pub(crate) use file::Pacman;
+pub(crate) use file::PacmanRepo;
+pub(crate) use file::Pkgbuild;
+pub(crate) use file::Publish;
+pub(crate) use file::Repo;
+pub(crate) use file::RepoCompression;
\ No newline at end of file
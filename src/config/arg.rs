@@ -26,10 +26,19 @@ pub(crate) struct Arg {
     #[arg(short='P', long, default_value_t = false)]
     pub(crate) holdpkg: bool,
 
-    /// Hold versions of git sources, do not update them
+    /// Hold versions of git and other VCS sources, do not update them
     #[arg(short='G', long, default_value_t = false)]
     pub(crate) holdgit: bool,
 
+    /// Cache netfile sources without integrity checksums by their URLs,
+    /// revalidating them every run, instead of downloading them every build
+    #[arg(short='U', long, default_value_t = false)]
+    pub(crate) urlcache: bool,
+
+    /// Hold netfile sources cached by their URLs, do not revalidate them
+    #[arg(short='H', long, default_value_t = false)]
+    pub(crate) holdurl: bool,
+
     /// Skip integrity check for netfile sources if they're found
     #[arg(short='I', long, default_value_t = false)]
     pub(crate) skipint: bool,
@@ -46,10 +55,20 @@ pub(crate) struct Arg {
     #[arg(short='N', long, default_value_t = false)]
     pub(crate) nonet: bool,
 
+    /// Retry PKGBUILDs that failed all tries in previous runs, even if their
+    /// inputs did not change
+    #[arg(short='F', long, default_value_t = false)]
+    pub(crate) retry_failed: bool,
+
     /// Drop to the specific uid:gid pair, instead of getting from SUDO_UID/GID
     #[arg(short='d', long)]
     pub(crate) drop: Option<String>,
 
+    /// Start as a normal user and build in our own user namespace, instead
+    /// of starting with sudo
+    #[arg(short='R', long, default_value_t = false)]
+    pub(crate) rootless: bool,
+
     /// Prefix of a 7Ji/git-mirrorer instance, e.g. git://gmr.lan,
     /// The mirror would be tried first before actual git remote
     #[arg(short='g', long)]
@@ -57,5 +76,47 @@ pub(crate) struct Arg {
 
     /// The GnuPG key ID used to sign packages
     #[arg(short, long)]
-    pub(crate) sign: Option<String>
+    pub(crate) sign: Option<String>,
+
+    /// Stage to run alone, all of sync, fetch and build if not set
+    #[command(subcommand)]
+    pub(crate) command: Option<Command>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub(crate) enum Command {
+    /// Sync PKGBUILDs repos only
+    Sync,
+    /// Fetch sources of PKGBUILDs only, implies --holdpkg
+    Fetch,
+    /// Build with PKGBUILDs and sources as they are, only fetching missing
+    /// ones, implies --holdpkg --holdgit --holdurl
+    Build,
+    /// Clean unused sources and outdated packages without building, implies
+    /// --holdpkg --holdgit --holdurl
+    Clean,
+    /// Show which PKGBUILDs would be (re)built and why, implies --holdpkg
+    /// --holdgit --holdurl --noclean
+    Status,
+    /// List pkgids and their packages, implies --holdpkg --holdgit --holdurl
+    /// --noclean
+    List,
+    /// Show per PKGBUILD the old and new pkgid, what changed, the deps to
+    /// install and its layer, implies --holdpkg --holdgit --holdurl --noclean
+    Plan {
+        /// Print the plan as JSON instead of human text
+        #[arg(long, default_value_t = false)]
+        json: bool,
+    },
+    /// Fetch the PKGBUILDs and sources needed by the config and export them
+    /// into a bundle, a plain tar archive if the path ends with .tar, or a
+    /// directory otherwise, without building anything
+    Export {
+        bundle: String,
+    },
+    /// Import a bundle exported by another builder into our sources, then
+    /// build offline with --holdpkg --holdgit
+    Import {
+        bundle: String,
+    },
 }
\ No newline at end of file
@@ -5,7 +5,7 @@ use serde::Deserialize;
 #[derive(Debug, PartialEq, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub(crate) enum DepHashStrategy {
-    Strict, // dep + makedep
+    Strict, // dep + makedep (+ checkdep if check)
     Loose,  // dep
     None,   // none
 }
@@ -27,10 +27,115 @@ pub(crate) enum Pkgbuild {
         deps: Option<Vec<String>>,
         makedeps: Option<Vec<String>>,
         home_binds: Option<Vec<String>>,
-        binds: Option<HashMap<String, String>>
+        binds: Option<HashMap<String, String>>,
+        env: Option<HashMap<String, String>>,
+        makepkg_args: Option<Vec<String>>,
+        network: Option<bool>,
+        check: Option<bool>,
+        arch: Option<Vec<String>>,
     },
 }
 
+/// A target arch, its base root is bootstrapped with its own configs
+#[derive(Debug, Default, PartialEq, Deserialize)]
+pub(crate) struct Arch {
+    pub(crate) pacman_conf: Option<String>,
+    pub(crate) makepkg_conf: Option<String>,
+    pub(crate) mirrorlist: Option<String>,
+}
+
+/// An extra repo appended to the pacman config of the builder
+#[derive(Debug, PartialEq, Deserialize)]
+pub(crate) struct PacmanRepo {
+    pub(crate) name: String,
+    #[serde(default)]
+    pub(crate) servers: Vec<String>,
+    pub(crate) include: Option<String>,
+    pub(crate) siglevel: Option<String>,
+}
+
+/// The pacman config of the builder, used for all roots and DB lookups
+/// instead of the host one
+#[derive(Debug, Default, PartialEq, Deserialize)]
+pub(crate) struct Pacman {
+    pub(crate) conf: Option<String>,
+    pub(crate) siglevel: Option<String>,
+    #[serde(default)]
+    pub(crate) repos: Vec<PacmanRepo>,
+}
+
+/// The makepkg config of the builder, copied into the base roots with the
+/// overrides appended
+#[derive(Debug, Default, PartialEq, Deserialize)]
+pub(crate) struct Makepkg {
+    pub(crate) conf: Option<String>,
+    pub(crate) cflags: Option<String>,
+    pub(crate) cxxflags: Option<String>,
+    pub(crate) ldflags: Option<String>,
+    pub(crate) makeflags: Option<String>,
+    pub(crate) pkgext: Option<String>,
+    pub(crate) buildenv: Option<Vec<String>>,
+    pub(crate) options: Option<Vec<String>>,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum RepoCompression {
+    None,
+    Gz,
+    Xz,
+    Zst,
+}
+
+impl Default for RepoCompression {
+    fn default() -> Self {
+        Self::Zst
+    }
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+pub(crate) struct Repo {
+    pub(crate) name: String,
+    #[serde(default)]
+    pub(crate) compression: RepoCompression,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+pub(crate) struct Publish {
+    #[serde(default = "default_publish_api")]
+    pub(crate) api: String,
+    pub(crate) owner: String,
+    pub(crate) repo: String,
+    pub(crate) tag: String,
+}
+
+fn default_publish_api() -> String {
+    String::from("https://api.github.com")
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum HookEvent {
+    BuildSuccess,
+    BuildFailure,
+    PackagesUpdated,
+    RunFinished,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum HookAction {
+    Command { command: Vec<String> },
+    Post { url: String },
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+pub(crate) struct Hook {
+    pub(crate) on: Vec<HookEvent>,
+    #[serde(flatten)]
+    pub(crate) action: HookAction,
+}
+
 #[derive(Debug, PartialEq, Deserialize)]
 pub(crate) struct Config {
     #[serde(default)]
@@ -38,6 +143,10 @@ pub(crate) struct Config {
     #[serde(default)]
     pub(crate) holdgit: bool,
     #[serde(default)]
+    pub(crate) urlcache: bool,
+    #[serde(default)]
+    pub(crate) holdurl: bool,
+    #[serde(default)]
     pub(crate) skipint: bool,
     #[serde(default)]
     pub(crate) nobuild: bool,
@@ -45,23 +154,48 @@ pub(crate) struct Config {
     pub(crate) noclean: bool,
     #[serde(default)]
     pub(crate) nonet: bool,
+    #[serde(default)]
+    pub(crate) rootless: bool,
+    #[serde(default = "default_check")]
+    pub(crate) check: bool,
     pub(crate) sign: Option<String>,
+    pub(crate) sign_key: Option<String>,
     pub(crate) gmr: Option<String>,
+    #[serde(default)]
+    pub(crate) mirrors: HashMap<String, Vec<String>>,
     pub(crate) proxy: Option<String>,
     pub(crate) proxy_after: Option<usize>,
     #[serde(default = "default_basepkgs")]
     pub(crate) basepkgs: Vec<String>,
     #[serde(default)]
     pub(crate) dephash_strategy: DepHashStrategy,
+    #[serde(default)]
+    pub(crate) arches: HashMap<String, Arch>,
+    #[serde(default)]
+    pub(crate) pacman: Pacman,
+    #[serde(default)]
+    pub(crate) makepkg: Makepkg,
     pub(crate) pkgbuilds: std::collections::HashMap<String, Pkgbuild>,
     #[serde(default = "default_home_binds")]
     pub(crate) home_binds: Vec<String>,
+    #[serde(default)]
+    pub(crate) env: HashMap<String, String>,
+    #[serde(default)]
+    pub(crate) makepkg_args: Vec<String>,
+    pub(crate) repo: Option<Repo>,
+    pub(crate) publish: Option<Publish>,
+    #[serde(default)]
+    pub(crate) hooks: Vec<Hook>,
 }
 
 fn default_basepkgs() -> Vec<String> {
     vec![String::from("base-devel")]
 }
 
+fn default_check() -> bool {
+    true
+}
+
 fn default_home_binds() -> Vec<String> {
     Vec::new()
 }
\ No newline at end of file
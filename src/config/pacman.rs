@@ -4,9 +4,12 @@ use std::fmt::{
         Write,
     };
 
-use crate::error::{
-        Error,
-        Result
+use crate::{
+        config::PacmanRepo,
+        error::{
+            Error,
+            Result
+        },
     };
 
 pub(crate) struct Section<'a> {
@@ -76,10 +79,65 @@ impl<'a> Config<'a> {
     pub(crate) fn with_cusrepo(&self, name: &str, path: &str) -> String {
         let mut content = self.options.to_string();
         content.push_str(
-            &format!("[{}]\nServer = file://{}\n", name, path));
+            &format!("[{}]\nSigLevel = Never\nServer = file://{}\n",
+                name, path));
         for repo in self.repos.iter() {
             content.push_str(repo.to_string().as_str())
         }
         content
     }
+
+    /// The config for the builder: `Architecture` overriden for a target
+    /// arch, `SigLevel` overriden if set, the `Include` of each repo replaced
+    /// by the mirrorlist if set, and the extra repos appended
+    pub(crate) fn for_builder(
+        &self, arch: Option<&str>, mirrorlist: Option<&str>,
+        siglevel: Option<&str>, extra_repos: &[PacmanRepo]
+    ) -> String
+    {
+        fn key_is(line: &str, key: &str) -> bool {
+            line.split('=').next().map(|k|k.trim() == key).unwrap_or_default()
+        }
+        let mut content = String::from("[options]\n");
+        for line in self.options.lines.iter() {
+            if (arch.is_some() && key_is(line, "Architecture")) ||
+                (siglevel.is_some() && key_is(line, "SigLevel"))
+            {
+                continue
+            }
+            content.push_str(line);
+            content.push('\n');
+        }
+        if let Some(arch) = arch {
+            content.push_str(&format!("Architecture = {}\n", arch));
+        }
+        if let Some(siglevel) = siglevel {
+            content.push_str(&format!("SigLevel = {}\n", siglevel));
+        }
+        for repo in self.repos.iter() {
+            content.push_str(&format!("[{}]\n", repo.name));
+            for line in repo.lines.iter() {
+                if mirrorlist.is_none() || ! key_is(line, "Include") {
+                    content.push_str(line);
+                    content.push('\n');
+                }
+            }
+            if let Some(mirrorlist) = mirrorlist {
+                content.push_str(&format!("Include = {}\n", mirrorlist));
+            }
+        }
+        for repo in extra_repos.iter() {
+            content.push_str(&format!("[{}]\n", repo.name));
+            if let Some(siglevel) = &repo.siglevel {
+                content.push_str(&format!("SigLevel = {}\n", siglevel));
+            }
+            for server in repo.servers.iter() {
+                content.push_str(&format!("Server = {}\n", server));
+            }
+            if let Some(include) = &repo.include {
+                content.push_str(&format!("Include = {}\n", include));
+            }
+        }
+        content
+    }
 }
@@ -3,5 +3,11 @@ mod depends;
 mod interdep;
 
 pub(crate) use db::DbHandle;
-pub(crate) use depends::Depends;
-pub(crate) use interdep::split_pkgbuilds;
\ No newline at end of file
+pub(crate) use depends::{
+    Depends,
+    strip_version,
+};
+pub(crate) use interdep::{
+    graph_pkgbuilds,
+    layer_pkgbuilds,
+};
\ No newline at end of file
@@ -19,7 +19,13 @@ pub(crate) struct DbHandle {
 }
 
 impl DbHandle {
-    pub(crate) fn new<P: AsRef<Path>>(root: P) -> Result<Self> {
+    /// The sync DBs of the root, those registered are the repos in the pacman
+    /// config
+    pub(crate) fn new<P, Q>(root: P, pacman_conf: Q) -> Result<Self>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
         let handle = match Alpm::new(
             root.as_ref().as_os_str().as_bytes(),
             root.as_ref().join("var/lib/pacman")
@@ -32,20 +38,17 @@ impl DbHandle {
                 return Err(Error::AlpmError(e))
             },
         };
-        let content = match std::fs::read_to_string(
-            "/etc/pacman.conf")
+        let content = match std::fs::read_to_string(&pacman_conf)
         {
             Ok(content) => content,
             Err(e) => {
-                log::error!("Failed to open pacman config: {}", e);
+                log::error!("Failed to open pacman config '{}': {}",
+                    pacman_conf.as_ref().display(), e);
                 return Err(Error::IoError(e))
             },
         };
         let config = crate::config::PacmanConfig::
             from_pacman_conf_content(&content)?;
-        let _new_config = config.with_cusrepo(
-            "arch_repo_builder_internal_do_not_use",
-            "/srv/repo_builder/pkgs");
         let sig_level = handle.default_siglevel();
         for repo in config.repos.iter() {
             if let Err(e) = handle.register_syncdb(repo.name, sig_level){
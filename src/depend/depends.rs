@@ -27,11 +27,29 @@ use crate::{
 pub(crate) struct Depends {
     pub(crate) deps: Vec<String>,
     pub(crate) makedeps: Vec<String>,
+    /// Only filled if check() would be run
+    pub(crate) checkdeps: Vec<String>,
     pub(crate) needs: Vec<String>,
+    /// Deps provided by our other PKGBUILDs, installed from a local repo
+    /// instead of the sync DBs
+    pub(crate) internal: Vec<String>,
     pub(crate) hash: u64,
 }
 
 
+/// The name part of a dep, without the version requirement
+pub(crate) fn strip_version(dep: &str) -> &str {
+    match dep.find(['<', '>', '=']) {
+        Some(end) => &dep[..end],
+        None => dep,
+    }
+}
+
+fn is_internal(internal: &[String], dep: &str) -> bool {
+    let dep = strip_version(dep);
+    internal.iter().any(|pkg|pkg == dep)
+}
+
 fn update_hash_from_pkg(hash: &mut xxh3::Xxh3, pkg: &Package) {
     if let Some(sig) = pkg.base64_sig() {
         hash.update(sig.as_bytes());
@@ -58,7 +76,10 @@ impl Depends {
     {
         let mut hash_box = Box::new(xxh3::Xxh3::new());
         let hash = hash_box.as_mut();
-        for dep in self.deps.iter().chain(self.makedeps.iter()) {
+        for dep in self.deps.iter().chain(self.makedeps.iter())
+            .chain(self.checkdeps.iter())
+            .filter(|dep|!is_internal(&self.internal, dep))
+        {
             let dep = match db_handle.find_satisfier(dep) {
                 Some(dep) => dep,
                 None => {
@@ -77,7 +98,9 @@ impl Depends {
     {
         let mut hash_box = Box::new(xxh3::Xxh3::new());
         let hash = hash_box.as_mut();
-        for dep in self.deps.iter() {
+        for dep in self.deps.iter().filter(
+            |dep|!is_internal(&self.internal, dep))
+        {
             let dep = match db_handle.find_satisfier(dep) {
                 Some(dep) => dep,
                 None => {
@@ -88,7 +111,9 @@ impl Depends {
             self.needs.push(dep.name().to_string());
             update_hash_from_pkg(hash, &dep);
         }
-        for dep in self.makedeps.iter() {
+        for dep in self.makedeps.iter().chain(self.checkdeps.iter())
+            .filter(|dep|!is_internal(&self.internal, dep))
+        {
             let dep = match db_handle.find_satisfier(dep) {
                 Some(dep) => dep,
                 None => {
@@ -103,7 +128,10 @@ impl Depends {
     }
 
     fn needed_and_no_hash(&mut self, db_handle: &DbHandle) -> Result<()> {
-        for dep in self.deps.iter().chain(self.makedeps.iter()) {
+        for dep in self.deps.iter().chain(self.makedeps.iter())
+            .chain(self.checkdeps.iter())
+            .filter(|dep|!is_internal(&self.internal, dep))
+        {
             let dep = match db_handle.find_satisfier(dep) {
                 Some(dep) => dep,
                 None => {
@@ -133,6 +161,26 @@ impl Depends {
         r
     }
 
+    /// Fold the pkgid of our PKGBUILD providing an internal dep into the hash,
+    /// so rebuilding the dep triggers a rebuild of us
+    pub(crate) fn fold_internal(
+        &mut self, dep: &str, pkgid: &str, hash_strategy: &DepHashStrategy
+    ) {
+        match hash_strategy {
+            DepHashStrategy::Strict => (),
+            DepHashStrategy::Loose => if ! self.deps.iter().any(
+                |d|strip_version(d) == dep)
+            {
+                return
+            },
+            DepHashStrategy::None => return,
+        }
+        let mut hash = xxh3::Xxh3::new();
+        hash.write_u64(self.hash);
+        hash.update(pkgid.as_bytes());
+        self.hash = hash.finish();
+    }
+
     pub(crate) fn update_needed(&mut self, db_handle: &DbHandle)
     {
         self.needs.retain(|pkg|!db_handle.is_installed(pkg));
@@ -140,8 +188,12 @@ impl Depends {
 
     /// Todo: cache package in our own storage, not tainting host, also without
     /// root permission.
-    pub(crate) fn cache_raw<S: AsRef<OsStr>>(deps: &Vec<String>, dbpath: S)
-        -> Result<()>
+    pub(crate) fn cache_raw<S, S2>(
+        deps: &Vec<String>, dbpath: S, config: S2, arch: Option<&str>
+    ) -> Result<()>
+    where
+        S: AsRef<OsStr>,
+        S2: AsRef<OsStr>,
     {
         if deps.len() == 0 {
             return Ok(())
@@ -149,6 +201,8 @@ impl Depends {
         log::info!("Caching the following dependencies on host: {:?}", deps);
 
         let mut command = Command::new("/usr/bin/pacman");
+        crate::root::set_pacman_cachedir(&mut command)?;
+        crate::root::set_pacman_arch(&mut command, arch);
         IdentityActual::set_root_command(
             crate::logfile::LogFile::new(
                 crate::logfile::LogType::Pacman, "cache-packages"
@@ -156,6 +210,8 @@ impl Depends {
                 command
                 .env("LANG", "C")
                 .arg("-S")
+                .arg("--config")
+                .arg(config.as_ref())
                 .arg("--dbpath")
                 .arg(dbpath.as_ref())
                 .arg("--noconfirm")
@@ -177,8 +233,10 @@ impl Depends {
     }
 
     pub(crate) fn wants(&self, pkg: &str) -> bool {
-        for dep in self.deps.iter().chain(self.makedeps.iter()) {
-            if dep == pkg {
+        for dep in self.deps.iter().chain(self.makedeps.iter())
+            .chain(self.checkdeps.iter())
+        {
+            if strip_version(dep) == pkg {
                 return true
             }
         }
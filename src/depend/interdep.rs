@@ -49,6 +49,8 @@ impl<'a> DepNodes<'a>  {
         Ok(Self{nodes})
     }
 
+    /// Split the nodes into layers, to verify there's no cycle and to show
+    /// the order
     fn split(mut self) -> Result<Vec<Vec<&'a PKGBUILD>>> {
         let mut layers: Vec<Vec<DepNode>> = vec![];
         while ! self.nodes.is_empty() {
@@ -75,27 +77,38 @@ impl<'a> DepNodes<'a>  {
             layers.push(layer);
         }
         log::info!("Split PKGBUILDs into {} layers:", layers.len());
-        let mut pkgbuild_layers = vec![];
         for (layer_id, layer) in
             layers.iter().enumerate()
         {
-            let mut pkgbuild_layer = vec![];
             let mut line = format!("Layer {}:", layer_id);
             for node in layer.iter() {
                 line.push_str(
-                    format!(" '{}'", &node.pkgbuild.base).as_str());
-                pkgbuild_layer.push(node.pkgbuild);
+                    format!(" '{}'", node.pkgbuild.key()).as_str());
             }
             line.push('\n');
             log::info!("{}", line);
-            pkgbuild_layers.push(pkgbuild_layer)
         }
-        Ok(pkgbuild_layers)
+        Ok(layers.into_iter().map(|layer|
+            layer.into_iter().map(|node|node.pkgbuild).collect()).collect())
     }
 }
 
-pub(crate) fn split_pkgbuilds<'a>(pkgbuilds: &'a PKGBUILDs)
-    -> Result<Vec<Vec<&'a PKGBUILD>>>
+/// Each PKGBUILD with the other PKGBUILDs it wants, the graph is verified to
+/// be acyclic
+pub(crate) fn graph_pkgbuilds<'a>(pkgbuilds: &'a PKGBUILDs)
+    -> Result<Vec<(&'a PKGBUILD, Vec<&'a PKGBUILD>)>>
+{
+    let nodes = DepNodes::from_pkgbuilds(pkgbuilds)?;
+    let graph = nodes.nodes.iter().map(|node|
+        (node.pkgbuild, node.wants.clone())).collect();
+    nodes.split()?;
+    Ok(graph)
+}
+
+/// PKGBUILDs split into layers, those in a layer only want those in former
+/// layers
+pub(crate) fn layer_pkgbuilds(pkgbuilds: &PKGBUILDs)
+    -> Result<Vec<Vec<&PKGBUILD>>>
 {
     DepNodes::from_pkgbuilds(pkgbuilds)?.split()
 }
\ No newline at end of file
@@ -16,13 +16,15 @@ pub(crate) enum Error {
     ImpossibleLogic,
     IntegrityError,
     InvalidConfig,
+    MalformedHeader (String),
     // MappingFailure,
     IoError (std::io::Error),
     NixErrno (nix::errno::Errno),
+    PgpError (pgp::errors::Error),
     ProcError (procfs::ProcError),
     ThreadFailure (Option<Box<dyn std::any::Any + Send + 'static>>),
     TimeError (time::Error),
-    UreqError (ureq::Error),
+    UreqError (Box<ureq::Error>),
     UrlParseError (url::ParseError),
 }
 
@@ -50,8 +52,11 @@ impl std::fmt::Display for Error {
             Error::ImpossibleLogic => write!(f, "Impossible Logic"),
             Error::IntegrityError => write!(f, "Integrity Error"),
             Error::InvalidConfig => write!(f, "Invalid Config"),
+            Error::MalformedHeader(header) =>
+                write!(f, "Malformed Header: {}", header),
             Error::IoError(e) => write!(f, "IO Error: {}", e),
             Error::NixErrno(e) => write!(f, "Nix Errno: {}", e),
+            Error::PgpError(e) => write!(f, "PGP Error: {}", e),
             Error::ProcError(e) => write!(f, "Proc Error: {}", e),
             Error::ThreadFailure(artifact) => write!(f, "Thread Failure, artifact: {:?}", artifact),
             Error::TimeError(e) => write!(f, "Time Error: {}", e),
@@ -85,6 +90,12 @@ impl From<nix::errno::Errno> for Error {
     }
 }
 
+impl From<pgp::errors::Error> for Error {
+    fn from(value: pgp::errors::Error) -> Self {
+        Self::PgpError(value)
+    }
+}
+
 impl From<procfs::ProcError> for Error {
     fn from(value: procfs::ProcError) -> Self {
         Self::ProcError(value)
@@ -93,7 +104,7 @@ impl From<procfs::ProcError> for Error {
 
 impl From<ureq::Error> for Error {
     fn from(value: ureq::Error) -> Self {
-        Self::UreqError(value)
+        Self::UreqError(Box::new(value))
     }
 }
 
@@ -135,8 +146,11 @@ impl Clone for Error {
             Self::ImpossibleLogic => Self::ImpossibleLogic,
             Self::IntegrityError => Self::IntegrityError,
             Self::InvalidConfig => Self::InvalidConfig,
+            Self::MalformedHeader(arg0) => Self::MalformedHeader(arg0.clone()),
             Self::IoError(arg0) => Self::IoError(std::io::Error::from(arg0.kind())),
             Self::NixErrno(arg0) => Self::NixErrno(*arg0),
+            Self::PgpError(arg0) =>
+                Self::Collapsed(format!("From PGP Error: {}", arg0)),
             Self::ProcError(arg0) => Self::Collapsed(format!("From Proc Error: {}", arg0)),
             Self::ThreadFailure(arg0) => Self::Collapsed(format!("From Thread Failure: {:?}", arg0)),
             Self::TimeError(arg0) => Self::Collapsed(format!("From Time Error: {}", arg0)),
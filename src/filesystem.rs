@@ -1,8 +1,12 @@
 use std::{
         fs::{
+            copy,
             create_dir,
+            create_dir_all,
             File,
+            hard_link,
             read_dir,
+            read_link,
             remove_dir,
             remove_dir_all,
             remove_file,
@@ -13,7 +17,10 @@ use std::{
             Write
         },
         os::unix::fs::{chown, symlink},
-        path::Path,
+        path::{
+            Path,
+            PathBuf,
+        },
     };
 
 use crate::error::{
@@ -216,14 +223,36 @@ where
 
 pub(crate) fn create_layout() -> Result<()> {
     create_dirs_allow_existing(["build", "logs", "pkgs", "sources"])?;
-    remove_dirs_allow_non_existing(["pkgs/updated", "pkgs/latest"])?;
-    create_dirs_under_allow_existing(["updated", "latest"], "pkgs")?;
     create_dirs_under_allow_existing([
         "file-ck", "file-md5", "file-sha1", "file-sha224", "file-sha256",
-        "file-sha384", "file-sha512", "file-b2", "git", "PKGBUILD"], 
+        "file-sha384", "file-sha512", "file-b2", "file-url", "git", "hg",
+        "svn", "bzr", "fossil", "PKGBUILD"],
         "sources")
 }
 
+/// `pkgs` for the host arch, or `pkgs/[arch]` for a target arch, containing
+/// pkgdirs, `updated`, `latest` and `repo`
+pub(crate) fn pkgs_dir(arch: Option<&str>) -> PathBuf {
+    match arch {
+        Some(arch) => PathBuf::from("pkgs").join(arch),
+        None => PathBuf::from("pkgs"),
+    }
+}
+
+/// Recreate empty `updated` and `latest` in the pkgs dirs of the arches, only
+/// for runs that would populate them again
+pub(crate) fn reset_pkgs_links(arches: &[Option<&str>]) -> Result<()> {
+    for arch in arches {
+        let dir = pkgs_dir(*arch);
+        let links = [dir.join("updated"), dir.join("latest")];
+        remove_dirs_allow_non_existing(&links)?;
+        create_dir_allow_existing(&dir)?;
+        create_dirs_allow_existing(&links)?
+
+    }
+    Ok(())
+}
+
 pub(crate) fn symlink_force<P, Q>(original: P, link: Q) -> Result<()>
 where
     P: AsRef<Path>,
@@ -262,4 +291,65 @@ where
         },
     }
 
-}
\ No newline at end of file
+}
+
+/// Clone a file or a dir recursively from source to target, files are hard
+/// linked if possible, otherwise copied; symlinks are recreated as-is
+pub(crate) fn clone_tree<P, Q>(source: P, target: Q) -> Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let source = source.as_ref();
+    let target = target.as_ref();
+    let metadata = source.symlink_metadata().map_err(|e|{
+        log::error!("Failed to get metadata of '{}': {}", source.display(), e);
+        Error::IoError(e)
+    })?;
+    if let Some(parent) = target.parent() {
+        create_dir_all(parent).map_err(|e|{
+            log::error!("Failed to create dir '{}': {}", parent.display(), e);
+            Error::IoError(e)
+        })?
+    }
+    if metadata.is_symlink() {
+        let original = read_link(source).map_err(|e|{
+            log::error!("Failed to read link '{}': {}", source.display(), e);
+            Error::IoError(e)
+        })?;
+        return symlink_force(original, target)
+    }
+    if metadata.is_dir() {
+        create_dir_allow_existing(target)?;
+        for entry in read_dir(source).map_err(|e|{
+            log::error!("Failed to read dir '{}': {}", source.display(), e);
+            Error::IoError(e)
+        })? {
+            let entry = entry.map_err(|e|{
+                log::error!("Failed to read entry in dir '{}': {}",
+                    source.display(), e);
+                Error::IoError(e)
+            })?;
+            clone_tree(entry.path(), target.join(entry.file_name()))?
+        }
+        return Ok(())
+    }
+    if target.symlink_metadata().is_ok() {
+        remove_file(target).map_err(|e|{
+            log::error!("Failed to remove existing '{}': {}",
+                target.display(), e);
+            Error::IoError(e)
+        })?
+    }
+    if hard_link(source, target).is_ok() {
+        return Ok(())
+    }
+    match copy(source, target) {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            log::error!("Failed to copy '{}' to '{}': {}",
+                source.display(), target.display(), e);
+            Err(Error::IoError(e))
+        },
+    }
+}
@@ -0,0 +1,189 @@
+// Hooks fired at the end of a build run, each either a command receiving the
+// event as JSON on stdin, or a HTTP POST of the event as JSON to a URL
+
+use std::{
+        io::Write,
+        process::{
+            Command,
+            Stdio,
+        },
+    };
+
+use serde::Serialize;
+
+use crate::{
+        config::{
+            Hook,
+            HookAction,
+            HookEvent,
+        },
+        error::{
+            Error,
+            Result
+        },
+        filesystem::pkgs_dir,
+        identity::IdentityActual,
+        report::Report,
+        state::{
+            BuildRecord,
+            Outcome,
+            StateDb,
+        },
+    };
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub(crate) enum Event<'a> {
+    BuildSuccess (&'a BuildRecord),
+    BuildFailure (&'a BuildRecord),
+    /// Package files newly linked in `updated` in this run, prefixed with
+    /// `[arch]/` for target arches
+    PackagesUpdated { packages: Vec<String> },
+    RunFinished (&'a Report),
+}
+
+impl<'a> Event<'a> {
+    fn kind(&self) -> HookEvent {
+        match self {
+            Event::BuildSuccess(_) => HookEvent::BuildSuccess,
+            Event::BuildFailure(_) => HookEvent::BuildFailure,
+            Event::PackagesUpdated { .. } => HookEvent::PackagesUpdated,
+            Event::RunFinished(_) => HookEvent::RunFinished,
+        }
+    }
+}
+
+fn run_command(
+    command: &[String], actual_identity: &IdentityActual, body: &str
+) -> Result<()>
+{
+    let (program, args) = match command.split_first() {
+        Some(split) => split,
+        None => {
+            log::error!("Hook command is empty");
+            return Err(Error::InvalidConfig)
+        },
+    };
+    let mut child = actual_identity.set_root_drop_command(
+        Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped()))
+        .spawn()
+        .map_err(|e|{
+            log::error!("Failed to spawn hook command '{}': {}", program, e);
+            Error::IoError(e)
+        })?;
+    if let Some(mut stdin) = child.stdin.take() {
+        if let Err(e) = stdin.write_all(body.as_bytes()) {
+            log::error!("Failed to write event to hook command '{}': {}",
+                program, e);
+        }
+    }
+    let status = child.wait().map_err(|e|{
+        log::error!("Failed to wait for hook command '{}': {}", program, e);
+        Error::IoError(e)
+    })?;
+    if status.success() {
+        Ok(())
+    } else {
+        log::error!("Hook command '{}' failed: {}", program, status);
+        Err(Error::BadChild { pid: None, code: status.code() })
+    }
+}
+
+fn post(url: &str, body: &str) -> Result<()> {
+    match ureq::post(url)
+        .set("Content-Type", "application/json")
+        .send_string(body)
+    {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            log::error!("Failed to POST event to '{}': {}", url, e);
+            Err(Error::UreqError(e.into()))
+        },
+    }
+}
+
+/// Fire all hooks wanting the event, all of them are tried even if some
+/// failed
+pub(crate) fn fire(
+    hooks: &[Hook], actual_identity: &IdentityActual, event: &Event
+) -> Result<()>
+{
+    let kind = event.kind();
+    let mut r = Ok(());
+    let mut body = None;
+    for hook in hooks.iter().filter(|hook|hook.on.contains(&kind)) {
+        let body = match &body {
+            Some(body) => body,
+            None => body.insert(serde_json::to_string(event).map_err(|e|{
+                log::error!("Failed to serialize event: {}", e);
+                Error::ImpossibleLogic
+            })?),
+        };
+        log::info!("Firing hook on {:?}", kind);
+        let r_hook = match &hook.action {
+            HookAction::Command { command } =>
+                run_command(command, actual_identity, body),
+            HookAction::Post { url } => post(url, body),
+        };
+        if let Err(e) = r_hook {
+            r = Err(e)
+        }
+    }
+    r
+}
+
+fn updated_packages(arches: &[Option<&str>]) -> Vec<String> {
+    let mut packages = vec![];
+    for arch in arches {
+        let dir = pkgs_dir(*arch).join("updated");
+        match dir.read_dir() {
+            Ok(reader) => packages.extend(reader.filter_map(|entry|entry.ok()
+                .map(|entry|{
+                    let name = entry.file_name().to_string_lossy()
+                        .into_owned();
+                    match arch {
+                        Some(arch) => format!("{}/{}", arch, name),
+                        None => name,
+                    }
+                }))),
+            Err(e) => log::error!("Failed to read dir '{}': {}",
+                dir.display(), e),
+        }
+    }
+    packages.sort_unstable();
+    packages
+}
+
+/// Fire hooks for all events of the run: builds of each PKGBUILD, updated
+/// packages if any, then the run itself
+pub(crate) fn fire_run_end(
+    hooks: &[Hook], actual_identity: &IdentityActual, state: &StateDb,
+    report: &Report, arches: &[Option<&str>]
+) -> Result<()>
+{
+    if hooks.is_empty() {
+        return Ok(())
+    }
+    let mut r = Ok(());
+    let mut events = vec![];
+    for build in state.run_builds() {
+        match build.result {
+            Outcome::Success => events.push(Event::BuildSuccess(build)),
+            Outcome::Failure => events.push(Event::BuildFailure(build)),
+            Outcome::Skipped => (),
+        }
+    }
+    let packages = updated_packages(arches);
+    if ! packages.is_empty() {
+        events.push(Event::PackagesUpdated { packages })
+    }
+    events.push(Event::RunFinished(report));
+    for event in events.iter() {
+        if let Err(e) = fire(hooks, actual_identity, event) {
+            r = Err(e)
+        }
+    }
+    r
+}
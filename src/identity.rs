@@ -1,9 +1,15 @@
-// Todo: drop all of this, use user namespaces, so we can operate fully without
-// jumping back and forth between root and normal user
+// The builder jumps back and forth between root and the actual user, the root
+// could either be the real root (started with sudo), or the root inside a user
+// namespace (rootless mode)
 
 use std::{
         ffi::OsString,
+        io::{
+            Read,
+            Write,
+        },
         os::unix::{
+            net::UnixStream,
             process::CommandExt,
             fs::chroot
         },
@@ -15,13 +21,33 @@ use std::{
             PathBuf,
             Path
         }, fmt::Display,
+        sync::atomic::{
+            AtomicBool,
+            Ordering,
+        },
     };
 
-use nix::unistd::{
-        getgid,
-        getuid,
-        Gid,
-        Uid,
+use nix::{
+        mount::{
+            mount,
+            MsFlags,
+        },
+        sched::{
+            unshare,
+            CloneFlags,
+        },
+        sys::wait::{
+            waitpid,
+            WaitStatus,
+        },
+        unistd::{
+            getgid,
+            getuid,
+            setgroups,
+            Gid,
+            Pid,
+            Uid,
+        },
     };
 
 use pwd::Passwd;
@@ -37,6 +63,194 @@ use crate::{
         },
     };
 
+static ROOTLESS: AtomicBool = AtomicBool::new(false);
+
+/// Whether we're running as root in our own user namespace, instead of the
+/// real root
+pub(crate) fn is_rootless() -> bool {
+    ROOTLESS.load(Ordering::Relaxed)
+}
+
+/// A subordinate ID range from /etc/subuid or /etc/subgid
+struct SubIds {
+    start: u32,
+    count: u32,
+}
+
+impl SubIds {
+    fn from_file(path: &str, name: &str, id: u32) -> Result<Self> {
+        let content = std::fs::read_to_string(path).map_err(|e|{
+            log::error!("Failed to read '{}': {}", path, e);
+            Error::IoError(e)
+        })?;
+        let id = id.to_string();
+        for line in content.lines() {
+            let mut components = line.trim().splitn(3, ':');
+            let (owner, start, count) = match (
+                components.next(), components.next(), components.next())
+            {
+                (Some(owner), Some(start), Some(count)) =>
+                    (owner, start, count),
+                _ => continue,
+            };
+            if owner != name && owner != id {
+                continue
+            }
+            if let (Ok(start), Ok(count)) = (start.parse(), count.parse()) {
+                return Ok(Self { start, count })
+            }
+        }
+        log::error!("No subordinate IDs for '{}' in '{}', rootless mode needs \
+            them to map the root and system users", name, path);
+        Err(Error::BrokenEnvironment)
+    }
+
+    /// The mapping arguments for newuidmap/newgidmap: the actual ID is mapped
+    /// to itself so files in the work dir keep their owner, while the other
+    /// IDs, including root, come from the subordinate range
+    fn map_args(&self, id: u32) -> Vec<String> {
+        let mut args = vec![];
+        let first = id.min(self.count);
+        if first > 0 {
+            args.extend([0, self.start, first].map(|arg|arg.to_string()))
+        }
+        args.extend([id, id, 1].map(|arg|arg.to_string()));
+        if self.count > id {
+            args.extend([id + 1, self.start + id, self.count - id].map(
+                |arg|arg.to_string()))
+        }
+        args
+    }
+}
+
+fn map_ids(pid: Pid, uid: Uid, gid: Gid, name: &str) -> Result<()> {
+    let subuids = SubIds::from_file("/etc/subuid", name, uid.as_raw())?;
+    let subgids = SubIds::from_file("/etc/subgid", name, gid.as_raw())?;
+    output_and_check(Command::new("/usr/bin/newuidmap")
+        .arg(pid.to_string())
+        .args(subuids.map_args(uid.as_raw())),
+        "to map uids")?;
+    output_and_check(Command::new("/usr/bin/newgidmap")
+        .arg(pid.to_string())
+        .args(subgids.map_args(gid.as_raw())),
+        "to map gids")
+}
+
+/// Wait for the child and exit with the same code, used by the processes
+/// left outside of the namespaces
+fn wait_and_exit(pid: Pid) -> ! {
+    match waitpid(pid, None) {
+        Ok(WaitStatus::Exited(_, code)) => exit(code),
+        Ok(WaitStatus::Signaled(_, signal, _)) => exit(128 + signal as i32),
+        Ok(status) => {
+            log::error!("Child {} did not exit cleanly: {:?}", pid, status);
+            exit(-1)
+        },
+        Err(e) => {
+            log::error!("Failed to wait for child {}: {}", pid, e);
+            exit(-1)
+        },
+    }
+}
+
+/// Enter new user, mount and pid namespaces, only the process inside them
+/// returns. This must be called before any thread is spawned.
+fn enter_namespaces(current: &IdentityCurrent) -> Result<()> {
+    let (mut parent_end, mut child_end) = UnixStream::pair().map_err(|e|{
+        log::error!("Failed to create socket pair: {}", e);
+        Error::IoError(e)
+    })?;
+    let mut buffer = [0; 1];
+    match unsafe { nix::unistd::fork() } {
+        Ok(nix::unistd::ForkResult::Parent { child }) => {
+            drop(child_end);
+            if parent_end.read_exact(&mut buffer).is_ok() &&
+                map_ids(child, current.uid, current.gid, &current.name).is_ok()
+            {
+                let _ = parent_end.write_all(&buffer);
+            } else {
+                log::error!("Failed to map IDs for namespaced child {}", child);
+            }
+            drop(parent_end);
+            wait_and_exit(child)
+        },
+        Ok(nix::unistd::ForkResult::Child) => (),
+        Err(e) => {
+            log::error!("Failed to fork: {}", e);
+            return Err(e.into())
+        },
+    }
+    drop(parent_end);
+    if let Err(e) = unshare(CloneFlags::CLONE_NEWUSER |
+        CloneFlags::CLONE_NEWNS | CloneFlags::CLONE_NEWPID)
+    {
+        log::error!("Child: Failed to unshare namespaces: {}, is unprivileged \
+            user namespace enabled?", e);
+        exit(-1)
+    }
+    if child_end.write_all(&buffer).is_err() ||
+        child_end.read_exact(&mut buffer).is_err()
+    {
+        log::error!("Child: Parent failed to map IDs for us");
+        exit(-1)
+    }
+    drop(child_end);
+    if let Err(e) = setgroups(&[]) {
+        log::error!("Child: Failed to clear supplementary groups: {}", e);
+        exit(-1)
+    }
+    if let Err(e) = IdentityCurrent::set_raw(
+        Uid::from_raw(0), Gid::from_raw(0))
+    {
+        log::error!("Child: Failed to become root in user namespace: {}", e);
+        exit(-1)
+    }
+    if let Err(e) = mount(None::<&str>, "/", None::<&str>,
+        MsFlags::MS_REC | MsFlags::MS_PRIVATE, None::<&str>)
+    {
+        log::error!("Child: Failed to make mounts private: {}", e);
+        exit(-1)
+    }
+    // The first child in the new pid namespace becomes its init, so proc
+    // could be mounted without the host root
+    match unsafe { nix::unistd::fork() } {
+        Ok(nix::unistd::ForkResult::Parent { child }) => wait_and_exit(child),
+        Ok(nix::unistd::ForkResult::Child) => Ok(()),
+        Err(e) => {
+            log::error!("Child: Failed to fork into pid namespace: {}", e);
+            exit(-1)
+        },
+    }
+}
+
+/// Bring up the loopback in a fresh network namespace, this is called between
+/// fork and exec so only raw syscalls are used
+fn loopback_up() -> std::io::Result<()> {
+    unsafe {
+        let fd = libc::socket(libc::AF_INET,
+            libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0);
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error())
+        }
+        let mut ifreq: libc::ifreq = std::mem::zeroed();
+        for (id, c) in b"lo".iter().enumerate() {
+            ifreq.ifr_name[id] = *c as libc::c_char
+        }
+        let mut r = libc::ioctl(fd, libc::SIOCGIFFLAGS, &mut ifreq);
+        if r >= 0 {
+            ifreq.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short;
+            r = libc::ioctl(fd, libc::SIOCSIFFLAGS, &ifreq);
+        }
+        let e = std::io::Error::last_os_error();
+        libc::close(fd);
+        if r < 0 {
+            Err(e)
+        } else {
+            Ok(())
+        }
+    }
+}
+
 #[derive(Clone)]
 struct Environment {
     shell: OsString,
@@ -160,6 +374,18 @@ pub(crate) trait Identity {
         command
     }
 
+    /// Move into a new network namespace with only loopback, as this uses
+    /// unshare(), you need to return to root first
+    fn set_nonet_command(command: &mut Command) -> &mut Command {
+        unsafe {
+            command.pre_exec(|| {
+                unshare(CloneFlags::CLONE_NEWNET)?;
+                loopback_up()
+            });
+        }
+        command
+    }
+
     fn run_chroot_command<P: AsRef<Path>>(
         command: &mut Command, root: P
     ) -> Result<()>
@@ -433,6 +659,23 @@ impl IdentityActual {
         Ok(identity)
     }
 
+    /// Start as a normal user, enter our own user namespace where we are
+    /// root, then drop to the user itself like the sudo case
+    pub(crate) fn new_rootless_and_drop() -> Result<Self> {
+        let current = IdentityCurrent::new()?;
+        if current.is_root() {
+            log::error!("Rootless mode should be started as a normal user, \
+                not root");
+            return Err(Error::BrokenEnvironment)
+        }
+        enter_namespaces(&current)?;
+        ROOTLESS.store(true, Ordering::Relaxed);
+        log::info!("Entered user namespace as root, mapped from {}", current);
+        let identity = Self::new(current.uid, current.gid)?;
+        identity.drop()?;
+        Ok(identity)
+    }
+
     /// Drop to the identity, as this uses setuid/setgid, you need to return
     /// to root first
     pub(crate) fn set_drop_command<'a>(&self, command: &'a mut Command)
@@ -457,14 +700,18 @@ impl IdentityActual {
         self.set_drop_command(command)
     }
 
-    /// Return to root, chroot to a folder, then drop
+    /// Return to root, chroot to a folder, optionally move into a network
+    /// namespace without network, then drop
     pub(crate) fn set_root_chroot_drop_command<'a, 'b, P: AsRef<Path>>(
-        &'a self, command: &'b mut Command, root: P
+        &'a self, command: &'b mut Command, root: P, nonet: bool
     ) -> &'b mut Command
     {
         self.env.set_command(command);
         Self::set_root_command(command);
         Self::set_chroot_command(command, root);
+        if nonet {
+            Self::set_nonet_command(command);
+        }
         self.set_drop_command(command)
         // command
     }
@@ -1,7 +1,9 @@
 // TODO: Split this into multiple modules
-// Progress: already splitted part into pkgbuild/parse.rs, add mod parse to enable part of that
 use crate::{
-        config::Pkgbuild as PkgbuildConfig,
+        config::{
+            Arch as ArchConfig,
+            Pkgbuild as PkgbuildConfig,
+        },
         error::{
             Error,
             Result
@@ -10,28 +12,38 @@ use crate::{
         source::{
             self,
             git::{self, Gmr},
-            MapByDomain, Proxy,
+            FetchSettings, MapByDomain, Proxy,
         },
         root::{
+            Bind,
             CommonRoot,
+            BaseConfigs,
             BaseRoot,
-            OverlayRoot, BootstrappingOverlayRoot,
+            OverlayExtras, OverlayRoot, BootstrappingOverlayRoot,
         },
         threading::{
             self,
             wait_if_too_busy,
-        }, filesystem::remove_dir_all_try_best, sign::sign_pkgs, depend::{Depends, DbHandle}, config::DepHashStrategy
+        },
+        filesystem::{pkgs_dir, remove_dir_all_try_best},
+        sign::Signer,
+        depend::{Depends, DbHandle, strip_version},
+        config::DepHashStrategy,
+        state::FailureMarker,
     };
 use git2::Oid;
 use std::{
-        collections::HashMap,
+        collections::{
+            BTreeMap,
+            HashMap,
+        },
         ffi::OsString,
         fs::{
             create_dir_all,
             remove_dir_all,
             rename
         },
-        io::{Write, Read},
+        io::Write,
         os::unix::{
             fs::symlink,
             process::CommandExt
@@ -45,13 +57,16 @@ use std::{
             Command,
             Stdio
         },
-        thread,
+        thread::{
+            self,
+            JoinHandle,
+        },
         iter::zip,
     };
 use xxhash_rust::xxh3::xxh3_64;
 // use super::{depend::Depends, DepHashStrategy};
 // use super::depend::DbHandle;
-// mod parse;
+mod parse;
 
 
 #[derive(Clone)]
@@ -60,21 +75,63 @@ enum Pkgver {
     Func { pkgver: String },
 }
 
+/// The options all PKGBUILDs get unless they set their own, and the arches
+/// they're built for
+#[derive(Clone, Copy)]
+pub(crate) struct PkgbuildDefaults<'a> {
+    pub(crate) home_binds: &'a [String],
+    pub(crate) env: &'a HashMap<String, String>,
+    pub(crate) makepkg_args: &'a [String],
+    pub(crate) check: bool,
+    pub(crate) arches: &'a HashMap<String, ArchConfig>,
+}
+
+/// The options a PKGBUILD sets for itself in the config
+#[derive(Default)]
+struct PkgbuildOptions<'a> {
+    branch: Option<&'a str>,
+    subtree: Option<&'a str>,
+    deps: Option<&'a Vec<String>>,
+    makedeps: Option<&'a Vec<String>>,
+    home_binds: Option<&'a Vec<String>>,
+    binds: &'a [Bind],
+    env: Option<&'a HashMap<String, String>>,
+    makepkg_args: Option<&'a Vec<String>>,
+    network: Option<bool>,
+    check: Option<bool>,
+}
+
 #[derive(Clone)]
 pub(crate) struct PKGBUILD {
+    /// The target arch, not set for the host arch
+    arch: Option<String>,
+    /// Other target arches the packages are linked for, if arch-independent
+    also_arches: Vec<String>,
+    /// The `arch=()` array of the PKGBUILD
+    arches_declared: Vec<String>,
     pub(crate) base: String,
+    binds: Vec<Bind>,
     branch: String,
     build: PathBuf,
+    check: bool,
     commit: git2::Oid,
     depends: Depends,
+    /// Passed to makepkg, the global ones overriden by the PKGBUILD's own
+    env: BTreeMap<String, String>,
     pub(crate) extracted: bool,
     git: PathBuf,
     home_binds: Vec<String>,
+    /// Unique among all PKGBUILDs, `[base]` or `[base]-[arch]`
+    key: String,
+    /// Appended to the makepkg command line, the global ones first
+    makepkg_args: Vec<String>,
     names: Vec<String>,
     pub(crate) need_build: bool,
+    network: bool,
     pub(crate) pkgid: String,
     pkgdir: PathBuf,
     pkgver: Pkgver,
+    pkgver_func: bool,
     provides: Vec<String>,
     sources: Vec<source::Source>,
     subtree: Option<PathBuf>,
@@ -115,7 +172,20 @@ impl PKGBUILD {
     // pub(crate) fn provides(&self, pkg: &String) -> bool {
     //     self.names.contains(pkg) || self.provides.contains(pkg)
     // }
+    /// Whether the packages are for the arch, arch-independent ones are
+    /// for all arches they're linked for
+    fn serves(&self, arch: Option<&str>) -> bool {
+        self.arch.as_deref() == arch || match arch {
+            Some(arch) => self.also_arches.iter().any(|also|also == arch),
+            None => false,
+        }
+    }
+
+    /// Packages of another arch are never wanted
     pub(crate) fn wants<'a> (&'a self, other: &'a Self) -> Option<&'a str> {
+        if ! other.serves(self.arch()) {
+            return None
+        }
         for pkg in other.names.iter().chain(other.provides.iter()) {
             if self.depends.wants(pkg) {
                 return Some(pkg)
@@ -124,10 +194,9 @@ impl PKGBUILD {
         None
     }
     fn new(
-        name: &str, url: &str, build_parent: &Path, git_parent: &Path,
-        branch: Option<&str>, subtree: Option<&str>, deps: Option<&Vec<String>>,
-        makedeps: Option<&Vec<String>>, home_binds: Option<&Vec<String>>,
-        home_binds_global: &Vec<String>
+        name: &str, arch: Option<&str>, url: &str, build_parent: &Path,
+        git_parent: &Path, options: &PkgbuildOptions,
+        defaults: &PkgbuildDefaults
     ) -> Self
     {
         let url = if url == "AUR" {
@@ -147,21 +216,30 @@ impl PKGBUILD {
         } else {
             url.to_string()
         };
+        let key = match arch {
+            Some(arch) => format!("{}-{}", name, arch),
+            None => name.to_string(),
+        };
         Self {
+            arch: arch.map(String::from),
+            also_arches: vec![],
+            arches_declared: vec![],
             base: name.to_string(),
-            branch: match branch {
+            binds: options.binds.to_vec(),
+            branch: match options.branch {
                 Some(branch) => branch.to_owned(),
                 None => String::from("master"),
             },
-            build: build_parent.join(name),
+            build: build_parent.join(&key),
+            check: options.check.unwrap_or(defaults.check),
             commit: Oid::zero(),
             depends: Depends {
-                deps: match deps {
+                deps: match options.deps {
                     Some(deps) => deps.clone(),
                     None => vec![],
                 },
                 makedeps: {
-                    let mut deps = match makedeps {
+                    let mut deps = match options.makedeps {
                         Some(deps) => deps.clone(),
                         None => vec![]
                     };
@@ -170,30 +248,52 @@ impl PKGBUILD {
                     }
                     deps
                 },
+                checkdeps: vec![],
                 needs: vec![],
+                internal: vec![],
                 hash: 0,
             },
+            env: {
+                let mut env_merged: BTreeMap<String, String> = defaults.env
+                    .iter().map(|(k, v)|(k.clone(), v.clone())).collect();
+                if let Some(env) = options.env {
+                    for (k, v) in env.iter() {
+                        env_merged.insert(k.clone(), v.clone());
+                    }
+                }
+                env_merged
+            },
             extracted: false,
             git: git_parent.join(
                 format!("{:016x}",xxh3_64(url.as_bytes()))),
             home_binds: {
-                let mut home_binds = match home_binds {
+                let mut home_binds = match options.home_binds {
                     Some(home_binds) => home_binds.clone(),
                     None => vec![],
                 };
-                for home_bind in home_binds_global.iter() {
+                for home_bind in defaults.home_binds.iter() {
                     home_binds.push(home_bind.clone())
                 }
                 home_binds
             },
+            key,
+            makepkg_args: {
+                let mut args = defaults.makepkg_args.to_vec();
+                if let Some(args_own) = options.makepkg_args {
+                    args.extend(args_own.iter().cloned())
+                }
+                args
+            },
             names: vec![],
             need_build: false,
+            network: options.network.unwrap_or_default(),
             pkgid: String::new(),
-            pkgdir: PathBuf::from("pkgs"),
+            pkgdir: pkgs_dir(arch),
             pkgver: Pkgver::Plain,
+            pkgver_func: false,
             provides: vec![],
             sources: vec![],
-            subtree: match subtree {
+            subtree: match options.subtree {
                 Some(subtree) => {
                     if subtree.ends_with('/') || subtree.starts_with('/') {
                         let mut subtree = subtree.to_owned();
@@ -274,48 +374,65 @@ impl PKGBUILD {
         Ok(())
     }
 
-    fn dep_reader_file<P: AsRef<Path>> (
-        actual_identity: &IdentityActual, pkgbuild_file: P
-    ) -> std::io::Result<Child>
-    {
-        actual_identity.set_root_drop_command(
-            Command::new("/bin/bash")
-                .arg("-ec")
-                .arg(". \"$1\"; \
-                    for dep in \"${depends[@]}\"; do \
-                        echo \"d:${dep}\"; \
-                    done; \
-                    for dep in  \"${makedepends[@]}\"; do \
-                        echo \"m:${dep}\"; \
-                    done")
-                .arg("Depends reader")
-                .arg(pkgbuild_file.as_ref())
-                .stdout(Stdio::piped()))
-            .spawn()
-    }
-
-    fn dep_reader<P: AsRef<Path>>(&self, actual_identity: &IdentityActual, dir: P)
-        -> std::io::Result<Child>
-    {
-        let pkgbuild_file = dir.as_ref().join(&self.base);
-        Self::dep_reader_file(actual_identity, &pkgbuild_file)
-    }
-
-    fn get_sources_file<P: AsRef<Path>> (pkgbuild_file: P)
-        -> Result<Vec<source::Source>>
+    /// Fill names, provides, deps, sources and pkgver type from the parser,
+    /// deps and provides of split packages are merged
+    fn fill_from_parsed(&mut self, parsed: parse::PkgbuildOwned, urlcache: bool)
+        -> Result<()>
     {
-        source::get_sources(pkgbuild_file)
-    }
-
-    fn get_sources<P: AsRef<Path>> (&mut self, dir: P) -> Result<()> {
-        let pkgbuild_file = dir.as_ref().join(&self.base);
-        match Self::get_sources_file(&pkgbuild_file) {
-            Ok(sources) => {
-                self.sources = sources;
-                Ok(())
-            },
-            Err(_) => Err(Error::BrokenPKGBUILDs(vec![self.base.clone()])),
+        fn sum(sums: &[String], id: usize) -> &str {
+            sums.get(id).map(|sum|sum.as_str()).unwrap_or("")
+        }
+        let mut sources = vec![];
+        for (id, source) in parsed.sources.iter().enumerate() {
+            if source::push_parsed_source(&mut sources,
+                &source.name, &source.protocol, &source.url, [
+                    sum(&parsed.cksums, id), sum(&parsed.md5sums, id),
+                    sum(&parsed.sha1sums, id), sum(&parsed.sha224sums, id),
+                    sum(&parsed.sha256sums, id), sum(&parsed.sha384sums, id),
+                    sum(&parsed.sha512sums, id), sum(&parsed.b2sums, id)],
+                urlcache
+            ).is_err() {
+                log::error!("Failed to parse source '{}' of PKGBUILD '{}'",
+                    &source.name, &self.base);
+                return Err(Error::BrokenPKGBUILDs(vec![self.base.clone()]))
+            }
+        }
+        self.sources = sources;
+        if parsed.base != self.base {
+            log::info!("PKGBUILD '{}' has pkgbase '{}'", &self.base,
+                &parsed.base);
+        }
+        self.names = parsed.pkgs.iter().map(|pkg|pkg.name.clone()).collect();
+        self.arches_declared = parsed.arches;
+        self.provides = parsed.provides;
+        self.depends.deps.extend(parsed.deps);
+        for pkg in parsed.pkgs {
+            self.provides.extend(pkg.provides);
+            self.depends.deps.extend(pkg.deps);
+        }
+        for provide in self.provides.iter_mut() {
+            *provide = strip_version(provide).to_string()
         }
+        self.provides.sort_unstable();
+        self.provides.dedup();
+        self.depends.makedeps.extend(parsed.makedeps);
+        if self.check {
+            self.depends.checkdeps.extend(parsed.checkdeps);
+        }
+        // Split packages could depend on each other
+        for deps in [&mut self.depends.deps, &mut self.depends.makedeps,
+            &mut self.depends.checkdeps]
+        {
+            deps.retain(|dep|{
+                let dep = strip_version(dep);
+                ! self.names.iter().chain(self.provides.iter())
+                    .any(|pkg|pkg == dep)
+            });
+            deps.sort_unstable();
+            deps.dedup();
+        }
+        self.pkgver_func = parsed.pkgver_func;
+        Ok(())
     }
 
     pub(crate) fn extractor_source(
@@ -338,10 +455,10 @@ impl PKGBUILD {
             Err(Error::IoError(e))
         })?;
         let mut arg0 = OsString::from("[EXTRACTOR/");
-        arg0.push(&self.base);
+        arg0.push(&self.key);
         arg0.push("] /bin/bash");
         let log_file = crate::logfile::LogFile::new(
-            crate::logfile::LogType::Extract, &self.base)?;
+            crate::logfile::LogType::Extract, &self.key)?;
         match actual_identity.set_root_drop_command(
             log_file.set_command(
                 Command::new("/bin/bash")
@@ -349,7 +466,9 @@ impl PKGBUILD {
                     .arg("-ec")
                     .arg(SCRIPT)
                     .arg("Source extractor")
-                    .arg(&pkgbuild_dir))?
+                    .arg(&pkgbuild_dir)
+                    .args(&self.arch)
+                    .envs(&self.env))?
             )
             .spawn()
         {
@@ -361,21 +480,46 @@ impl PKGBUILD {
         }
     }
 
+    /// Hash of the env and makepkg args, none if neither is set, so pkgids
+    /// of PKGBUILDs not using them stay the same
+    fn options_hash(&self) -> Option<u64> {
+        if self.env.is_empty() && self.makepkg_args.is_empty() {
+            return None
+        }
+        let mut options = vec![];
+        for (k, v) in self.env.iter() {
+            options.extend_from_slice(k.as_bytes());
+            options.push(b'=');
+            options.extend_from_slice(v.as_bytes());
+            options.push(0);
+        }
+        options.push(0);
+        for arg in self.makepkg_args.iter() {
+            options.extend_from_slice(arg.as_bytes());
+            options.push(0);
+        }
+        Some(xxh3_64(&options))
+    }
+
     fn fill_id_dir(&mut self, dephash_strategy: &DepHashStrategy) {
         let mut pkgid = if let DepHashStrategy::None = dephash_strategy
         {
-            format!("{}-{}", self.base, self.commit)
+            format!("{}-{}", self.key, self.commit)
         } else {
-            format!( "{}-{}-{:016x}", self.base, self.commit,
+            format!( "{}-{}-{:016x}", self.key, self.commit,
                 self.depends.hash)
         };
+        if let Some(options) = self.options_hex() {
+            pkgid.push('-');
+            pkgid.push_str(&options);
+        }
         if let Pkgver::Func { pkgver } = &self.pkgver {
             pkgid.push('-');
             pkgid.push_str(&pkgver);
         }
         self.pkgdir.push(&pkgid);
         self.pkgid = pkgid;
-        log::info!("PKGBUILD '{}' pkgid is '{}'", self.base, self.pkgid);
+        log::info!("PKGBUILD '{}' pkgid is '{}'", self.key, self.pkgid);
     }
 
     pub(crate) fn get_temp_pkgdir(&self) -> Result<PathBuf> {
@@ -392,17 +536,72 @@ impl PKGBUILD {
         }
     }
 
+    pub(crate) fn arch(&self) -> Option<&str> {
+        self.arch.as_deref()
+    }
+
+    pub(crate) fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub(crate) fn commit(&self) -> git2::Oid {
+        self.commit
+    }
+
+    pub(crate) fn dephash_hex(&self) -> String {
+        format!("{:016x}", self.depends.hash)
+    }
+
+    /// The hash of env and makepkg args in the pkgid, if any
+    pub(crate) fn options_hex(&self) -> Option<String> {
+        self.options_hash().map(|hash|format!("{:016x}", hash))
+    }
+
+    pub(crate) fn pkgver(&self) -> Option<&str> {
+        match &self.pkgver {
+            Pkgver::Plain => None,
+            Pkgver::Func { pkgver } => Some(pkgver),
+        }
+    }
+
+    pub(crate) fn depends(&self) -> &Depends {
+        &self.depends
+    }
+
+    pub(crate) fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    pub(crate) fn pkgdir(&self) -> &Path {
+        &self.pkgdir
+    }
+
+    /// Whether the pkgdir of the current pkgid is populated
+    pub(crate) fn built(&self) -> bool {
+        match self.pkgdir.read_dir() {
+            Ok(mut dir) => dir.next().is_some(),
+            Err(_) => false,
+        }
+    }
+
+    /// Whether the build should be done without network, the global `nonet`
+    /// could be overriden by `network` of the PKGBUILD
+    pub(crate) fn nonet(&self, nonet: bool) -> bool {
+        nonet && ! self.network
+    }
+
     pub(crate) fn get_build_command(
         &self,
         actual_identity: &IdentityActual,
-        temp_pkgdir: &Path
+        temp_pkgdir: &Path,
+        nonet: bool
     )
         -> Result<Command>
     {
         let cwd = actual_identity.cwd();
         let cwd_no_root = actual_identity.cwd_no_root()?;
         let pkgdest = cwd.join(temp_pkgdir);
-        let root = OverlayRoot::get_root_no_init(&self.base);
+        let root = OverlayRoot::get_root_no_init(&self.key);
         let mut builder = cwd.join(&root);
         builder.push(cwd_no_root);
         builder.push(&self.build);
@@ -416,18 +615,35 @@ impl PKGBUILD {
             .arg("--holdver")
             .arg("--nodeps")
             .arg("--noextract")
-            .arg("--ignorearch")
             .arg("--nosign")
+            .arg(if self.check { "--check" } else { "--nocheck" })
+            // Target arches only build what the PKGBUILDs declare, the host
+            // arch builds anything as before
+            .args(self.arch.is_none().then_some("--ignorearch"))
+            .args(&self.makepkg_args)
+            .envs(&self.env)
             .env("PKGDEST", &pkgdest);
-        actual_identity.set_root_chroot_drop_command(&mut command, chroot);
+        actual_identity.set_root_chroot_drop_command(
+            &mut command, chroot, self.nonet(nonet));
         command.env_remove("PATH");
         Ok(command)
     }
 
+    /// The dirs of links of the kind, `latest` or `updated`, the packages are
+    /// linked in, with the pkgdir relative to them
+    fn link_dirs(&self, kind: &str) -> Vec<(PathBuf, PathBuf)> {
+        let mut dirs = vec![(self.pkgdir.with_file_name(kind),
+            PathBuf::from("..").join(&self.pkgid))];
+        for arch in self.also_arches.iter() {
+            let mut rel = PathBuf::from("../..");
+            rel.push(self.arch().unwrap_or_default());
+            rel.push(&self.pkgid);
+            dirs.push((pkgs_dir(Some(arch)).join(kind), rel))
+        }
+        dirs
+    }
+
     pub(crate) fn link_pkgs(&self) -> Result<()> {
-        let mut rel = PathBuf::from("..");
-        rel.push(&self.pkgid);
-        let updated = PathBuf::from("pkgs/updated");
         // let mut bad = false;
         let readdir = match self.pkgdir.read_dir() {
             Ok(readdir) => readdir,
@@ -436,6 +652,7 @@ impl PKGBUILD {
                 return Err(e.into())
             },
         };
+        let dirs = self.link_dirs("updated");
         let mut r = Ok(());
         for entry in readdir {
             let entry = match entry {
@@ -446,19 +663,22 @@ impl PKGBUILD {
                     continue
                 },
             };
-            let original = rel.join(entry.file_name());
-            let link = updated.join(entry.file_name());
-            if let Err(e) = symlink(&original, &link) {
-                log::error!("Failed to symlink '{}' => '{}': {}",
-                    link.display(), original.display(), e);
-                r = Err(e.into());
+            for (updated, rel) in dirs.iter() {
+                let original = rel.join(entry.file_name());
+                let link = updated.join(entry.file_name());
+                if let Err(e) = symlink(&original, &link) {
+                    log::error!("Failed to symlink '{}' => '{}': {}",
+                        link.display(), original.display(), e);
+                    r = Err(e.into());
+                }
             }
         }
         r
     }
 
     pub(crate) fn finish_build(&self,
-        actual_identity: &IdentityActual, temp_pkgdir: &Path, sign: Option<&str>
+        actual_identity: &IdentityActual, temp_pkgdir: &Path,
+        sign: Option<&Signer>
     )
         -> Result<()>
     {
@@ -469,8 +689,8 @@ impl PKGBUILD {
                 return Err(e.into())
             }
         }
-        if let Some(key) = sign {
-            sign_pkgs(actual_identity, temp_pkgdir, key)?;
+        if let Some(signer) = sign {
+            signer.sign_pkgs(actual_identity, temp_pkgdir)?;
         }
         if let Err(e) = rename(&temp_pkgdir, &self.pkgdir) {
             log::error!("Failed to rename temp pkgdir '{}' to persistent pkgdir \
@@ -515,16 +735,27 @@ impl PKGBUILD {
         &self, actual_identity: &IdentityActual, nonet: bool
     ) -> Result<OverlayRoot>
     {
-        OverlayRoot::_new(&self.base, actual_identity,
-            &self.depends.needs, self.get_home_binds(), nonet)
+        OverlayRoot::_new(&self.key, self.arch(), actual_identity,
+            &self.depends.needs, self.get_home_binds(), &self.binds,
+            self.nonet(nonet))
     }
 
+    /// The wanted PKGBUILDs should already be built, their packages are
+    /// installed through a local repo
     pub(crate) fn get_bootstrapping_overlay_root(
-        &self, actual_identity: &IdentityActual, nonet: bool
+        &self, actual_identity: &IdentityActual, nonet: bool,
+        wants: &[&PKGBUILD]
     ) -> Result<BootstrappingOverlayRoot>
     {
-        BootstrappingOverlayRoot::new(&self.base, actual_identity,
-            &self.depends.needs, self.get_home_binds(), nonet)
+        let pkgdirs: Vec<PathBuf> = wants.iter().map(
+            |pkgbuild|pkgbuild.pkgdir.clone()).collect();
+        BootstrappingOverlayRoot::new(&self.key, self.arch(), actual_identity,
+            &self.depends.needs, self.get_home_binds(), &OverlayExtras {
+                pkgs_local: &self.depends.internal,
+                pkgdirs: &pkgdirs,
+                binds: &self.binds,
+                nonet: self.nonet(nonet),
+            })
     }
 }
 
@@ -532,34 +763,129 @@ impl PKGBUILD {
 pub(crate) struct PKGBUILDs (pub(crate) Vec<PKGBUILD>);
 
 impl PKGBUILDs {
+    /// The arches a PKGBUILD is built for, its own `arch` list, or all of
+    /// the configured arches, or only the host arch if there's none
+    fn target_arches<'a>(
+        name: &str, arch: Option<&'a Vec<String>>,
+        arches: &'a HashMap<String, ArchConfig>
+    ) -> Result<Vec<Option<&'a str>>>
+    {
+        let mut targets: Vec<Option<&str>> = match arch {
+            Some(arch) => {
+                if let Some(bad) = arch.iter().find(
+                    |arch|! arches.contains_key(*arch))
+                {
+                    log::error!("PKGBUILD '{}' wants arch '{}' which is not \
+                        defined in arches", name, bad);
+                    return Err(Error::InvalidConfig)
+                }
+                arch.iter().map(|arch|Some(arch.as_str())).collect()
+            },
+            None if arches.is_empty() => vec![None],
+            None => arches.keys().map(|arch|Some(arch.as_str())).collect(),
+        };
+        targets.sort_unstable();
+        targets.dedup();
+        Ok(targets)
+    }
+
+    /// Set by the builder itself for makepkg, they could not be overriden
+    const ENV_RESERVED: [&'static str; 11] = ["HOME", "LANG", "LOGNAME",
+        "PATH", "PKGDEST", "PWD", "SHELL", "SUDO_GID", "SUDO_UID",
+        "SUDO_USER", "USER"];
+
+    fn check_env(name: &str, env: &HashMap<String, String>) -> Result<()> {
+        for key in env.keys() {
+            if key.is_empty() || key.starts_with(|c: char|c.is_ascii_digit())
+                || ! key.chars().all(|c|c.is_ascii_alphanumeric() || c == '_')
+            {
+                log::error!("Env of {} has invalid variable name '{}'",
+                    name, key);
+                return Err(Error::InvalidConfig)
+            }
+            if Self::ENV_RESERVED.contains(&key.as_str()) {
+                log::error!("Env of {} sets '{}', which is set by the builder \
+                    and could not be overriden", name, key);
+                return Err(Error::InvalidConfig)
+            }
+        }
+        if let Some((key, _)) = env.iter().find(
+            |(_, value)|value.contains('\0'))
+        {
+            log::error!("Env of {} has NUL in the value of '{}'", name, key);
+            return Err(Error::InvalidConfig)
+        }
+        Ok(())
+    }
+
     pub(crate) fn from_config(
-        config: &HashMap<String, PkgbuildConfig>, home_binds_global: &Vec<String>
+        config: &HashMap<String, PkgbuildConfig>, defaults: &PkgbuildDefaults
     )
         -> Result<Self>
     {
+        Self::check_env("config", defaults.env)?;
         let build_parent = PathBuf::from("build");
         let git_parent = PathBuf::from("sources/PKGBUILD");
-        let mut pkgbuilds: Vec<_> = config.iter().map(|
-            (name, detail)|
-        {
+        let mut pkgbuilds = vec![];
+        for (name, detail) in config.iter() {
             match detail {
-                PkgbuildConfig::Simple(url) => PKGBUILD::new(
-                    name, url, &build_parent, &git_parent,
-                    None, None, None, None,
-                    None, home_binds_global
-                ),
+                PkgbuildConfig::Simple(url) => {
+                    for arch in Self::target_arches(
+                        name, None, defaults.arches)?
+                    {
+                        pkgbuilds.push(PKGBUILD::new(
+                            name, arch, url, &build_parent, &git_parent,
+                            &PkgbuildOptions::default(), defaults))
+                    }
+                },
                 PkgbuildConfig::Complex { url, branch,
                     subtree, deps,
                     makedeps,
-                    home_binds,binds: _
-                } => PKGBUILD::new(
-                    name, url, &build_parent, &git_parent,
-                    branch.as_deref(), subtree.as_deref(),
-                    deps.as_ref(), makedeps.as_ref(), home_binds.as_ref(), home_binds_global)
+                    home_binds, binds,
+                    env, makepkg_args,
+                    network, check, arch
+                } => {
+                    let mut binds_parsed = vec![];
+                    for (host, chroot) in binds.iter().flatten() {
+                        binds_parsed.push(Bind::from_config(host, chroot)?)
+                    }
+                    // Parents must be bound before their children
+                    binds_parsed.sort_unstable_by(
+                        |a, b|a.chroot().cmp(b.chroot()));
+                    if let Some(pair) = binds_parsed.windows(2).find(
+                        |pair|pair[0].chroot() == pair[1].chroot())
+                    {
+                        log::error!("PKGBUILD '{}' binds multiple paths to \
+                            '{}'", name, pair[0].chroot().display());
+                        return Err(Error::InvalidConfig)
+                    }
+                    if let Some(env) = env {
+                        Self::check_env(&format!("PKGBUILD '{}'", name), env)?
+                    }
+                    let options = PkgbuildOptions {
+                        branch: branch.as_deref(),
+                        subtree: subtree.as_deref(),
+                        deps: deps.as_ref(),
+                        makedeps: makedeps.as_ref(),
+                        home_binds: home_binds.as_ref(),
+                        binds: &binds_parsed,
+                        env: env.as_ref(),
+                        makepkg_args: makepkg_args.as_ref(),
+                        network: *network,
+                        check: *check,
+                    };
+                    for arch in Self::target_arches(
+                        name, arch.as_ref(), defaults.arches)?
+                    {
+                        pkgbuilds.push(PKGBUILD::new(
+                            name, arch, url, &build_parent, &git_parent,
+                            &options, defaults))
+                    }
+                },
             }
-        }).collect();
+        }
         pkgbuilds.sort_unstable_by(
-            |a, b| a.base.cmp(&b.base));
+            |a, b| (&a.base, &a.arch).cmp(&(&b.base, &b.arch)));
         Ok(Self(pkgbuilds))
     }
 
@@ -600,10 +926,10 @@ impl PKGBUILDs {
     pub(crate) fn from_config_healthy(
         config: &HashMap<String, PkgbuildConfig>,
         hold: bool, noclean: bool, proxy: Option<&Proxy>, gmr: Option<&Gmr>,
-        home_binds: &Vec<String>, terminal: bool
+        defaults: &PkgbuildDefaults, terminal: bool
     ) -> Result<Self>
     {
-        let mut pkgbuilds = Self::from_config(config, home_binds)?;
+        let mut pkgbuilds = Self::from_config(config, defaults)?;
         let update_pkg = if hold {
             if let Err(e) = pkgbuilds.healthy_set_commit() {
                 log::error!("Warning: holdpkg set, but PKGBUILDs unhealthy, \
@@ -648,7 +974,7 @@ impl PKGBUILDs {
         let dir = dir.as_ref();
         let mut r = Ok(());
         for pkgbuild in self.0.iter() {
-            let target = dir.join(&pkgbuild.base);
+            let target = dir.join(&pkgbuild.key);
             if let Err(e) = pkgbuild.dump(&target) {
                 log::error!("Failed to dump PKGBUILD '{}' to '{}'",
                     pkgbuild.base, target.display());
@@ -658,60 +984,113 @@ impl PKGBUILDs {
         r
     }
 
-    fn get_deps<P: AsRef<Path>> (
-        &mut self, actual_identity: &IdentityActual, dir: P, db_handle: &DbHandle,
-        dephash_strategy: &DepHashStrategy
+    /// Parse all dumped PKGBUILDs in one go for each arch
+    fn parse<P: AsRef<Path>> (
+        &mut self, actual_identity: &IdentityActual, dir: P, urlcache: bool
     ) -> Result<()>
     {
-        let mut r = Ok(());
-        let mut children = vec![];
-        for pkgbuild in self.0.iter() {
-            match pkgbuild.dep_reader(actual_identity, &dir) {
-                Ok(child) => children.push(child),
-                Err(e) => {
-                    log::error!(
-                        "Failed to spawn dep reader for PKGBUILD '{}': {}",
-                        pkgbuild.base, e);
-                    r = Err(e.into())
-                },
+        let arches: Vec<Option<String>> = self.arches().into_iter().map(
+            |arch|arch.map(String::from)).collect();
+        for arch in arches {
+            let mut pkgbuilds: Vec<&mut PKGBUILD> = self.0.iter_mut().filter(
+                |pkgbuild|pkgbuild.arch == arch).collect();
+            let parsed = parse::PkgbuildsOwned::from_dumped_pkgbuilds(
+                &dir, pkgbuilds.iter().map(
+                    |pkgbuild|(&pkgbuild.key, &pkgbuild.env)),
+                actual_identity, arch.as_deref())?;
+            for (pkgbuild, parsed) in
+                zip(pkgbuilds.iter_mut(), parsed.entries)
+            {
+                pkgbuild.fill_from_parsed(parsed, urlcache)?
             }
         }
-        if r.is_err() {
-            for mut child in children {
-                if let Err(e) = child.kill() {
-                    log::error!("Failed to kill child: {}", e);
-                    r = Err(e.into())
-                }
+        self.filter_declared_arches();
+        Ok(())
+    }
+
+    /// Drop PKGBUILDs for target arches they don't declare in `arch=()`, and
+    /// build arch-independent ones only once: for the host arch if targeted,
+    /// otherwise the first arch, then link them for the other arches
+    fn filter_declared_arches(&mut self) {
+        let mut independent: HashMap<String, Vec<String>> = HashMap::new();
+        self.0.retain(|pkgbuild|{
+            let arch = match &pkgbuild.arch {
+                Some(arch) => arch,
+                None => return true,
+            };
+            let declared = &pkgbuild.arches_declared;
+            if declared.iter().any(|declared|declared == "any") {
+                independent.entry(pkgbuild.base.clone()).or_default()
+                    .push(arch.clone());
+                true
+            } else if declared.is_empty() || declared.contains(arch) {
+                true
+            } else {
+                log::info!("PKGBUILD '{}' does not support arch '{}', \
+                    skipped", pkgbuild.base, arch);
+                false
             }
-            return r
-        }
-        if self.0.len() != children.len() {
-            return Err(Error::ImpossibleLogic)
+        });
+        let host = std::env::consts::ARCH;
+        let mut chosen: HashMap<String, (String, Vec<String>)> =
+            HashMap::new();
+        for (base, mut arches) in independent {
+            arches.sort_unstable();
+            let arch = match arches.iter().position(|arch|arch == host) {
+                Some(id) => arches.remove(id),
+                None => arches.remove(0),
+            };
+            log::info!("PKGBUILD '{}' is arch-independent, only built for \
+                arch '{}'", base, arch);
+            chosen.insert(base, (arch, arches));
         }
-        // let mut all_deps = vec![];
-        for (pkgbuild, child) in
-            zip(self.0.iter_mut(), children)
+        self.0.retain_mut(|pkgbuild|{
+            let (arch, also) = match chosen.get(&pkgbuild.base) {
+                Some(chosen) => chosen,
+                None => return true,
+            };
+            if pkgbuild.arch.as_ref() == Some(arch) {
+                pkgbuild.also_arches = also.clone();
+                true
+            } else {
+                false
+            }
+        });
+    }
+
+    /// All distinct target arches of the PKGBUILDs, sorted
+    pub(crate) fn arches(&self) -> Vec<Option<&str>> {
+        let mut arches: Vec<Option<&str>> = self.0.iter().map(
+            |pkgbuild|pkgbuild.arch()).collect();
+        arches.sort_unstable();
+        arches.dedup();
+        arches
+    }
+
+    /// Get deps of PKGBUILDs of the arch only, from the DBs of that arch
+    fn get_deps(
+        &mut self, db_handle: &DbHandle, arch: Option<&str>,
+        dephash_strategy: &DepHashStrategy
+    ) -> Result<()>
+    {
+        let mut r = Ok(());
+        // Deps provided by our other PKGBUILDs are not looked up in DBs
+        let internals: Vec<Vec<String>> = self.0.iter().map(|pkgbuild|
+            self.0.iter().filter(|other|!std::ptr::eq(pkgbuild, *other))
+                .filter_map(|other|pkgbuild.wants(other).map(String::from))
+                .collect()
+        ).collect();
+        for (pkgbuild, internal) in
+            zip(self.0.iter_mut(), internals)
         {
-            let output = child.wait_with_output()
-                .expect("Failed to wait for child");
-            for line in
-                output.stdout.split(|byte| byte == &b'\n')
-            {
-                if line.len() == 0 {
-                    continue;
-                }
-                let dep =
-                    String::from_utf8_lossy(&line[2..]).into_owned();
-                match &line[0..2] {
-                    b"d:" => pkgbuild.depends.deps.push(dep),
-                    b"m:" => pkgbuild.depends.makedeps.push(dep),
-                    _ => ()
-                }
+            if pkgbuild.arch() != arch {
+                continue
             }
-            pkgbuild.depends.deps.sort_unstable();
-            pkgbuild.depends.makedeps.sort_unstable();
-            pkgbuild.depends.deps.dedup();
-            pkgbuild.depends.makedeps.dedup();
+            if ! internal.is_empty() {
+                log::info!("PKGBUILD '{}' depends on our own packages: {:?}",
+                    &pkgbuild.base, &internal);
+            }
+            pkgbuild.depends.internal = internal;
             match pkgbuild.depends.needed_and_hash(
                 db_handle, dephash_strategy)
             {
@@ -737,166 +1116,20 @@ impl PKGBUILDs {
 
     }
 
-    fn check_deps<P: AsRef<Path>> (
-        &mut self, actual_identity: &IdentityActual, dir: P, root: P,
-        dephash_strategy: &DepHashStrategy
+    fn check_deps(
+        &mut self, root: &BaseRoot, dephash_strategy: &DepHashStrategy
     )   -> Result<()>
     {
-        let db_handle = DbHandle::new(root)?;
-        self.get_deps(actual_identity, dir, &db_handle, dephash_strategy)
+        let db_handle = DbHandle::new(root.path(), root.pacman_conf())?;
+        self.get_deps(&db_handle, root.arch(), dephash_strategy)
     }
 
-    fn get_all_sources<P: AsRef<Path>> (&mut self, dir: P)
-      -> Result<(Vec<source::Source>, Vec<source::Source>, Vec<source::Source>)>
-    {
-        let mut sources_non_unique = vec![];
-        for pkgbuild in self.0.iter_mut() {
-            if let Err(e) = pkgbuild.get_sources(&dir) {
-                log::error!("Failed to get sources for PKGBUILD '{}'",
-                    pkgbuild.base);
-                return Err(e);
-            } else {
-                for source in pkgbuild.sources.iter() {
-                    sources_non_unique.push(source);
-                }
-            }
-        }
+    fn get_all_sources(&self) -> Result<source::UniqueSources> {
+        let sources_non_unique: Vec<&source::Source> = self.0.iter().flat_map(
+            |pkgbuild|pkgbuild.sources.iter()).collect();
         source::unique_sources(&sources_non_unique)
     }
 
-    fn filter_with_pkgver_func<P: AsRef<Path>>(
-        &mut self, actual_identity: &IdentityActual, dir: P
-    ) -> Result<Vec<&mut PKGBUILD>>
-    {
-        let mut buffer = vec![];
-        for pkgbuild in self.0.iter() {
-            for byte in pkgbuild.base.bytes() {
-                buffer.push(byte)
-            }
-            buffer.push(b'\n');
-        }
-        let mut child = match actual_identity.set_root_drop_command(
-            Command::new("/bin/bash")
-                .arg("-c")
-                .arg(
-                   "cd \"$1\"
-                    while read -r line; do \
-                        source \"$line\"; \
-                        type -t pkgver; \
-                        printf '|'; \
-                        unset -f pkgver; \
-                    done")
-                .arg("Type Identifier")
-                .arg(dir.as_ref())
-                .stdin(Stdio::piped())
-                .stdout(Stdio::piped()))
-                .stderr(Stdio::null())
-            .spawn()
-        {
-            Ok(child) => child,
-            Err(e) => {
-                log::error!("Failed to spawn child to read pkgver types: {}", e);
-                return Err(e.into())
-            },
-        };
-        let mut child_in = match child.stdin.take() {
-            Some(stdin) => stdin,
-            None => {
-                log::error!("Failed to take child stdin");
-                if let Err(e) = child.kill() {
-                    log::error!("Failed to kill child: {}", e);
-                }
-                return Err(Error::ImpossibleLogic)
-            },
-        };
-        let mut child_out = match child.stdout.take() {
-            Some(stdout) => stdout,
-            None => {
-                log::error!("Failed to take child stdout");
-                if let Err(e) = child.kill() {
-                    log::error!("Failed to kill child: {}", e);
-                }
-                return Err(Error::ImpossibleLogic)
-            },
-        };
-        let mut output = vec![];
-        let mut output_buffer = vec![0; libc::PIPE_BUF];
-        let mut written = 0;
-        let total = buffer.len();
-        while written < total {
-            let mut end = written + libc::PIPE_BUF;
-            if end > total {
-                end = total;
-            }
-            match child_in.write(&buffer[written..end]) {
-                Ok(written_this) => written += written_this,
-                Err(e) => {
-                    log::error!("Failed to write buffer to child: {}", e);
-                    if let Err(e) = child.kill() {
-                        log::error!("Failed to kill child: {}", e);
-                    }
-                    return Err(e.into())
-                },
-            }
-            match child_out.read(&mut output_buffer[..]) {
-                Ok(read_this) =>
-                    output.extend_from_slice(&output_buffer[0..read_this]),
-                Err(e) => {
-                    log::error!("Failed to read stdout child: {}", e);
-                    if let Err(e) = child.kill() {
-                        log::error!("Failed to kill child: {}", e);
-                    }
-                    return Err(e.into())
-                },
-            }
-        }
-        drop(child_in);
-        match child_out.read_to_end(&mut output) {
-            Ok(_) => (),
-            Err(e) => {
-                log::error!("Failed to read stdout child: {}", e);
-                if let Err(e) = child.kill() {
-                    log::error!("Failed to kill child: {}", e);
-                }
-                return Err(e.into())
-            },
-        }
-        let status = match child.wait() {
-            Ok(status) => status,
-            Err(e) => {
-                log::error!(
-                    "Failed to wait for child reading pkgver type: {}", e);
-                return Err(e.into())
-            },
-        };
-        match status.code() {
-            Some(code) => {
-                if code != 0 {
-                    log::error!("Reader bad return");
-                    return Err(Error::BadChild { pid: None, code: Some(code) })
-                }
-            },
-            None => {
-                log::error!("Failed to get return code from child reading \
-                        pkgver type");
-                return Err(Error::ImpossibleLogic)
-            },
-        }
-        let types: Vec<&[u8]> =
-            output.split(|byte| *byte == b'|').collect();
-        let types = &types[0..self.0.len()];
-        assert!(types.len() == self.0.len());
-        let mut pkgbuilds_with_pkgver_func = vec![];
-        for (pkgbuild, pkgver_type) in
-            zip(self.0.iter_mut(), types.iter())
-        {
-            if pkgver_type == b"function\n" {
-                pkgbuilds_with_pkgver_func.push(pkgbuild)
-            }
-        }
-        Ok(pkgbuilds_with_pkgver_func)
-    }
-
     fn extract_sources_many(
         actual_identity: &IdentityActual,
         pkgbuilds: &mut [&mut PKGBUILD]
@@ -923,13 +1156,11 @@ impl PKGBUILDs {
         r
     }
 
-    fn fill_all_pkgvers<P: AsRef<Path>>(
-        &mut self, actual_identity: &IdentityActual, dir: P
-    )
+    fn fill_all_pkgvers(&mut self, actual_identity: &IdentityActual)
         -> Result<()>
     {
-        let mut pkgbuilds =
-            self.filter_with_pkgver_func(actual_identity, dir)?;
+        let mut pkgbuilds: Vec<&mut PKGBUILD> = self.0.iter_mut().filter(
+            |pkgbuild|pkgbuild.pkgver_func).collect();
         Self::extract_sources_many(actual_identity, &mut pkgbuilds)?;
         let children: Vec<Child> = pkgbuilds.iter().map(
         |pkgbuild| {
@@ -942,6 +1173,7 @@ impl PKGBUILDs {
                     .arg(pkgbuild.build.join("src")
                         .canonicalize()
                         .expect("Failed to canonicalize dir"))
+                    .envs(&pkgbuild.env)
                     .stdout(Stdio::piped()))
                 .spawn()
                 .expect("Failed to run script")
@@ -960,29 +1192,85 @@ impl PKGBUILDs {
         Ok(())
     }
 
+    /// A PKGBUILD's pkgid is only filled after those of the PKGBUILDs it
+    /// wants, which are folded into its dephash
     fn fill_all_ids_dirs(&mut self, dephash_strategy: &DepHashStrategy) {
-        for pkgbuild in self.0.iter_mut() {
-            pkgbuild.fill_id_dir(dephash_strategy)
+        let mut filled = vec![false; self.0.len()];
+        loop {
+            let mut progressed = false;
+            for id in 0..self.0.len() {
+                if filled[id] {
+                    continue
+                }
+                let mut wanted = vec![];
+                let mut ready = true;
+                for (id_other, other) in self.0.iter().enumerate() {
+                    if id_other == id {
+                        continue
+                    }
+                    if let Some(dep) = self.0[id].wants(other) {
+                        if ! filled[id_other] {
+                            ready = false;
+                            break
+                        }
+                        wanted.push((dep.to_string(), other.pkgid.clone()))
+                    }
+                }
+                if ! ready {
+                    continue
+                }
+                let pkgbuild = &mut self.0[id];
+                for (dep, pkgid) in wanted.iter() {
+                    pkgbuild.depends.fold_internal(dep, pkgid, dephash_strategy)
+                }
+                pkgbuild.fill_id_dir(dephash_strategy);
+                filled[id] = true;
+                progressed = true
+            }
+            if ! progressed {
+                break
+            }
+        }
+        for (pkgbuild, filled) in zip(self.0.iter_mut(), filled) {
+            if ! filled {
+                log::warn!("PKGBUILD '{}' is in a dependency cycle, its \
+                    dephash does not cover our own packages", &pkgbuild.base);
+                pkgbuild.fill_id_dir(dephash_strategy)
+            }
         }
     }
 
-    fn check_if_need_build(&mut self)
+    fn check_if_need_build(&mut self, retry_failed: bool)
         -> Result<u32>
     {
         let mut cleaners = vec![];
         let mut r = Ok(0);
         let mut need_build = 0;
         for pkgbuild in self.0.iter_mut() {
-            let mut built = false;
-            if let Ok(mut dir) = pkgbuild.pkgdir.read_dir() {
-                if let Some(_) = dir.next() {
-                    built = true;
+            let built = pkgbuild.built();
+            let mut failed = false;
+            if ! built {
+                if let Some(marker) = FailureMarker::read(&pkgbuild.pkgid) {
+                    if retry_failed {
+                        log::warn!("Retrying '{}' which failed at {}",
+                            pkgbuild.pkgid, marker.time);
+                    } else {
+                        log::warn!("Skipped '{}' as it failed all {} tries at \
+                            {} (log: {}), it would be retried when its commit, \
+                            dephash or pkgver changes, or with --retry-failed",
+                            pkgbuild.pkgid, marker.tries, marker.time,
+                            marker.log.as_deref().unwrap_or(
+                                Path::new("(none)")).display());
+                        failed = true
+                    }
                 }
             }
-            if built { // Does not need build
+            if built || failed { // Does not need build
                 pkgbuild.need_build = false;
-                log::info!("Skipped already built '{}'",
-                    pkgbuild.pkgdir.display());
+                if built {
+                    log::info!("Skipped already built '{}'",
+                        pkgbuild.pkgdir.display());
+                }
                 if pkgbuild.extracted {
                     pkgbuild.extracted = false;
                     let dir = pkgbuild.build.clone();
@@ -1010,20 +1298,13 @@ impl PKGBUILDs {
         r
     }
 
-    pub(crate) fn prepare_sources(
+    /// Dump and parse all PKGBUILDs, then cache all of their sources
+    fn fetch_sources(
         &mut self,
         actual_identity: &IdentityActual,
-        basepkgs: &Vec<String>,
-        holdgit: bool,
-        skipint: bool,
-        noclean: bool,
-        proxy: Option<&Proxy>,
-        gmr: Option<&git::Gmr>,
-        dephash_strategy: &DepHashStrategy,
-        terminal: bool
-    ) -> Result<Option<BaseRoot>>
+        fetch: &FetchSettings
+    ) -> Result<source::UniqueSources>
     {
-
         let dir = match tempfile::tempdir() {
             Ok(dir) => dir,
             Err(e) => {
@@ -1031,18 +1312,49 @@ impl PKGBUILDs {
                 return Err(e.into())
             },
         };
+        self.dump(&dir)?;
+        self.parse(actual_identity, &dir, fetch.urlcache)?;
+        let sources = self.get_all_sources()?;
+        source::cache_sources_mt(
+            &sources.0, &sources.1, &sources.2, actual_identity, fetch)?;
+        Ok(sources)
+    }
+
+    /// Fetch all sources, then return the paths of all caches needed by the
+    /// PKGBUILDs, including the PKGBUILDs repos themselves
+    pub(crate) fn bundle_paths(
+        &mut self,
+        actual_identity: &IdentityActual,
+        fetch: &FetchSettings
+    ) -> Result<Vec<PathBuf>>
+    {
+        let (netfile_sources, git_sources, vcs_sources, _)
+            = self.fetch_sources(actual_identity, fetch)?;
+        let mut paths: Vec<PathBuf> = self.0.iter().map(
+            |pkgbuild|pkgbuild.git.clone()).collect();
+        paths.append(&mut source::cache_paths(
+            &netfile_sources, &git_sources, &vcs_sources));
+        paths.sort_unstable();
+        paths.dedup();
+        Ok(paths)
+    }
+
+    /// Fetch all sources into a clean build dir, and start cleaning unused
+    /// sources unless noclean
+    fn fetch_and_clean(
+        &mut self,
+        actual_identity: &IdentityActual,
+        fetch: &FetchSettings
+    ) -> Result<Option<Vec<JoinHandle<()>>>>
+    {
         let cleaner = match
             PathBuf::from("build").exists()
         {
             true => Some(thread::spawn(|| remove_dir_all_try_best("build"))),
             false => None,
         };
-        self.dump(&dir)?;
-        let (netfile_sources, git_sources, _)
-            = self.get_all_sources(&dir)?;
-        source::cache_sources_mt(
-            &netfile_sources, &git_sources, actual_identity,
-            holdgit, skipint, proxy, gmr, terminal)?;
+        let (netfile_sources, git_sources, vcs_sources, _)
+            = self.fetch_sources(actual_identity, fetch)?;
         if let Some(cleaner) = cleaner {
             match cleaner.join() {
                 Ok(r) => if let Err(e) = r {
@@ -1055,67 +1367,156 @@ impl PKGBUILDs {
                 },
             }
         }
-        let cleaners = match noclean {
+        Ok(match fetch.noclean {
             true => None,
-            false => Some(source::cleanup(netfile_sources, git_sources)),
-        };
-        self.fill_all_pkgvers(actual_identity, &dir)?;
-        // Use the fresh DBs in target root
-        let base_root = BaseRoot::db_only()?;
-        self.check_deps(
-            actual_identity, dir.as_ref(), base_root.path(),
-            dephash_strategy)?;
+            false => Some(source::cleanup(
+                netfile_sources, git_sources, vcs_sources)),
+        })
+    }
+
+    fn join_cleaners(cleaners: Option<Vec<JoinHandle<()>>>) {
+        if let Some(cleaners) = cleaners {
+            for cleaner in cleaners {
+                cleaner.join()
+                .expect("Failed to join sources cleaner thread");
+            }
+        }
+    }
+
+    /// Fill pkgvers, deps and pkgids, and decide which PKGBUILDs need build,
+    /// returns the base roots of all arches with only fresh DBs
+    fn plan(
+        &mut self,
+        actual_identity: &IdentityActual,
+        base_configs: BaseConfigs,
+        dephash_strategy: &DepHashStrategy,
+        retry_failed: bool
+    ) -> Result<(Vec<BaseRoot>, bool)>
+    {
+        self.fill_all_pkgvers(actual_identity)?;
+        BaseRoot::remove_all()?;
+        let mut base_roots = vec![];
+        for arch in self.arches() {
+            // Use the fresh DBs in target root
+            base_roots.push(BaseRoot::db_only(arch, base_configs)?)
+        }
+        for base_root in base_roots.iter() {
+            self.check_deps(base_root, dephash_strategy)?
+        }
         self.fill_all_ids_dirs(dephash_strategy);
-        let need_builds = self.check_if_need_build()? > 0;
+        let need_builds = self.check_if_need_build(retry_failed)? > 0;
+        Ok((base_roots, need_builds))
+    }
+
+    /// Fetch all sources only, for the fetch stage
+    pub(crate) fn fetch(
+        &mut self,
+        actual_identity: &IdentityActual,
+        fetch: &FetchSettings
+    ) -> Result<()>
+    {
+        let cleaners = self.fetch_and_clean(actual_identity, fetch)?;
+        Self::join_cleaners(cleaners);
+        Ok(())
+    }
+
+    /// Fetch all sources and fill pkgids, without preparing the base root, so
+    /// pkgids and what need build could be inspected
+    pub(crate) fn resolve(
+        &mut self,
+        actual_identity: &IdentityActual,
+        fetch: &FetchSettings,
+        base_configs: BaseConfigs,
+        dephash_strategy: &DepHashStrategy,
+        retry_failed: bool
+    ) -> Result<()>
+    {
+        let cleaners = self.fetch_and_clean(actual_identity, fetch)?;
+        let r = self.plan(
+            actual_identity, base_configs, dephash_strategy, retry_failed);
+        Self::join_cleaners(cleaners);
+        r.map(|_|())
+    }
+
+    pub(crate) fn prepare_sources(
+        &mut self,
+        actual_identity: &IdentityActual,
+        basepkgs: &Vec<String>,
+        fetch: &FetchSettings,
+        base_configs: BaseConfigs,
+        dephash_strategy: &DepHashStrategy,
+        retry_failed: bool
+    ) -> Result<Option<Vec<BaseRoot>>>
+    {
+        let cleaners = self.fetch_and_clean(actual_identity, fetch)?;
+        let (base_roots, need_builds) = self.plan(
+            actual_identity, base_configs, dephash_strategy, retry_failed)?;
         if need_builds {
-            let mut all_deps = vec![];
-            for pkgbuild in self.0.iter() {
-                if ! pkgbuild.need_build {
+            for base_root in base_roots.iter() {
+                // Only roots of arches with something to build are finished
+                if ! self.0.iter().any(|pkgbuild|
+                    pkgbuild.need_build && pkgbuild.arch() == base_root.arch())
+                {
                     continue
                 }
-                for dep in pkgbuild.depends.needs.iter() {
-                    all_deps.push(dep.clone())
+                let mut all_deps = vec![];
+                for pkgbuild in self.0.iter() {
+                    if ! pkgbuild.need_build ||
+                        pkgbuild.arch() != base_root.arch()
+                    {
+                        continue
+                    }
+                    for dep in pkgbuild.depends.needs.iter() {
+                        all_deps.push(dep.clone())
+                    }
                 }
-            }
-            for pkg in basepkgs.iter() {
-                all_deps.push(pkg.clone())
-            }
-            all_deps.sort_unstable();
-            all_deps.dedup();
-            Depends::cache_raw(&all_deps, base_root.db_path())?;
-            base_root.finish(actual_identity, basepkgs)?;
-            let db_handle = DbHandle::new(base_root.path())?;
-            for pkgbuild in self.0.iter_mut() {
-                if pkgbuild.need_build {
-                    pkgbuild.depends.update_needed(&db_handle);
+                for pkg in basepkgs.iter() {
+                    all_deps.push(pkg.clone())
+                }
+                all_deps.sort_unstable();
+                all_deps.dedup();
+                Depends::cache_raw(&all_deps, base_root.db_path(),
+                    base_root.pacman_conf(), base_root.arch())?;
+                base_root.finish(actual_identity, basepkgs)?;
+                let db_handle = DbHandle::new(
+                    base_root.path(), base_root.pacman_conf())?;
+                for pkgbuild in self.0.iter_mut() {
+                    if pkgbuild.need_build &&
+                        pkgbuild.arch() == base_root.arch()
+                    {
+                        pkgbuild.depends.update_needed(&db_handle);
+                    }
                 }
             }
         }
-        if let Some(cleaners) = cleaners {
-            for cleaner in cleaners {
-                cleaner.join()
-                .expect("Failed to join sources cleaner thread");
-            }
-        }
+        Self::join_cleaners(cleaners);
         if need_builds {
-            Ok(Some(base_root))
+            Ok(Some(base_roots))
         } else {
             Ok(None)
         }
     }
 
-    pub(crate) fn clean_pkgdir(&self) {
-        let mut used: Vec<String> = self.0.iter().map(
-            |pkgbuild| pkgbuild.pkgid.clone()).collect();
-        used.push(String::from("updated"));
-        used.push(String::from("latest"));
-        used.sort_unstable();
-        source::remove_unused("pkgs", &used);
+    /// Remove pkgdirs not used by any PKGBUILD under the pkgs dir of each
+    /// arch, and failure markers of unused pkgids
+    pub(crate) fn clean_pkgdir(&self, arches: &[Option<&str>]) {
+        let mut used_all = vec![];
+        for arch in arches {
+            let mut used: Vec<String> = self.0.iter().filter(
+                |pkgbuild|pkgbuild.arch() == *arch).map(
+                |pkgbuild| pkgbuild.pkgid.clone()).collect();
+            used_all.extend(used.iter().cloned());
+            used.push(String::from("updated"));
+            used.push(String::from("latest"));
+            used.push(String::from("repo"));
+            used.sort_unstable();
+            source::remove_unused(pkgs_dir(*arch), &used);
+        }
+        used_all.sort_unstable();
+        FailureMarker::remove_unused(&used_all);
     }
 
     pub(crate) fn link_pkgs(&self) {
-        let rel = PathBuf::from("..");
-        let latest = PathBuf::from("pkgs/latest");
         for pkgbuild in self.0.iter() {
             if ! pkgbuild.pkgdir.exists() {
                 continue;
@@ -1128,14 +1529,16 @@ impl PKGBUILDs {
                     continue
                 },
             };
-            let rel = rel.join(&pkgbuild.pkgid);
+            let dirs = pkgbuild.link_dirs("latest");
             for entry in dirent {
                 if let Ok(entry) = entry {
-                    let original = rel.join(entry.file_name());
-                    let link = latest.join(entry.file_name());
-                    if let Err(e) = symlink(&original, &link) {
-                        log::error!("Failed to link '{}' => '{}': {}",
-                            link.display(), original.display(), e);
+                    for (latest, rel) in dirs.iter() {
+                        let original = rel.join(entry.file_name());
+                        let link = latest.join(entry.file_name());
+                        if let Err(e) = symlink(&original, &link) {
+                            log::error!("Failed to link '{}' => '{}': {}",
+                                link.display(), original.display(), e);
+                        }
                     }
                 }
             }
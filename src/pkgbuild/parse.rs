@@ -1,8 +1,25 @@
-// Parse an on-disk
+// Parse on-disk PKGBUILDs in one go, with a single bash instance
 
-use std::{path::Path, process::{Command, Stdio}, io::{Write, Read}};
+use std::{
+        collections::BTreeMap,
+        io::{
+            Read,
+            Write,
+        },
+        path::Path,
+        process::{
+            Command,
+            Stdio,
+        },
+    };
 
-use crate::identity::IdentityActual;
+use crate::{
+        error::{
+            Error,
+            Result
+        },
+        identity::IdentityActual,
+    };
 
 struct PackageBorrowed<'a> {
     name: &'a [u8],
@@ -20,13 +37,22 @@ impl<'a> Default for PackageBorrowed<'a> {
     }
 }
 
+#[derive(Default)]
+struct SourceBorrowed<'a> {
+    name: &'a [u8],
+    protocol: &'a [u8],
+    url: &'a [u8],
+}
+
 struct PkgbuildBorrowed<'a> {
     base: &'a [u8],
     pkgs: Vec<PackageBorrowed<'a>>,
+    arches: Vec<&'a [u8]>,
     deps: Vec<&'a [u8]>,
     makedeps: Vec<&'a [u8]>,
+    checkdeps: Vec<&'a [u8]>,
     provides: Vec<&'a [u8]>,
-    sources: Vec<&'a [u8]>,
+    sources: Vec<SourceBorrowed<'a>>,
     cksums: Vec<&'a [u8]>,
     md5sums: Vec<&'a [u8]>,
     sha1sums: Vec<&'a [u8]>,
@@ -38,43 +64,15 @@ struct PkgbuildBorrowed<'a> {
     pkgver_func: bool,
 }
 
-impl<'a> PkgbuildBorrowed<'a> {
-    fn find_pkg_mut(&'a mut self, name: &[u8])
-        -> Result<&mut PackageBorrowed>
-    {
-        let mut pkg = None;
-        for pkg_cmp in self.pkgs.iter_mut() {
-            if pkg_cmp.name == name {
-                pkg = Some(pkg_cmp);
-                break
-            }
-        }
-        pkg.ok_or_else(||log::error!("Failed to find pkg {}",
-            String::from_utf8_lossy(name)))
-    }
-    fn push_pkg_dep(&'a mut self, pkg_name: &[u8], dep: &'a [u8])
-        -> Result<()>
-    {
-        let pkg = self.find_pkg_mut(pkg_name)?;
-        pkg.deps.push(dep);
-        Ok(())
-    }
-    fn push_pkg_provide(&'a mut self, pkg_name: &[u8], provide: &'a [u8])
-        -> Result<()>
-    {
-        let pkg = self.find_pkg_mut(pkg_name)?;
-        pkg.provides.push(provide);
-        Ok(())
-    }
-}
-
 impl<'a> Default for PkgbuildBorrowed<'a> {
     fn default() -> Self {
         Self {
             base: b"",
             pkgs: vec![],
+            arches: vec![],
             deps: vec![],
             makedeps: vec![],
+            checkdeps: vec![],
             provides: vec![],
             sources: vec![],
             cksums: vec![],
@@ -90,13 +88,20 @@ impl<'a> Default for PkgbuildBorrowed<'a> {
     }
 }
 
+/// The PKGBUILD of the entry id in the names list, as the broken one
+fn broken(names: &[String], id: usize) -> Error {
+    Error::BrokenPKGBUILDs(names.get(id).cloned().into_iter().collect())
+}
+
 struct PkgbuildsBorrowed<'a> {
     entries: Vec<PkgbuildBorrowed<'a>>
 }
 
 
 impl<'a> PkgbuildsBorrowed<'a> {
-    fn from_parser_output(output: &'a Vec<u8>) -> Result<Self> {
+    /// Names are those of the PKGBUILDs in the order they're parsed, to tell
+    /// which one is broken
+    fn from_parser_output(output: &'a [u8], names: &[String]) -> Result<Self> {
         let mut pkgbuilds = vec![];
         let mut pkgbuild = PkgbuildBorrowed::default();
         let mut started = false;
@@ -105,22 +110,42 @@ impl<'a> PkgbuildsBorrowed<'a> {
             if line.contains(&b':') {
                 let mut it =
                     line.splitn(2, |byte| byte == &b':');
-                let key = it.next().ok_or_else(
-                    ||log::error!("Failed to get key"))?;
-                let value = it.next().ok_or_else(
-                    ||log::error!("Failed to get value"))?;
+                let key = it.next().ok_or_else(||{
+                    log::error!("Failed to get key");
+                    Error::ImpossibleLogic
+                })?;
+                let value = it.next().ok_or_else(||{
+                    log::error!("Failed to get value");
+                    Error::ImpossibleLogic
+                })?;
                 match key {
                     b"base" => pkgbuild.base = value,
-                    b"name" => {
-                        let mut pkg =
-                            PackageBorrowed::default();
-                        pkg.name = value;
-                        pkgbuild.pkgs.push(pkg);
-                    },
+                    b"name" => pkgbuild.pkgs.push(PackageBorrowed {
+                        name: value,
+                        ..Default::default()
+                    }),
+                    b"arch" => pkgbuild.arches.push(value),
                     b"dep" => pkgbuild.deps.push(value),
                     b"makedep" => pkgbuild.makedeps.push(value),
+                    b"checkdep" => pkgbuild.checkdeps.push(value),
                     b"provide" => pkgbuild.provides.push(value),
-                    b"source" => pkgbuild.sources.push(value),
+                    b"source" => pkgbuild.sources.push(SourceBorrowed {
+                        name: value,
+                        ..Default::default()
+                    }),
+                    b"protocol" | b"url" => {
+                        let source = pkgbuild.sources.last_mut().ok_or_else(
+                        ||{
+                            log::error!("Source attribute before source: {}",
+                                String::from_utf8_lossy(line));
+                            broken(names, pkgbuilds.len())
+                        })?;
+                        if key == b"protocol" {
+                            source.protocol = value
+                        } else {
+                            source.url = value
+                        }
+                    },
                     b"ck" => pkgbuild.cksums.push(value),
                     b"md5" => pkgbuild.md5sums.push(value),
                     b"sha1" => pkgbuild.sha1sums.push(value),
@@ -135,7 +160,7 @@ impl<'a> PkgbuildsBorrowed<'a> {
                         _ => {
                             log::error!("Unexpected value: {}",
                                 String::from_utf8_lossy(value));
-                            return Err(())
+                            return Err(broken(names, pkgbuilds.len()))
                         }
                     }
                     _ => {
@@ -145,7 +170,7 @@ impl<'a> PkgbuildsBorrowed<'a> {
                         else {
                             log::error!("Unexpected line: {}",
                                 String::from_utf8_lossy(line));
-                            return Err(())
+                            return Err(broken(names, pkgbuilds.len()))
                         };
                         let name = &key[offset..];
                         let mut pkg = None;
@@ -157,9 +182,11 @@ impl<'a> PkgbuildsBorrowed<'a> {
                                 break
                             }
                         }
-                        let pkg = pkg.ok_or_else(
-                            ||log::error!("Failed to find pkg {}",
-                            String::from_utf8_lossy(name)))?;
+                        let pkg = pkg.ok_or_else(||{
+                            log::error!("Failed to find pkg {}",
+                                String::from_utf8_lossy(name));
+                            broken(names, pkgbuilds.len())
+                        })?;
                         if is_dep {
                             pkg.deps.push(value)
                         } else {
@@ -176,44 +203,59 @@ impl<'a> PkgbuildsBorrowed<'a> {
                 }
             } else {
                 log::error!("Illegal line: {}", String::from_utf8_lossy(line));
-                return Err(())
+                return Err(broken(names, pkgbuilds.len()))
             }
         }
-        pkgbuilds.push(pkgbuild);
+        if started {
+            pkgbuilds.push(pkgbuild);
+        }
         Ok(Self {
             entries: pkgbuilds,
         })
     }
 }
 
-struct PackageOwned {
-    name: String,
-    deps: Vec<String>,
-    provides: Vec<String>,
+pub(super) struct PackageOwned {
+    pub(super) name: String,
+    pub(super) deps: Vec<String>,
+    pub(super) provides: Vec<String>,
 }
 
-struct PkgbuildOwned {
-    base: String,
-    pkgs: Vec<PackageOwned>,
-    deps: Vec<String>,
-    makedeps: Vec<String>,
-    provides: Vec<String>,
-    sources: Vec<String>,
-    cksums: Vec<String>,
-    md5sums: Vec<String>,
-    sha1sums: Vec<String>,
-    sha224sums: Vec<String>,
-    sha256sums: Vec<String>,
-    sha384sums: Vec<String>,
-    sha512sums: Vec<String>,
-    b2sums: Vec<String>,
-    pkgver_func: bool,
+pub(super) struct SourceOwned {
+    pub(super) name: String,
+    pub(super) protocol: String,
+    pub(super) url: String,
 }
-struct PkgbuildsOwned {
-    entries: Vec<PkgbuildOwned>
+
+pub(super) struct PkgbuildOwned {
+    pub(super) base: String,
+    pub(super) pkgs: Vec<PackageOwned>,
+    pub(super) arches: Vec<String>,
+    pub(super) deps: Vec<String>,
+    pub(super) makedeps: Vec<String>,
+    pub(super) checkdeps: Vec<String>,
+    pub(super) provides: Vec<String>,
+    pub(super) sources: Vec<SourceOwned>,
+    pub(super) cksums: Vec<String>,
+    pub(super) md5sums: Vec<String>,
+    pub(super) sha1sums: Vec<String>,
+    pub(super) sha224sums: Vec<String>,
+    pub(super) sha256sums: Vec<String>,
+    pub(super) sha384sums: Vec<String>,
+    pub(super) sha512sums: Vec<String>,
+    pub(super) b2sums: Vec<String>,
+    pub(super) pkgver_func: bool,
+}
+
+pub(super) struct PkgbuildsOwned {
+    pub(super) entries: Vec<PkgbuildOwned>
 }
 
-fn vec_string_from_vec_u8(original: &Vec<&[u8]>) -> Vec<String> {
+fn string_from_u8(original: &[u8]) -> String {
+    String::from_utf8_lossy(original).into_owned()
+}
+
+fn vec_string_from_vec_u8(original: &[&[u8]]) -> Vec<String> {
     original.iter().map(|item|
         String::from_utf8_lossy(item).into_owned()).collect()
 }
@@ -221,23 +263,36 @@ fn vec_string_from_vec_u8(original: &Vec<&[u8]>) -> Vec<String> {
 impl PackageOwned {
     fn from_borrowed(borrowed: &PackageBorrowed) -> Self {
         Self {
-            name: String::from_utf8_lossy(borrowed.name).into_owned(),
+            name: string_from_u8(borrowed.name),
             deps: vec_string_from_vec_u8(&borrowed.deps),
             provides: vec_string_from_vec_u8(&borrowed.provides),
         }
     }
 }
 
+impl SourceOwned {
+    fn from_borrowed(borrowed: &SourceBorrowed) -> Self {
+        Self {
+            name: string_from_u8(borrowed.name),
+            protocol: string_from_u8(borrowed.protocol),
+            url: string_from_u8(borrowed.url),
+        }
+    }
+}
+
 impl PkgbuildOwned {
     fn from_borrowed(borrowed: &PkgbuildBorrowed) -> Self {
         Self {
-            base: String::from_utf8_lossy(borrowed.base).into_owned(),
+            base: string_from_u8(borrowed.base),
             pkgs: borrowed.pkgs.iter().map(|pkg|
                 PackageOwned::from_borrowed(pkg)).collect(),
+            arches: vec_string_from_vec_u8(&borrowed.arches),
             deps: vec_string_from_vec_u8(&borrowed.deps),
             makedeps: vec_string_from_vec_u8(&borrowed.makedeps),
+            checkdeps: vec_string_from_vec_u8(&borrowed.checkdeps),
             provides: vec_string_from_vec_u8(&borrowed.provides),
-            sources: vec_string_from_vec_u8(&borrowed.sources),
+            sources: borrowed.sources.iter().map(|source|
+                SourceOwned::from_borrowed(source)).collect(),
             cksums: vec_string_from_vec_u8(&borrowed.cksums),
             md5sums: vec_string_from_vec_u8(&borrowed.md5sums),
             sha1sums: vec_string_from_vec_u8(&borrowed.sha1sums),
@@ -261,106 +316,288 @@ impl PkgbuildsOwned {
     }
 }
 
+/// The names in list, and the input for the parser: each entry is the
+/// PKGBUILD name, then either `#` and the index of an env already sent, or a
+/// new env as KEY=VALUE items ended by an empty one, all terminated by NUL
+fn parser_input<'a, I, S>(list: I) -> (Vec<String>, Vec<u8>)
+where
+    I: IntoIterator<Item = (S, &'a BTreeMap<String, String>)>,
+    S: AsRef<str>,
+{
+    let mut input = vec![];
+    let mut names = vec![];
+    let mut envs: Vec<&BTreeMap<String, String>> = vec![];
+    for (pkgbuild_name, env) in list.into_iter() {
+        names.push(pkgbuild_name.as_ref().to_string());
+        input.extend_from_slice(pkgbuild_name.as_ref().as_bytes());
+        input.push(0);
+        if let Some(id) = envs.iter().position(|sent|*sent == env) {
+            input.extend_from_slice(format!("#{}", id).as_bytes());
+            input.push(0);
+            continue
+        }
+        for (key, value) in env.iter() {
+            input.extend_from_slice(format!("{}={}", key, value).as_bytes());
+            input.push(0);
+        }
+        input.push(0);
+        envs.push(env)
+    }
+    (names, input)
+}
+
 impl PkgbuildsOwned {
-    fn from_dumped_pkgbuilds<P, I, S> (
-        dir: P, list: I, actual_identity: IdentityActual
+    /// Parse the PKGBUILDs in dir with names in list, each sourced with its
+    /// env, the entries are in the same order as the list. Arch-specific
+    /// arrays are those of the target arch if set, otherwise those of the
+    /// host arch
+    pub(super) fn from_dumped_pkgbuilds<'a, P, I, S> (
+        dir: P, list: I, actual_identity: &IdentityActual, arch: Option<&str>
     ) -> Result<Self>
     where
         P: AsRef<Path>,
-        I: IntoIterator<Item = S>,
+        I: IntoIterator<Item = (S, &'a BTreeMap<String, String>)>,
         S: AsRef<str>,
     {
-        let mut write_buffer = vec![];
-        for pkgbuild_name in list.into_iter() {
-            for byte in pkgbuild_name.as_ref().as_bytes() {
-                write_buffer.push(*byte)
-            }
-            write_buffer.push(b'\n');
-        }
-        let mut child = match actual_identity.set_root_drop_command(
-            Command::new("/bin/bash")
+        let (names, write_buffer) = parser_input(list);
+        let mut command = Command::new("/bin/bash");
+        actual_identity.set_root_drop_command(
+            command
                 .arg("-c")
                 .arg(include_str!("../../scripts/parse_pkgbuilds.bash"))
                 .arg("PKGBUILD Parser")
-                .current_dir(dir.as_ref())
-                .stdin(Stdio::piped())
-                .stdout(Stdio::piped()))
-                .stderr(Stdio::null())
+                .args(arch)
+                .current_dir(dir.as_ref()));
+        Self::from_parser(&mut command, &names, &write_buffer)
+    }
+
+    /// Run the parser, feeding it the input, and parse its output
+    fn from_parser(command: &mut Command, names: &[String], input: &[u8])
+        -> Result<Self>
+    {
+        let mut child = match command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
             .spawn()
         {
             Ok(child) => child,
             Err(e) => {
                 log::error!("Failed to spawn child to parse pkgbuilds: {}", e);
-                return Err(())
+                return Err(e.into())
             },
         };
         let mut child_in = match child.stdin.take() {
             Some(stdin) => stdin,
             None => {
-                log::error!("Failed to open stdin");
-                child.kill().expect("Failed to kill child");
-                return Err(())
+                log::error!("Failed to take child stdin");
+                if let Err(e) = child.kill() {
+                    log::error!("Failed to kill child: {}", e);
+                }
+                return Err(Error::ImpossibleLogic)
             },
         };
         let mut child_out = match child.stdout.take() {
             Some(stdout) => stdout,
             None => {
-                log::error!("Failed to open stdin");
-                child.kill().expect("Failed to kill child");
-                return Err(())
+                log::error!("Failed to take child stdout");
+                if let Err(e) = child.kill() {
+                    log::error!("Failed to kill child: {}", e);
+                }
+                return Err(Error::ImpossibleLogic)
             },
         };
+        // The parser writes much more than it reads, so the input must be
+        // written concurrently, or both pipes fill up and block each other
         let mut output = vec![];
-        let mut output_buffer = vec![0; libc::PIPE_BUF];
-        let mut written = 0;
-        let total = write_buffer.len();
-        while written < total {
-            let mut end = written + libc::PIPE_BUF;
-            if end > total {
-                end = total;
-            }
-            match child_in.write(&write_buffer[written..end]) {
-                Ok(written_this) => written += written_this,
-                Err(e) => {
-                    log::error!("Failed to write buffer to child: {}", e);
-                    child.kill().expect("Failed to kill child");
-                    return Err(())
-                },
-            }
-            match child_out.read(&mut output_buffer[..]) {
-                Ok(read_this) =>
-                    output.extend_from_slice(&output_buffer[0..read_this]),
-                Err(e) => {
-                    log::error!("Failed to read stdout child: {}", e);
-                    child.kill().expect("Failed to kill child");
-                    return Err(())
-                },
+        let (r_write, r_read) = std::thread::scope(|scope|{
+            let writer = scope.spawn(move||{
+                let r = child_in.write_all(input);
+                drop(child_in);
+                r
+            });
+            let r_read = child_out.read_to_end(&mut output);
+            (writer.join(), r_read)
+        });
+        let r_write = match r_write {
+            Ok(r) => r,
+            Err(_) => {
+                log::error!("Failed to join thread writing to parser");
+                if let Err(e) = child.kill() {
+                    log::error!("Failed to kill child: {}", e);
+                }
+                return Err(Error::ThreadFailure(None))
+            },
+        };
+        // A broken pipe means the parser quit early, judged by its status
+        match r_write {
+            Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => {
+                log::error!("Failed to write buffer to child: {}", e);
+                if let Err(e) = child.kill() {
+                    log::error!("Failed to kill child: {}", e);
+                }
+                return Err(e.into())
+            },
+            _ => (),
+        }
+        if let Err(e) = r_read {
+            log::error!("Failed to read stdout of child: {}", e);
+            if let Err(e) = child.kill() {
+                log::error!("Failed to kill child: {}", e);
             }
+            return Err(e.into())
         }
-        drop(child_in);
-        match child_out.read_to_end(&mut output) {
-            Ok(_) => (),
+        let status = match child.wait() {
+            Ok(status) => status,
             Err(e) => {
-                log::error!("Failed to read stdout child: {}", e);
-                child.kill().expect("Failed to kill child");
-                return Err(())
-            },
-        }
-        if child
-            .wait()
-            .or_else(|e|{
                 log::error!(
                     "Failed to wait for child parsing PKGBUILDs: {}", e);
-                Err(())
-            })?
-            .code()
-            .ok_or_else(||{
+                return Err(e.into())
+            },
+        };
+        match status.code() {
+            Some(0) => (),
+            Some(code) => {
+                // Each header is only written after its PKGBUILD is sourced
+                let parsed = output.split(|byte| *byte == b'\n').filter(
+                    |line| line == b"[PKGBUILD]").count();
+                log::error!("Parser bad return {} at PKGBUILD '{}'", code,
+                    names.get(parsed).map(String::as_str).unwrap_or_default());
+                return Err(broken(names, parsed))
+            },
+            None => {
                 log::error!("Failed to get return code from child parsing \
-                        PKGBUILD type")
-            })? != 0 {
-                log::error!("Reader bad return");
-                return Err(())
-            }
-        Ok(Self::from_borrowed(PkgbuildsBorrowed::from_parser_output(&output)?))
+                    PKGBUILDs");
+                return Err(Error::ImpossibleLogic)
+            },
+        }
+        let pkgbuilds = Self::from_borrowed(
+            PkgbuildsBorrowed::from_parser_output(&output, names)?);
+        // The parser stops at the first PKGBUILD it could not get through
+        if pkgbuilds.entries.len() != names.len() {
+            log::error!("Parsed {} PKGBUILDs but expected {}, PKGBUILD '{}' \
+                might be broken", pkgbuilds.entries.len(), names.len(),
+                names.get(pkgbuilds.entries.len()).map(String::as_str)
+                    .unwrap_or_default());
+            return Err(broken(names, pkgbuilds.entries.len()))
+        }
+        Ok(pkgbuilds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// A dir with stub makepkg libraries, and the given PKGBUILDs
+    fn workdir(name: &str, pkgbuilds: &[(String, String)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "arb-parse-test-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("library")).unwrap();
+        std::fs::write(dir.join("library/util.sh"), "\
+source_makepkg_config() { :; }
+").unwrap();
+        std::fs::write(dir.join("library/source.sh"), "\
+get_all_sources_for_arch() { declare -n a=\"$1\"; a=(\"${source[@]}\"); }
+get_filename() { echo \"${1##*/}\"; }
+get_protocol() { echo \"${1%%://*}\"; }
+get_url() { echo \"$1\"; }
+").unwrap();
+        for (pkgbuild, content) in pkgbuilds {
+            std::fs::write(dir.join(pkgbuild), content).unwrap();
+        }
+        dir
     }
-}
\ No newline at end of file
+
+    fn parse<'a, I>(dir: &Path, list: I) -> Result<PkgbuildsOwned>
+    where
+        I: IntoIterator<Item = (&'a String, &'a BTreeMap<String, String>)>,
+    {
+        let (names, write_buffer) = parser_input(list);
+        PkgbuildsOwned::from_parser(
+            Command::new("/bin/bash")
+                .arg("-c")
+                .arg(include_str!("../../scripts/parse_pkgbuilds.bash"))
+                .arg("PKGBUILD Parser")
+                .arg("x86_64")
+                .env("LIBRARY", dir.join("library"))
+                .current_dir(dir),
+            &names, &write_buffer)
+    }
+
+    #[test]
+    fn parse_many_pkgbuilds() {
+        const COUNT: usize = 3000;
+        let pkgbuilds: Vec<(String, String)> = (0..COUNT).map(|id|(
+            format!("pkgbuild-{:04}", id),
+            format!("\
+pkgname=pkg-{id}-${{SUFFIX}}
+arch=(x86_64)
+depends=(dep-a dep-b dep-c)
+source=(https://example.com/pkg-{id}/file.tar.gz)
+sha256sums=(SKIP)
+pkgver() {{ :; }}
+package() {{ depends=(dep-d); }}
+")
+        )).collect();
+        let dir = workdir("many", &pkgbuilds);
+        let envs: Vec<BTreeMap<String, String>> = (0..COUNT).map(|id|
+            [(String::from("SUFFIX"), format!("{:0>64}", id))].into()
+        ).collect();
+        let r = parse(&dir, pkgbuilds.iter().map(|(name, _)|name)
+            .zip(envs.iter()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let parsed = r.unwrap();
+        assert_eq!(parsed.entries.len(), COUNT);
+        for (id, entry) in parsed.entries.iter().enumerate() {
+            assert_eq!(entry.base, format!("pkg-{}-{:0>64}", id, id));
+            assert_eq!(entry.arches, ["x86_64"]);
+            assert_eq!(entry.deps, ["dep-a", "dep-b", "dep-c"]);
+            assert_eq!(entry.sources.len(), 1);
+            assert_eq!(entry.sha256sums, ["SKIP"]);
+            assert!(entry.pkgver_func);
+        }
+    }
+
+    #[test]
+    fn parse_shared_envs() {
+        let pkgbuilds: Vec<(String, String)> = (0..4).map(|id|(
+            format!("pkgbuild-{}", id),
+            String::from("pkgname=pkg-${SUFFIX}\n"),
+        )).collect();
+        let dir = workdir("shared", &pkgbuilds);
+        let a = BTreeMap::from([(String::from("SUFFIX"), String::from("a"))]);
+        let b = BTreeMap::from([(String::from("SUFFIX"), String::from("b"))]);
+        let empty = BTreeMap::new();
+        let envs = [&a, &b, &a, &empty];
+        let (_, input) = parser_input(pkgbuilds.iter()
+            .map(|(name, _)|name).zip(envs));
+        assert_eq!(input, b"pkgbuild-0\0SUFFIX=a\0\0pkgbuild-1\0SUFFIX=b\0\0\
+            pkgbuild-2\0#0\0pkgbuild-3\0\0");
+        let r = parse(&dir, pkgbuilds.iter().map(|(name, _)|name).zip(envs));
+        let _ = std::fs::remove_dir_all(&dir);
+        let bases: Vec<String> = r.unwrap().entries.into_iter()
+            .map(|entry|entry.base).collect();
+        assert_eq!(bases, ["pkg-a", "pkg-b", "pkg-a", "pkg-"]);
+    }
+
+    #[test]
+    fn parse_broken_pkgbuild() {
+        let pkgbuilds = [
+            (String::from("good"), String::from("pkgname=good\n")),
+            (String::from("bad"), String::from("pkgname=bad\nexit 1\n")),
+        ];
+        // Enough entries after the broken one to not fit in the pipe
+        let env = BTreeMap::from([(String::from("PAD"), "x".repeat(1024))]);
+        let dir = workdir("broken", &pkgbuilds);
+        let r = parse(&dir, pkgbuilds.iter().chain(
+            std::iter::repeat_n(&pkgbuilds[0], 1000))
+                .map(|(name, _)|(name, &env)));
+        let _ = std::fs::remove_dir_all(&dir);
+        assert!(matches!(r, Err(Error::BrokenPKGBUILDs(names))
+            if names == ["bad"]))
+    }
+}
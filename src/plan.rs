@@ -0,0 +1,131 @@
+// The plan of a run, i.e. what would be built and why, without building
+// anything. Printed as human text or as JSON for other tools
+
+use serde::Serialize;
+
+use crate::{
+        depend::layer_pkgbuilds,
+        error::{
+            Error,
+            Result
+        },
+        pkgbuild::PKGBUILDs,
+        state::{
+            Change,
+            StateDb,
+        },
+    };
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Action {
+    /// The pkgid is not built yet
+    Build,
+    /// The pkgid is already built
+    Built,
+    /// The pkgid failed all tries before, and would be skipped
+    Failed,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Entry {
+    pub(crate) base: String,
+    /// The target arch, not set for the host arch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) arch: Option<String>,
+    pub(crate) layer: usize,
+    pub(crate) action: Action,
+    /// The pkgid of the last successful build, if any
+    pub(crate) old_pkgid: Option<String>,
+    pub(crate) new_pkgid: String,
+    pub(crate) changes: Vec<Change>,
+    /// Deps from repos that would be installed, before excluding those
+    /// already in the base chroot
+    pub(crate) installs: Vec<String>,
+    /// Deps provided by our other PKGBUILDs
+    pub(crate) installs_local: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Plan {
+    pub(crate) entries: Vec<Entry>,
+}
+
+impl Plan {
+    /// Plan with PKGBUILDs whose pkgids are already filled, ordered by layer
+    pub(crate) fn new(pkgbuilds: &PKGBUILDs, state: &StateDb) -> Result<Self> {
+        let mut entries = vec![];
+        for (layer, pkgbuilds) in
+            layer_pkgbuilds(pkgbuilds)?.into_iter().enumerate()
+        {
+            for pkgbuild in pkgbuilds {
+                let action = if pkgbuild.need_build {
+                    Action::Build
+                } else if pkgbuild.built() {
+                    Action::Built
+                } else {
+                    Action::Failed
+                };
+                let (old_pkgid, changes) = match state.changes(pkgbuild) {
+                    Some((last, changes)) =>
+                        (Some(last.pkgid.clone()), changes),
+                    None => (None, vec![]),
+                };
+                let depends = pkgbuild.depends();
+                entries.push(Entry {
+                    base: pkgbuild.base.clone(),
+                    arch: pkgbuild.arch().map(String::from),
+                    layer,
+                    action,
+                    old_pkgid,
+                    new_pkgid: pkgbuild.pkgid.clone(),
+                    changes,
+                    installs: depends.needs.clone(),
+                    installs_local: depends.internal.clone(),
+                })
+            }
+        }
+        Ok(Self { entries })
+    }
+
+    pub(crate) fn print_text(&self) {
+        for entry in self.entries.iter() {
+            let action = match entry.action {
+                Action::Build => "build",
+                Action::Built => "up to date",
+                Action::Failed => "skip, failed all tries before",
+            };
+            match &entry.arch {
+                Some(arch) => println!("{} [{}] (layer {}): {}",
+                    entry.base, arch, entry.layer, action),
+                None => println!("{} (layer {}): {}",
+                    entry.base, entry.layer, action),
+            }
+            match &entry.old_pkgid {
+                Some(old) if old != &entry.new_pkgid =>
+                    println!("    pkgid: {} -> {}", old, entry.new_pkgid),
+                Some(_) => println!("    pkgid: {}", entry.new_pkgid),
+                None => println!("    pkgid: (none) -> {}", entry.new_pkgid),
+            }
+            for change in entry.changes.iter() {
+                println!("    {}", change)
+            }
+            if ! entry.installs.is_empty() {
+                println!("    install: {}", entry.installs.join(" "))
+            }
+            if ! entry.installs_local.is_empty() {
+                println!("    install local: {}",
+                    entry.installs_local.join(" "))
+            }
+        }
+    }
+
+    pub(crate) fn print_json(&self) -> Result<()> {
+        let json = serde_json::to_string_pretty(self).map_err(|e|{
+            log::error!("Failed to serialize plan: {}", e);
+            Error::ImpossibleLogic
+        })?;
+        println!("{}", json);
+        Ok(())
+    }
+}
@@ -0,0 +1,619 @@
+// Publish packages and repo DBs to a GitHub release through the REST API,
+// the release is kept a mirror of `latest` of an arch: new and changed files
+// are uploaded, and assets of packages cleaned locally are deleted. Other
+// assets of the release are never touched
+
+use std::{
+        collections::HashMap,
+        fs::File,
+        path::{
+            Path,
+            PathBuf,
+        },
+    };
+
+use serde::Deserialize;
+
+use crate::{
+        config::Publish as PublishConfig,
+        error::{
+            Error,
+            Result
+        },
+        filesystem::pkgs_dir,
+    };
+
+/// Replaced in the tag by the arch published
+const ARCH_PLACEHOLDER: &str = "{arch}";
+
+#[derive(Deserialize)]
+struct Release {
+    id: u64,
+    upload_url: String,
+}
+
+#[derive(Deserialize)]
+struct Asset {
+    id: u64,
+    name: String,
+    size: u64,
+}
+
+struct Client<'a> {
+    agent: ureq::Agent,
+    token: String,
+    config: &'a PublishConfig,
+    /// The tag with `{arch}` replaced
+    tag: String,
+}
+
+/// The name GitHub would store a file as, it replaces special characters,
+/// e.g. the `:` of epochs, with `.`
+fn asset_name(name: &str) -> String {
+    name.chars().map(|c|
+        if c.is_ascii_alphanumeric() || "._+-".contains(c) {
+            c
+        } else {
+            '.'
+        }).collect()
+}
+
+/// Whether an asset is one we publish, a package or a repo DB, or their
+/// signatures
+fn managed(name: &str, repo: Option<&str>) -> bool {
+    let name = name.strip_suffix(".sig").unwrap_or(name);
+    if name.contains(".pkg.tar") {
+        return true
+    }
+    let repo = match repo {
+        Some(repo) => asset_name(repo),
+        None => return false,
+    };
+    ["db", "files"].iter().any(|kind|{
+        let db = format!("{}.{}", repo, kind);
+        name == db || name.starts_with(&format!("{}.tar", db))
+    })
+}
+
+impl<'a> Client<'a> {
+    const TOKEN_ENVS: [&'static str; 2] = ["ARB_GITHUB_TOKEN", "GITHUB_TOKEN"];
+    const PER_PAGE: usize = 100;
+
+    fn new(config: &'a PublishConfig, tag: String) -> Result<Self> {
+        let token = match Self::TOKEN_ENVS.iter().find_map(
+            |env|std::env::var(env).ok())
+        {
+            Some(token) => token,
+            None => {
+                log::error!("No GitHub token to publish with, set one of \
+                    environment variables {:?}", Self::TOKEN_ENVS);
+                return Err(Error::InvalidConfig)
+            },
+        };
+        Ok(Self { agent: ureq::agent(), token, config, tag })
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/repos/{}/{}/{}", self.config.api.trim_end_matches('/'),
+            self.config.owner, self.config.repo, path)
+    }
+
+    fn request(&self, method: &str, url: &str) -> ureq::Request {
+        self.agent.request(method, url)
+            .set("Accept", "application/vnd.github+json")
+            .set("Authorization", &format!("Bearer {}", self.token))
+            .set("X-GitHub-Api-Version", "2022-11-28")
+    }
+
+    fn release(&self) -> Result<Release> {
+        let url = self.url(&format!("releases/tags/{}", self.tag));
+        let response = match self.request("GET", &url).call() {
+            Ok(response) => response,
+            Err(ureq::Error::Status(404, _)) => {
+                log::info!("Release '{}' does not exist, creating it",
+                    self.tag);
+                match self.request("POST", &self.url("releases"))
+                    .send_json(serde_json::json!({
+                        "tag_name": self.tag,
+                        "name": self.tag,
+                    }))
+                {
+                    Ok(response) => response,
+                    Err(e) => {
+                        log::error!("Failed to create release '{}': {}",
+                            self.tag, e);
+                        return Err(Error::UreqError(e.into()))
+                    },
+                }
+            },
+            Err(e) => {
+                log::error!("Failed to get release '{}': {}",
+                    self.tag, e);
+                return Err(Error::UreqError(e.into()))
+            },
+        };
+        response.into_json().map_err(|e|{
+            log::error!("Failed to parse release: {}", e);
+            Error::IoError(e)
+        })
+    }
+
+    fn assets(&self, release: &Release) -> Result<Vec<Asset>> {
+        let url = self.url(&format!("releases/{}/assets", release.id));
+        let mut assets = vec![];
+        for page in 1.. {
+            let mut page: Vec<Asset> = match self.request("GET", &url)
+                .query("per_page", &Self::PER_PAGE.to_string())
+                .query("page", &page.to_string())
+                .call()
+            {
+                Ok(response) => response.into_json().map_err(|e|{
+                    log::error!("Failed to parse assets: {}", e);
+                    Error::IoError(e)
+                })?,
+                Err(e) => {
+                    log::error!("Failed to list assets of release '{}': {}",
+                        self.tag, e);
+                    return Err(Error::UreqError(e.into()))
+                },
+            };
+            let last = page.len() < Self::PER_PAGE;
+            assets.append(&mut page);
+            if last {
+                break
+            }
+        }
+        Ok(assets)
+    }
+
+    fn delete(&self, asset: &Asset) -> Result<()> {
+        log::info!("Deleting asset '{}'", asset.name);
+        let url = self.url(&format!("releases/assets/{}", asset.id));
+        match self.request("DELETE", &url).call() {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                log::error!("Failed to delete asset '{}': {}", asset.name, e);
+                Err(Error::UreqError(e.into()))
+            },
+        }
+    }
+
+    fn upload(&self, release: &Release, name: &str, path: &Path, size: u64)
+        -> Result<()>
+    {
+        log::info!("Uploading '{}'", path.display());
+        let file = File::open(path).map_err(|e|{
+            log::error!("Failed to open '{}' to upload: {}", path.display(), e);
+            Error::IoError(e)
+        })?;
+        // The URL is a template like `https://uploads.github.com/repos/
+        // [owner]/[repo]/releases/[id]/assets{?name,label}`
+        let url = match release.upload_url.split_once('{') {
+            Some((url, _)) => url,
+            None => &release.upload_url,
+        };
+        match self.request("POST", url)
+            .query("name", name)
+            .set("Content-Type", "application/octet-stream")
+            .set("Content-Length", &size.to_string())
+            .send(file)
+        {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                log::error!("Failed to upload '{}': {}", path.display(), e);
+                Err(Error::UreqError(e.into()))
+            },
+        }
+    }
+}
+
+/// Files to publish by their asset names, with their paths and sizes
+fn local_files(dir: &Path) -> Result<HashMap<String, (PathBuf, u64)>> {
+    let reader = std::fs::read_dir(dir).map_err(|e|{
+        log::error!("Failed to read dir '{}': {}", dir.display(), e);
+        Error::IoError(e)
+    })?;
+    let mut files = HashMap::new();
+    for entry in reader {
+        let entry = entry.map_err(|e|{
+            log::error!("Failed to read entry from '{}': {}",
+                dir.display(), e);
+            Error::IoError(e)
+        })?;
+        let path = entry.path();
+        // Follow the links
+        let size = match path.metadata() {
+            Ok(metadata) if metadata.is_file() => metadata.len(),
+            Ok(_) => continue,
+            Err(e) => {
+                log::warn!("Skipped '{}' as it could not be read: {}",
+                    path.display(), e);
+                continue
+            },
+        };
+        files.insert(asset_name(&entry.file_name().to_string_lossy()),
+            (path, size));
+    }
+    Ok(files)
+}
+
+/// Make the release of each arch the same as its `latest`: files in its
+/// `updated`, or missing from the release, or with a different size are
+/// uploaded again. If prune, assets of packages and DBs of the repo not in
+/// `latest` are deleted
+pub(crate) fn publish(
+    config: &PublishConfig, prune: bool, repo: Option<&str>,
+    arches: &[Option<&str>]
+) -> Result<()>
+{
+    if arches.len() > 1 && ! config.tag.contains(ARCH_PLACEHOLDER) {
+        log::error!("Publish tag '{}' must contain '{}' to publish multiple \
+            arches", config.tag, ARCH_PLACEHOLDER);
+        return Err(Error::InvalidConfig)
+    }
+    for arch in arches {
+        publish_arch(config, prune, repo, *arch)?
+    }
+    Ok(())
+}
+
+fn publish_arch(
+    config: &PublishConfig, prune: bool, repo: Option<&str>,
+    arch: Option<&str>
+) -> Result<()>
+{
+    let tag = config.tag.replace(ARCH_PLACEHOLDER,
+        arch.unwrap_or(std::env::consts::ARCH));
+    publish_pkgs(config, prune, repo, tag, &pkgs_dir(arch))
+}
+
+fn publish_pkgs(
+    config: &PublishConfig, prune: bool, repo: Option<&str>, tag: String,
+    pkgs: &Path
+) -> Result<()>
+{
+    log::info!("Publishing to release '{}' of {}/{}",
+        tag, config.owner, config.repo);
+    let latest = local_files(&pkgs.join("latest"))?;
+    let updated = local_files(&pkgs.join("updated"))?;
+    let client = Client::new(config, tag)?;
+    let release = client.release()?;
+    let mut assets: HashMap<String, Asset> = client.assets(&release)?
+        .into_iter().map(|asset|(asset.name.clone(), asset)).collect();
+    let mut names: Vec<&String> = latest.keys().collect();
+    names.sort_unstable();
+    let mut uploaded = 0;
+    for name in names {
+        let (path, size) = &latest[name];
+        if let Some(asset) = assets.remove(name) {
+            if asset.size == *size && ! updated.contains_key(name) {
+                continue
+            }
+            client.delete(&asset)?
+        }
+        client.upload(&release, name, path, *size)?;
+        uploaded += 1
+    }
+    let mut deleted = 0;
+    if prune {
+        for asset in assets.values().filter(|asset|managed(&asset.name, repo)) {
+            client.delete(asset)?;
+            deleted += 1
+        }
+    }
+    log::info!("Published to release '{}', uploaded {} files, deleted {} \
+        assets", client.tag, uploaded, deleted);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+            io::{
+                BufRead,
+                BufReader,
+                Read,
+                Write,
+            },
+            net::{
+                TcpListener,
+                TcpStream,
+            },
+            path::PathBuf,
+            sync::{
+                Arc,
+                Mutex,
+            },
+        };
+
+    use super::*;
+
+    struct MockAsset {
+        id: u64,
+        name: String,
+        size: u64,
+    }
+
+    /// A release on the mock server, with a log of the changes made to it
+    #[derive(Default)]
+    struct MockRelease {
+        exists: bool,
+        assets: Vec<MockAsset>,
+        next_id: u64,
+        log: Vec<String>,
+    }
+
+    impl MockRelease {
+        fn add(&mut self, name: &str, size: u64) {
+            self.next_id += 1;
+            self.assets.push(MockAsset {
+                id: self.next_id, name: name.into(), size });
+        }
+
+        fn has(&self, name: &str) -> bool {
+            self.assets.iter().any(|asset|asset.name == name)
+        }
+
+        fn logged(&self, entry: &str) -> bool {
+            self.log.iter().any(|logged|logged == entry)
+        }
+    }
+
+    fn query<'a>(query: &'a str, key: &str) -> Option<&'a str> {
+        query.split('&').find_map(|pair|match pair.split_once('=') {
+            Some((k, v)) if k == key => Some(v),
+            _ => None,
+        })
+    }
+
+    fn read_body(reader: &mut BufReader<TcpStream>, headers: &[String])
+        -> Vec<u8>
+    {
+        let header = |name: &str| headers.iter().find_map(|line|{
+            let (key, value) = line.split_once(':')?;
+            key.eq_ignore_ascii_case(name).then(||value.trim().to_string())
+        });
+        let mut body = vec![];
+        if let Some(length) = header("Content-Length") {
+            body.resize(length.parse().unwrap(), 0);
+            reader.read_exact(&mut body).unwrap();
+        } else if header("Transfer-Encoding").as_deref() == Some("chunked") {
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let size = usize::from_str_radix(line.trim(), 16).unwrap();
+                let mut chunk = vec![0; size + 2];
+                reader.read_exact(&mut chunk).unwrap();
+                if size == 0 {
+                    break
+                }
+                body.extend_from_slice(&chunk[..size]);
+            }
+        }
+        body
+    }
+
+    fn handle(release: &Mutex<MockRelease>, stream: TcpStream, base: &str) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let mut parts = line.split_whitespace();
+        let method = parts.next().unwrap_or_default().to_string();
+        let target = parts.next().unwrap_or_default().to_string();
+        let mut headers = vec![];
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let line = line.trim_end();
+            if line.is_empty() {
+                break
+            }
+            headers.push(line.to_string())
+        }
+        let body = read_body(&mut reader, &headers);
+        let (path, query_str) = target.split_once('?')
+            .unwrap_or((&target, ""));
+        let release_json = serde_json::json!({
+            "id": 1,
+            "upload_url": format!("{}/upload/1{{?name,label}}", base),
+        }).to_string();
+        let mut release = release.lock().unwrap();
+        let (status, response) = match (method.as_str(), path) {
+            ("GET", "/repos/owner/repo/releases/tags/tag") =>
+                if release.exists {
+                    (200, release_json)
+                } else {
+                    (404, String::from("{}"))
+                },
+            ("POST", "/repos/owner/repo/releases") => {
+                release.exists = true;
+                release.log.push(String::from("CREATE"));
+                (201, release_json)
+            },
+            ("GET", "/repos/owner/repo/releases/1/assets") => {
+                let page: usize = query(query_str, "page").unwrap().parse()
+                    .unwrap();
+                let per_page: usize = query(query_str, "per_page").unwrap()
+                    .parse().unwrap();
+                release.log.push(format!("PAGE {}", page));
+                let assets: Vec<serde_json::Value> = release.assets.iter()
+                    .skip((page - 1) * per_page).take(per_page)
+                    .map(|asset|serde_json::json!({
+                        "id": asset.id,
+                        "name": asset.name,
+                        "size": asset.size,
+                    })).collect();
+                (200, serde_json::Value::from(assets).to_string())
+            },
+            ("DELETE", path) if path.starts_with(
+                "/repos/owner/repo/releases/assets/") =>
+            {
+                let id: u64 = path.rsplit('/').next().unwrap().parse()
+                    .unwrap();
+                match release.assets.iter().position(|asset|asset.id == id) {
+                    Some(index) => {
+                        let asset = release.assets.remove(index);
+                        release.log.push(format!("DELETE {}", asset.name));
+                        (204, String::new())
+                    },
+                    None => (404, String::from("{}")),
+                }
+            },
+            ("POST", "/upload/1") => {
+                let name = query(query_str, "name").unwrap().to_string();
+                if release.has(&name) {
+                    (422, String::from("{}"))
+                } else {
+                    release.add(&name, body.len() as u64);
+                    release.log.push(format!("UPLOAD {}", name));
+                    (201, String::from("{}"))
+                }
+            },
+            _ => (404, String::from("{}")),
+        };
+        let mut stream = stream;
+        write!(stream, "HTTP/1.1 {} Mock\r\nContent-Type: application/json\
+            \r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status, response.len(), response).unwrap();
+    }
+
+    /// Serve the release in the background, returns the base URL
+    fn serve(release: Arc<Mutex<MockRelease>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let base_thread = base.clone();
+        std::thread::spawn(move||{
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => handle(&release, stream, &base_thread),
+                    Err(_) => break,
+                }
+            }
+        });
+        base
+    }
+
+    /// A pkgs dir with files of the given names and sizes in `latest`, and
+    /// those in `updated` linked there as well
+    fn pkgs(name: &str, latest: &[(&str, usize)], updated: &[&str])
+        -> PathBuf
+    {
+        let pkgs = std::env::temp_dir().join(format!(
+            "arb-publish-test-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&pkgs);
+        for dir in ["latest", "updated"] {
+            std::fs::create_dir_all(pkgs.join(dir)).unwrap();
+        }
+        for (file, size) in latest {
+            std::fs::write(pkgs.join("latest").join(file), vec![0; *size])
+                .unwrap();
+        }
+        for file in updated {
+            std::os::unix::fs::symlink(PathBuf::from("../latest").join(file),
+                pkgs.join("updated").join(file)).unwrap();
+        }
+        pkgs
+    }
+
+    fn publish_mock(
+        release: &Arc<Mutex<MockRelease>>, prune: bool, pkgs: &Path
+    ) -> Result<()>
+    {
+        std::env::set_var("ARB_GITHUB_TOKEN", "token");
+        let config = PublishConfig {
+            api: serve(release.clone()),
+            owner: String::from("owner"),
+            repo: String::from("repo"),
+            tag: String::from("tag"),
+        };
+        let r = publish_pkgs(&config, prune, Some("myrepo"),
+            String::from("tag"), pkgs);
+        let _ = std::fs::remove_dir_all(pkgs);
+        r
+    }
+
+    #[test]
+    fn publish_creates_release() {
+        let release = Arc::new(Mutex::new(MockRelease::default()));
+        let pkgs = pkgs("create", &[
+            ("foo-1-1-x86_64.pkg.tar.zst", 10),
+            ("foo-1-1-x86_64.pkg.tar.zst.sig", 2),
+            ("myrepo.db", 3),
+        ], &[]);
+        publish_mock(&release, true, &pkgs).unwrap();
+        let release = release.lock().unwrap();
+        assert!(release.logged("CREATE"));
+        assert_eq!(release.assets.len(), 3);
+        assert!(release.has("foo-1-1-x86_64.pkg.tar.zst.sig"));
+        assert!(release.has("myrepo.db"));
+    }
+
+    #[test]
+    fn publish_syncs_and_prunes() {
+        let mut release = MockRelease { exists: true, ..Default::default() };
+        // More than a page, never ours to delete
+        for i in 0..150 {
+            release.add(&format!("other-{}.txt", i), 1)
+        }
+        release.add("same-1-1-x86_64.pkg.tar.zst", 10);
+        release.add("changed-1-1-x86_64.pkg.tar.zst", 10);
+        release.add("updated-1-1-x86_64.pkg.tar.zst", 10);
+        release.add("removed-1-1-x86_64.pkg.tar.zst", 10);
+        release.add("removed-1-1-x86_64.pkg.tar.zst.sig", 2);
+        release.add("myrepo.db.tar.zst", 3);
+        release.add("otherrepo.db", 3);
+        let release = Arc::new(Mutex::new(release));
+        let pkgs = pkgs("sync", &[
+            ("same-1-1-x86_64.pkg.tar.zst", 10),
+            ("changed-1-1-x86_64.pkg.tar.zst", 11),
+            ("updated-1-1-x86_64.pkg.tar.zst", 10),
+            ("new-1-1-x86_64.pkg.tar.zst", 10),
+        ], &["updated-1-1-x86_64.pkg.tar.zst"]);
+        publish_mock(&release, true, &pkgs).unwrap();
+        let release = release.lock().unwrap();
+        assert!(! release.logged("CREATE"));
+        assert!(release.logged("PAGE 1") && release.logged("PAGE 2"));
+        assert!(! release.logged("UPLOAD same-1-1-x86_64.pkg.tar.zst"));
+        for name in [
+            "changed-1-1-x86_64.pkg.tar.zst",
+            "updated-1-1-x86_64.pkg.tar.zst",
+        ] {
+            assert!(release.logged(&format!("DELETE {}", name)));
+            assert!(release.logged(&format!("UPLOAD {}", name)));
+        }
+        assert!(release.logged("UPLOAD new-1-1-x86_64.pkg.tar.zst"));
+        for name in [
+            "removed-1-1-x86_64.pkg.tar.zst",
+            "removed-1-1-x86_64.pkg.tar.zst.sig",
+            "myrepo.db.tar.zst",
+        ] {
+            assert!(! release.has(name));
+        }
+        assert!(release.has("otherrepo.db"));
+        assert!(release.has("other-149.txt"));
+        assert_eq!(release.assets.len(), 150 + 5);
+    }
+
+    #[test]
+    fn publish_keeps_without_prune() {
+        let mut release = MockRelease { exists: true, ..Default::default() };
+        release.add("removed-1-1-x86_64.pkg.tar.zst", 10);
+        let release = Arc::new(Mutex::new(release));
+        let pkgs = pkgs("keep", &[("foo-1-1-x86_64.pkg.tar.zst", 10)], &[]);
+        publish_mock(&release, false, &pkgs).unwrap();
+        let release = release.lock().unwrap();
+        assert!(release.has("removed-1-1-x86_64.pkg.tar.zst"));
+        assert!(release.logged("UPLOAD foo-1-1-x86_64.pkg.tar.zst"));
+    }
+
+    #[test]
+    fn managed_assets() {
+        assert!(managed("foo-1-1-any.pkg.tar.zst", None));
+        assert!(managed("foo-1-1-any.pkg.tar.xz.sig", None));
+        assert!(managed("myrepo.files.tar.zst.sig", Some("myrepo")));
+        assert!(managed("myrepo.db", Some("myrepo")));
+        assert!(! managed("myrepo.db", None));
+        assert!(! managed("myrepo.dbx", Some("myrepo")));
+        assert!(! managed("README.md", Some("myrepo")));
+    }
+}
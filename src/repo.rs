@@ -0,0 +1,252 @@
+// Native replacement of repo-add, the DBs are generated from `latest` and
+// updated incrementally from `updated`, both in the pkgs dir of each arch
+mod compress;
+mod db;
+mod package;
+
+use std::{
+        collections::HashSet,
+        fs::{
+            read_dir,
+            remove_file,
+        },
+        path::{
+            Path,
+            PathBuf,
+        },
+    };
+
+use crate::{
+        config::{
+            Repo as RepoConfig,
+            RepoCompression,
+        },
+        error::{
+            Error,
+            Result
+        },
+        filesystem::{
+            create_dir_allow_existing,
+            symlink_force,
+        },
+        identity::IdentityActual,
+        sign::Signer,
+    };
+
+use compress::Decoder;
+use package::Package;
+
+fn is_package(name: &str) -> bool {
+    name.contains(".pkg.tar") && ! name.ends_with(".sig")
+}
+
+/// Whether packages with the makepkg PKGEXT could be added to our repo DBs
+pub(crate) fn supports_pkgext(pkgext: &str) -> bool {
+    pkgext.starts_with(".pkg.tar") && Decoder::supports(pkgext)
+}
+
+/// Whether the package could be read, warn if not
+fn readable(name: &str) -> bool {
+    if Decoder::supports(name) {
+        true
+    } else {
+        log::warn!("Skipped package '{}' with unsupported compression", name);
+        false
+    }
+}
+
+pub(crate) fn list_packages<P: AsRef<Path>>(dir: P) -> Result<Vec<String>> {
+    let reader = match read_dir(&dir) {
+        Ok(reader) => reader,
+        Err(e) => {
+            log::error!("Failed to read dir '{}': {}",
+                dir.as_ref().display(), e);
+            return Err(Error::IoError(e))
+        },
+    };
+    let mut packages = vec![];
+    for entry in reader {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                log::error!("Failed to read entry from '{}': {}",
+                    dir.as_ref().display(), e);
+                return Err(Error::IoError(e))
+            },
+        };
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if is_package(&name) {
+            packages.push(name)
+        }
+    }
+    packages.sort_unstable();
+    Ok(packages)
+}
+
+struct RepoDbs {
+    db: PathBuf,
+    files: PathBuf,
+}
+
+impl RepoDbs {
+    /// DBs in `repo` of the pkgs dir
+    fn new(config: &RepoConfig, pkgs: &Path) -> Self {
+        let suffix = compress::Encoder::suffix(&config.compression);
+        let dir = pkgs.join("repo");
+        Self {
+            db: dir.join(format!("{}.db.{}", config.name, suffix)),
+            files: dir.join(format!("{}.files.{}", config.name, suffix)),
+        }
+    }
+
+    fn signed(&self) -> bool {
+        [&self.db, &self.files].iter().all(|path|{
+            let mut sig = path.as_os_str().to_owned();
+            sig.push(".sig");
+            Path::new(&sig).exists()
+        })
+    }
+
+    fn sign(&self, actual_identity: &IdentityActual, signer: &Signer)
+        -> Result<()>
+    {
+        for path in [&self.db, &self.files] {
+            let mut sig = path.as_os_str().to_owned();
+            sig.push(".sig");
+            let sig = PathBuf::from(sig);
+            if sig.exists() {
+                if let Err(e) = remove_file(&sig) {
+                    log::error!("Failed to remove old signature '{}': {}",
+                        sig.display(), e);
+                    return Err(Error::IoError(e))
+                }
+            }
+            signer.sign_file(actual_identity, path)?;
+        }
+        Ok(())
+    }
+
+    /// Link the DBs and their signatures into `[dir]` next to `repo`, with
+    /// both the full name and the short name pacman would look up
+    fn link<P: AsRef<Path>>(&self, config: &RepoConfig, dir: P) -> Result<()>
+    {
+        let rel = PathBuf::from("../repo");
+        for (path, short) in [
+            (&self.db, format!("{}.db", config.name)),
+            (&self.files, format!("{}.files", config.name))]
+        {
+            let name = match path.file_name() {
+                Some(name) => name.to_owned(),
+                None => return Err(Error::ImpossibleLogic),
+            };
+            let mut name_sig = name.clone();
+            name_sig.push(".sig");
+            let mut links = vec![
+                (rel.join(&name), dir.as_ref().join(&name)),
+                (rel.join(&name), dir.as_ref().join(&short))];
+            if path.with_file_name(&name_sig).exists() {
+                links.push((rel.join(&name_sig), dir.as_ref().join(&name_sig)));
+                links.push((rel.join(&name_sig),
+                    dir.as_ref().join(format!("{}.sig", short))));
+            }
+            for (original, link) in links {
+                symlink_force(&original, &link)?
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Update the repo DBs in the pkgs dir from its `latest`, only packages in its
+/// `updated` or missing from the existing DB are parsed again
+pub(crate) fn update(
+    config: &RepoConfig, actual_identity: &IdentityActual,
+    sign: Option<&Signer>, pkgs: &Path
+) -> Result<()>
+{
+    log::info!("Updating repo DB '{}' in '{}'", config.name, pkgs.display());
+    let dbs = RepoDbs::new(config, pkgs);
+    create_dir_allow_existing(pkgs.join("repo"))?;
+    let latest_dir = pkgs.join("latest");
+    let updated_dir = pkgs.join("updated");
+    let latest = list_packages(&latest_dir)?;
+    let updated: HashSet<String> =
+        list_packages(&updated_dir)?.into_iter().collect();
+    let mut existing = if dbs.files.exists() {
+        match db::read_files_db(&dbs.files) {
+            Ok(existing) => existing,
+            Err(e) => {
+                log::warn!("Failed to read existing repo DB, regenerating from \
+                    scratch: {}", e);
+                vec![]
+            },
+        }
+    } else {
+        vec![]
+    };
+    let existing_count = existing.len();
+    let mut packages = vec![];
+    let mut changed = false;
+    for name in latest.iter().filter(|name|readable(name)) {
+        let reused = if updated.contains(name) {
+            None
+        } else {
+            existing.iter().position(|package|&package.filename == name)
+        };
+        match reused {
+            Some(id) => packages.push(existing.swap_remove(id)),
+            None => {
+                packages.push(Package::from_file(
+                    &latest_dir.join(name))?);
+                changed = true
+            },
+        }
+    }
+    if packages.len() != existing_count {
+        changed = true
+    }
+    if changed || ! dbs.db.exists() || (sign.is_some() && ! dbs.signed()) {
+        packages.sort_unstable_by(|a, b|a.dir.cmp(&b.dir));
+        db::write_db(&dbs.db, &packages, &config.compression, false)?;
+        db::write_db(&dbs.files, &packages, &config.compression, true)?;
+        if let Some(signer) = sign {
+            dbs.sign(actual_identity, signer)?
+        }
+        dbs.link(config, &updated_dir)?;
+        log::info!("Updated repo DB '{}' with {} packages",
+            config.name, packages.len());
+    } else {
+        log::info!("Repo DB '{}' is already up to date", config.name);
+    }
+    dbs.link(config, &latest_dir)
+}
+
+/// Create a repo at `dir` only for pacman to install the packages in pkgdirs
+/// from, unsigned and without the files DB. The packages are only symlinked,
+/// the returned DB should be put into the sync DBs directly
+pub(crate) fn create_local(dir: &Path, name: &str, pkgdirs: &[PathBuf])
+    -> Result<PathBuf>
+{
+    let mut packages = vec![];
+    for pkgdir in pkgdirs.iter() {
+        let pkgdir = pkgdir.canonicalize().map_err(|e|{
+            log::error!("Failed to canonicalize pkgdir '{}': {}",
+                pkgdir.display(), e);
+            Error::IoError(e)
+        })?;
+        for name in list_packages(&pkgdir)? {
+            if ! readable(&name) {
+                continue
+            }
+            let original = pkgdir.join(&name);
+            symlink_force(&original, dir.join(&name))?;
+            packages.push(Package::from_file(&original)?)
+        }
+    }
+    packages.sort_unstable_by(|a, b|a.dir.cmp(&b.dir));
+    let db = dir.join(format!("{}.db", name));
+    db::write_db(&db, &packages, &RepoCompression::None, false)?;
+    log::info!("Created local repo '{}' with {} packages",
+        name, packages.len());
+    Ok(db)
+}
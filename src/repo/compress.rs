@@ -0,0 +1,143 @@
+use std::{
+        fs::File,
+        io::{
+            BufReader,
+            Read,
+            Write,
+        },
+        path::Path,
+    };
+
+use crate::{
+        config::RepoCompression,
+        error::{
+            Error,
+            Result
+        },
+    };
+
+pub(super) enum Decoder {
+    None (BufReader<File>),
+    Gz (flate2::read::GzDecoder<BufReader<File>>),
+    Xz (xz2::read::XzDecoder<BufReader<File>>),
+    Zst (zstd::Decoder<'static, BufReader<File>>),
+}
+
+/// The compression suffixes we could decode, a subset of those makepkg could
+/// produce
+const SUFFIXES: [&str; 4] = [".tar", ".gz", ".xz", ".zst"];
+
+impl Decoder {
+    pub(super) fn supports(name: &str) -> bool {
+        SUFFIXES.iter().any(|suffix|name.ends_with(suffix))
+    }
+
+    /// Decide the compression by the suffix, like how pacman and makepkg
+    /// would do
+    pub(super) fn from_path(path: &Path) -> Result<Self> {
+        let file = File::open(path).map_err(|e|{
+            log::error!("Failed to open '{}': {}", path.display(), e);
+            Error::IoError(e)
+        })?;
+        let reader = BufReader::new(file);
+        let name = path.to_string_lossy();
+        if name.ends_with(".tar") {
+            Ok(Self::None(reader))
+        } else if name.ends_with(".gz") {
+            Ok(Self::Gz(flate2::read::GzDecoder::new(reader)))
+        } else if name.ends_with(".xz") {
+            Ok(Self::Xz(xz2::read::XzDecoder::new(reader)))
+        } else if name.ends_with(".zst") {
+            match zstd::Decoder::with_buffer(reader) {
+                Ok(decoder) => Ok(Self::Zst(decoder)),
+                Err(e) => {
+                    log::error!("Failed to create zstd decoder for '{}': {}",
+                        path.display(), e);
+                    Err(Error::IoError(e))
+                },
+            }
+        } else {
+            log::error!("Unsupported compression for '{}'", path.display());
+            Err(Error::InvalidConfig)
+        }
+    }
+}
+
+impl Read for Decoder {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Self::None(reader) => reader.read(buf),
+            Self::Gz(reader) => reader.read(buf),
+            Self::Xz(reader) => reader.read(buf),
+            Self::Zst(reader) => reader.read(buf),
+        }
+    }
+}
+
+pub(super) enum Encoder {
+    None (File),
+    Gz (flate2::write::GzEncoder<File>),
+    Xz (xz2::write::XzEncoder<File>),
+    Zst (zstd::Encoder<'static, File>),
+}
+
+impl Encoder {
+    pub(super) fn new(file: File, compression: &RepoCompression)
+        -> Result<Self>
+    {
+        Ok(match compression {
+            RepoCompression::None => Self::None(file),
+            RepoCompression::Gz => Self::Gz(flate2::write::GzEncoder::new(
+                file, flate2::Compression::default())),
+            RepoCompression::Xz => Self::Xz(xz2::write::XzEncoder::new(
+                file, 6)),
+            RepoCompression::Zst => Self::Zst(
+                zstd::Encoder::new(file, 0).map_err(|e|{
+                    log::error!("Failed to create zstd encoder: {}", e);
+                    Error::IoError(e)
+                })?),
+        })
+    }
+
+    pub(super) fn finish(self) -> Result<()> {
+        let r = match self {
+            Self::None(mut file) => file.flush(),
+            Self::Gz(encoder) => encoder.finish().and(Ok(())),
+            Self::Xz(encoder) => encoder.finish().and(Ok(())),
+            Self::Zst(encoder) => encoder.finish().and(Ok(())),
+        };
+        r.map_err(|e|{
+            log::error!("Failed to finish compressed stream: {}", e);
+            Error::IoError(e)
+        })
+    }
+
+    pub(super) fn suffix(compression: &RepoCompression) -> &'static str {
+        match compression {
+            RepoCompression::None => "tar",
+            RepoCompression::Gz => "tar.gz",
+            RepoCompression::Xz => "tar.xz",
+            RepoCompression::Zst => "tar.zst",
+        }
+    }
+}
+
+impl Write for Encoder {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Self::None(writer) => writer.write(buf),
+            Self::Gz(writer) => writer.write(buf),
+            Self::Xz(writer) => writer.write(buf),
+            Self::Zst(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::None(writer) => writer.flush(),
+            Self::Gz(writer) => writer.flush(),
+            Self::Xz(writer) => writer.flush(),
+            Self::Zst(writer) => writer.flush(),
+        }
+    }
+}
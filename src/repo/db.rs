@@ -0,0 +1,157 @@
+use std::{
+        collections::HashMap,
+        fs::{
+            File,
+            rename,
+        },
+        io::Read,
+        path::Path,
+    };
+
+use crate::{
+        config::RepoCompression,
+        error::{
+            Error,
+            Result
+        },
+    };
+
+use super::{
+        compress::{
+            Decoder,
+            Encoder,
+        },
+        package::Package,
+    };
+
+fn filename_from_desc(desc: &str) -> Option<&str> {
+    let mut lines = desc.lines();
+    while let Some(line) = lines.next() {
+        if line == "%FILENAME%" {
+            return lines.next()
+        }
+    }
+    None
+}
+
+/// Read all entries from an existing files DB, which contains both the desc
+/// and the files of each package
+pub(super) fn read_files_db(path: &Path) -> Result<Vec<Package>> {
+    let mut archive = tar::Archive::new(Decoder::from_path(path)?);
+    let entries = archive.entries().map_err(|e|{
+        log::error!("Failed to read entries of DB '{}': {}", path.display(), e);
+        Error::IoError(e)
+    })?;
+    let mut descs: HashMap<String, String> = HashMap::new();
+    let mut files: HashMap<String, String> = HashMap::new();
+    for entry in entries {
+        let mut entry = entry.map_err(|e|{
+            log::error!("Failed to read entry of DB '{}': {}",
+                path.display(), e);
+            Error::IoError(e)
+        })?;
+        if ! entry.header().entry_type().is_file() {
+            continue
+        }
+        let entry_path = entry.path().map_err(|e|{
+            log::error!("Failed to get path of entry in DB '{}': {}",
+                path.display(), e);
+            Error::IoError(e)
+        })?.to_string_lossy().into_owned();
+        let (dir, name) = match entry_path.split_once('/') {
+            Some((dir, name)) => (dir.to_string(), name.to_string()),
+            None => continue,
+        };
+        let mut content = String::new();
+        entry.read_to_string(&mut content).map_err(|e|{
+            log::error!("Failed to read '{}' in DB '{}': {}",
+                entry_path, path.display(), e);
+            Error::IoError(e)
+        })?;
+        match name.as_str() {
+            "desc" => { descs.insert(dir, content); },
+            "files" => { files.insert(dir, content); },
+            _ => (),
+        }
+    }
+    let mut packages = vec![];
+    for (dir, desc) in descs {
+        let filename = match filename_from_desc(&desc) {
+            Some(filename) => filename.to_string(),
+            None => {
+                log::error!("Entry '{}' in DB '{}' has no filename",
+                    dir, path.display());
+                return Err(Error::IntegrityError)
+            },
+        };
+        let files = files.remove(&dir).unwrap_or_default();
+        packages.push(Package { filename, dir, desc, files })
+    }
+    Ok(packages)
+}
+
+fn append_file(
+    builder: &mut tar::Builder<Encoder>, path: &str, content: &str, mtime: u64
+) -> Result<()>
+{
+    let mut header = tar::Header::new_gnu();
+    header.set_size(content.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(mtime);
+    header.set_entry_type(tar::EntryType::Regular);
+    builder.append_data(&mut header, path, content.as_bytes()).map_err(|e|{
+        log::error!("Failed to append '{}' to DB: {}", path, e);
+        Error::IoError(e)
+    })
+}
+
+fn append_dir(builder: &mut tar::Builder<Encoder>, path: &str, mtime: u64)
+    -> Result<()>
+{
+    let mut header = tar::Header::new_gnu();
+    header.set_size(0);
+    header.set_mode(0o755);
+    header.set_mtime(mtime);
+    header.set_entry_type(tar::EntryType::Directory);
+    builder.append_data(&mut header, path, std::io::empty()).map_err(|e|{
+        log::error!("Failed to append '{}' to DB: {}", path, e);
+        Error::IoError(e)
+    })
+}
+
+/// Write the DB to a temp file then move it to the target path, so the
+/// existing DB is never left half-written
+pub(super) fn write_db(
+    path: &Path, packages: &[Package], compression: &RepoCompression,
+    with_files: bool
+) -> Result<()>
+{
+    let mut name_temp = path.as_os_str().to_owned();
+    name_temp.push(".temp");
+    let path_temp = Path::new(&name_temp);
+    let file = File::create(path_temp).map_err(|e|{
+        log::error!("Failed to create DB '{}': {}", path_temp.display(), e);
+        Error::IoError(e)
+    })?;
+    let mtime = time::OffsetDateTime::now_utc().unix_timestamp() as u64;
+    let mut builder = tar::Builder::new(Encoder::new(file, compression)?);
+    for package in packages.iter() {
+        append_dir(&mut builder, &format!("{}/", package.dir), mtime)?;
+        append_file(&mut builder, &format!("{}/desc", package.dir),
+            &package.desc, mtime)?;
+        if with_files {
+            append_file(&mut builder, &format!("{}/files", package.dir),
+                &package.files, mtime)?;
+        }
+    }
+    let encoder = builder.into_inner().map_err(|e|{
+        log::error!("Failed to finish DB '{}': {}", path_temp.display(), e);
+        Error::IoError(e)
+    })?;
+    encoder.finish()?;
+    rename(path_temp, path).map_err(|e|{
+        log::error!("Failed to move DB '{}' to '{}': {}",
+            path_temp.display(), path.display(), e);
+        Error::IoError(e)
+    })
+}
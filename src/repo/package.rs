@@ -0,0 +1,281 @@
+use std::{
+        fs::File,
+        io::Read,
+        path::Path,
+    };
+
+use base64::Engine;
+use sha2::{
+        Digest,
+        Sha256,
+    };
+
+use crate::error::{
+        Error,
+        Result
+    };
+
+use super::compress::Decoder;
+
+#[derive(Default)]
+struct PkgInfo {
+    pkgname: String,
+    pkgbase: String,
+    pkgver: String,
+    pkgdesc: String,
+    url: String,
+    builddate: String,
+    packager: String,
+    size: String,
+    arch: String,
+    license: Vec<String>,
+    replaces: Vec<String>,
+    group: Vec<String>,
+    conflict: Vec<String>,
+    provides: Vec<String>,
+    depend: Vec<String>,
+    optdepend: Vec<String>,
+    makedepend: Vec<String>,
+    checkdepend: Vec<String>,
+}
+
+impl PkgInfo {
+    fn from_content(content: &str) -> Result<Self> {
+        let mut pkginfo = Self::default();
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue
+            }
+            // Empty values are written as e.g. `url = `, trimmed to `url =`
+            let (key, value) = match line.split_once(" =") {
+                Some((key, value)) => (key, value.trim_start().to_string()),
+                None => {
+                    log::error!("Illegal line in .PKGINFO: {}", line);
+                    return Err(Error::IntegrityError)
+                },
+            };
+            match key {
+                "pkgname" => pkginfo.pkgname = value,
+                "pkgbase" => pkginfo.pkgbase = value,
+                "pkgver" => pkginfo.pkgver = value,
+                "pkgdesc" => pkginfo.pkgdesc = value,
+                "url" => pkginfo.url = value,
+                "builddate" => pkginfo.builddate = value,
+                "packager" => pkginfo.packager = value,
+                "size" => pkginfo.size = value,
+                "arch" => pkginfo.arch = value,
+                "license" => pkginfo.license.push(value),
+                "replaces" => pkginfo.replaces.push(value),
+                "group" => pkginfo.group.push(value),
+                "conflict" => pkginfo.conflict.push(value),
+                "provides" => pkginfo.provides.push(value),
+                "depend" => pkginfo.depend.push(value),
+                "optdepend" => pkginfo.optdepend.push(value),
+                "makedepend" => pkginfo.makedepend.push(value),
+                "checkdepend" => pkginfo.checkdepend.push(value),
+                _ => (), // backup, xdata, etc, not needed in DB
+            }
+        }
+        if pkginfo.pkgname.is_empty() || pkginfo.pkgver.is_empty() {
+            log::error!(".PKGINFO does not have pkgname or pkgver");
+            return Err(Error::IntegrityError)
+        }
+        Ok(pkginfo)
+    }
+}
+
+fn push_field(desc: &mut String, key: &str, value: &str) {
+    if value.is_empty() {
+        return
+    }
+    desc.push_str(&format!("%{}%\n{}\n\n", key, value));
+}
+
+fn push_list(desc: &mut String, key: &str, values: &[String]) {
+    if values.is_empty() {
+        return
+    }
+    desc.push_str(&format!("%{}%\n", key));
+    for value in values.iter() {
+        desc.push_str(value);
+        desc.push('\n');
+    }
+    desc.push('\n');
+}
+
+/// A package file, parsed into the entry that would be written into repo DBs
+pub(super) struct Package {
+    pub(super) filename: String,
+    pub(super) dir: String,
+    pub(super) desc: String,
+    pub(super) files: String,
+}
+
+impl Package {
+    fn sums(path: &Path) -> Result<(u64, String, String)> {
+        let mut file = File::open(path).map_err(|e|{
+            log::error!("Failed to open package '{}': {}", path.display(), e);
+            Error::IoError(e)
+        })?;
+        let mut md5 = md5::Context::new();
+        let mut sha256 = Sha256::new();
+        let mut buffer = vec![0; 0x400000];
+        let mut size = 0;
+        loop {
+            let size_chunk = file.read(&mut buffer).map_err(|e|{
+                log::error!("Failed to read package '{}': {}",
+                    path.display(), e);
+                Error::IoError(e)
+            })?;
+            if size_chunk == 0 {
+                break
+            }
+            let chunk = &buffer[0..size_chunk];
+            md5.consume(chunk);
+            sha256.update(chunk);
+            size += size_chunk as u64;
+        }
+        Ok((size, format!("{:x}", md5.compute()),
+            hex::encode(sha256.finalize())))
+    }
+
+    fn pgpsig(path: &Path) -> Result<String> {
+        let mut name = path.as_os_str().to_owned();
+        name.push(".sig");
+        let path_sig = Path::new(&name);
+        if ! path_sig.exists() {
+            return Ok(String::new())
+        }
+        match std::fs::read(path_sig) {
+            Ok(sig) =>
+                Ok(base64::engine::general_purpose::STANDARD.encode(sig)),
+            Err(e) => {
+                log::error!("Failed to read signature '{}': {}",
+                    path_sig.display(), e);
+                Err(Error::IoError(e))
+            },
+        }
+    }
+
+    pub(super) fn from_file(path: &Path) -> Result<Self> {
+        let filename = match path.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => {
+                log::error!("Package path '{}' has no file name",
+                    path.display());
+                return Err(Error::ImpossibleLogic)
+            },
+        };
+        let mut archive = tar::Archive::new(Decoder::from_path(path)?);
+        let entries = archive.entries().map_err(|e|{
+            log::error!("Failed to read entries of package '{}': {}",
+                path.display(), e);
+            Error::IoError(e)
+        })?;
+        let mut pkginfo = None;
+        let mut files = String::from("%FILES%\n");
+        for entry in entries {
+            let mut entry = entry.map_err(|e|{
+                log::error!("Failed to read entry of package '{}': {}",
+                    path.display(), e);
+                Error::IoError(e)
+            })?;
+            let entry_path = entry.path().map_err(|e|{
+                log::error!("Failed to get path of entry in package '{}': {}",
+                    path.display(), e);
+                Error::IoError(e)
+            })?.to_string_lossy().into_owned();
+            if entry_path == ".PKGINFO" {
+                let mut content = String::new();
+                entry.read_to_string(&mut content).map_err(|e|{
+                    log::error!("Failed to read .PKGINFO of package '{}': {}",
+                        path.display(), e);
+                    Error::IoError(e)
+                })?;
+                pkginfo = Some(PkgInfo::from_content(&content)?);
+                continue
+            }
+            if entry_path.starts_with('.') {
+                continue // .BUILDINFO, .MTREE, .INSTALL, .CHANGELOG
+            }
+            files.push_str(entry_path.trim_start_matches("./"));
+            if entry.header().entry_type().is_dir() &&
+                ! entry_path.ends_with('/')
+            {
+                files.push('/')
+            }
+            files.push('\n');
+        }
+        let pkginfo = match pkginfo {
+            Some(pkginfo) => pkginfo,
+            None => {
+                log::error!("Package '{}' does not contain .PKGINFO",
+                    path.display());
+                return Err(Error::IntegrityError)
+            },
+        };
+        let (csize, md5sum, sha256sum) = Self::sums(path)?;
+        let mut desc = String::new();
+        push_field(&mut desc, "FILENAME", &filename);
+        push_field(&mut desc, "NAME", &pkginfo.pkgname);
+        push_field(&mut desc, "BASE", &pkginfo.pkgbase);
+        push_field(&mut desc, "VERSION", &pkginfo.pkgver);
+        push_field(&mut desc, "DESC", &pkginfo.pkgdesc);
+        push_list(&mut desc, "GROUPS", &pkginfo.group);
+        push_field(&mut desc, "CSIZE", &csize.to_string());
+        push_field(&mut desc, "ISIZE", &pkginfo.size);
+        push_field(&mut desc, "MD5SUM", &md5sum);
+        push_field(&mut desc, "SHA256SUM", &sha256sum);
+        push_field(&mut desc, "PGPSIG", &Self::pgpsig(path)?);
+        push_field(&mut desc, "URL", &pkginfo.url);
+        push_list(&mut desc, "LICENSE", &pkginfo.license);
+        push_field(&mut desc, "ARCH", &pkginfo.arch);
+        push_field(&mut desc, "BUILDDATE", &pkginfo.builddate);
+        push_field(&mut desc, "PACKAGER", &pkginfo.packager);
+        push_list(&mut desc, "REPLACES", &pkginfo.replaces);
+        push_list(&mut desc, "CONFLICTS", &pkginfo.conflict);
+        push_list(&mut desc, "PROVIDES", &pkginfo.provides);
+        push_list(&mut desc, "DEPENDS", &pkginfo.depend);
+        push_list(&mut desc, "OPTDEPENDS", &pkginfo.optdepend);
+        push_list(&mut desc, "MAKEDEPENDS", &pkginfo.makedepend);
+        push_list(&mut desc, "CHECKDEPENDS", &pkginfo.checkdepend);
+        log::info!("Parsed package '{}' for repo DB", filename);
+        Ok(Self {
+            filename,
+            dir: format!("{}-{}", pkginfo.pkgname, pkginfo.pkgver),
+            desc,
+            files,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PkgInfo;
+
+    #[test]
+    fn pkginfo_empty_values() {
+        let pkginfo = PkgInfo::from_content("\
+# Generated by makepkg
+pkgname = foo
+pkgbase = foo
+pkgver = 1.0-1
+pkgdesc = 
+url = 
+arch = x86_64
+depend = bar>=1
+").unwrap();
+        assert_eq!(pkginfo.pkgname, "foo");
+        assert_eq!(pkginfo.pkgver, "1.0-1");
+        assert_eq!(pkginfo.pkgdesc, "");
+        assert_eq!(pkginfo.url, "");
+        assert_eq!(pkginfo.depend, ["bar>=1"]);
+    }
+
+    #[test]
+    fn pkginfo_illegal_line() {
+        assert!(PkgInfo::from_content("pkgname foo\npkgver = 1.0-1\n")
+            .is_err())
+    }
+}
@@ -0,0 +1,172 @@
+// Machine-readable report of a run, written as `state/reports/[run].json` at
+// the end of each build run, with `state/report.json` linking to the latest.
+// Failed repos and sources are recorded here as they happen, from whichever
+// thread they're synced in
+
+use std::{
+        path::PathBuf,
+        sync::Mutex,
+    };
+
+use serde::Serialize;
+use time::{
+        format_description::well_known::Rfc3339,
+        OffsetDateTime,
+    };
+
+use crate::{
+        error::{
+            Error,
+            Result
+        },
+        filesystem::{
+            create_dir_allow_existing,
+            symlink_force,
+        },
+        pkgbuild::PKGBUILDs,
+        repo::list_packages,
+        state::{
+            now,
+            Outcome,
+            StateDb,
+        },
+    };
+
+static FAILED_REPOS: Mutex<Vec<FailedRepo>> = Mutex::new(vec![]);
+static FAILED_SOURCES: Mutex<Vec<String>> = Mutex::new(vec![]);
+
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct FailedRepo {
+    pub(crate) url: String,
+    pub(crate) path: PathBuf,
+}
+
+/// Record a git repo, either of PKGBUILDs or a source, that failed to sync
+pub(crate) fn repo_failed(url: &str, path: &std::path::Path) {
+    if let Ok(mut repos) = FAILED_REPOS.lock() {
+        repos.push(FailedRepo { url: url.into(), path: path.into() })
+    }
+}
+
+/// Record a non-git source that failed to download
+pub(crate) fn source_failed(url: &str) {
+    if let Ok(mut sources) = FAILED_SOURCES.lock() {
+        sources.push(url.into())
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Package {
+    pub(crate) file: String,
+    pub(crate) signed: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct PkgbuildReport {
+    pub(crate) base: String,
+    /// The target arch, not set for the host arch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) arch: Option<String>,
+    pub(crate) pkgid: String,
+    pub(crate) need_build: bool,
+    /// Not set if not built in this run
+    pub(crate) result: Option<Outcome>,
+    pub(crate) tries: usize,
+    /// In seconds, from the start of the first try to the end of the last
+    pub(crate) duration: Option<i64>,
+    pub(crate) log: Option<PathBuf>,
+    pub(crate) packages: Vec<Package>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Report {
+    pub(crate) run: String,
+    pub(crate) start: String,
+    pub(crate) end: String,
+    pub(crate) result: Outcome,
+    /// Why the run failed, if it did
+    pub(crate) error: Option<String>,
+    pub(crate) pkgbuilds: Vec<PkgbuildReport>,
+    pub(crate) failed_repos: Vec<FailedRepo>,
+    pub(crate) failed_sources: Vec<String>,
+}
+
+fn duration(start: &str, end: &str) -> Option<i64> {
+    let start = OffsetDateTime::parse(start, &Rfc3339).ok()?;
+    let end = OffsetDateTime::parse(end, &Rfc3339).ok()?;
+    Some((end - start).whole_seconds())
+}
+
+impl Report {
+    const DIR: &'static str = "state/reports";
+    const LATEST: &'static str = "state/report.json";
+
+    /// Report of the run, pkgbuilds are not set if the run failed before they
+    /// were synced
+    pub(crate) fn new(
+        state: &StateDb, pkgbuilds: Option<&PKGBUILDs>, error: Option<&str>
+    ) -> Result<Self>
+    {
+        let mut reports = vec![];
+        for pkgbuild in pkgbuilds.map(|pkgbuilds|pkgbuilds.0.as_slice())
+            .unwrap_or_default()
+        {
+            let build = state.run_builds().rev().find(|build|
+                build.base == pkgbuild.base &&
+                build.arch.as_deref() == pkgbuild.arch());
+            let mut packages = vec![];
+            if pkgbuild.built() {
+                for file in list_packages(pkgbuild.pkgdir())? {
+                    let signed = pkgbuild.pkgdir()
+                        .join(format!("{}.sig", file)).exists();
+                    packages.push(Package { file, signed })
+                }
+            }
+            reports.push(PkgbuildReport {
+                base: pkgbuild.base.clone(),
+                arch: pkgbuild.arch().map(String::from),
+                pkgid: pkgbuild.pkgid.clone(),
+                need_build: pkgbuild.need_build,
+                result: build.map(|build|build.result),
+                tries: build.map(|build|build.tries).unwrap_or_default(),
+                duration: build.and_then(|build|duration(
+                    build.start.as_deref()?, &build.end)),
+                log: build.and_then(|build|build.log.clone()),
+                packages,
+            })
+        }
+        Ok(Self {
+            run: state.run().into(),
+            start: state.start().into(),
+            end: now()?,
+            result: match error {
+                Some(_) => Outcome::Failure,
+                None => Outcome::Success,
+            },
+            error: error.map(String::from),
+            pkgbuilds: reports,
+            failed_repos: FAILED_REPOS.lock().map(|repos|repos.clone())
+                .unwrap_or_default(),
+            failed_sources: FAILED_SOURCES.lock().map(
+                |sources|sources.clone()).unwrap_or_default(),
+        })
+    }
+
+    pub(crate) fn write(&self) -> Result<()> {
+        create_dir_allow_existing(Self::DIR)?;
+        let content = serde_json::to_string_pretty(self).map_err(|e|{
+            log::error!("Failed to serialize run report: {}", e);
+            Error::ImpossibleLogic
+        })?;
+        let name = format!("{}.json", self.run);
+        let path = PathBuf::from(Self::DIR).join(&name);
+        std::fs::write(&path, content).map_err(|e|{
+            log::error!("Failed to write run report '{}': {}",
+                path.display(), e);
+            Error::IoError(e)
+        })?;
+        symlink_force(PathBuf::from("reports").join(&name), Self::LATEST)?;
+        log::info!("Wrote run report to '{}'", path.display());
+        Ok(())
+    }
+}
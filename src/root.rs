@@ -1,12 +1,22 @@
 
 mod base;
+mod binfmt;
 mod common;
 mod mount;
 mod overlay;
 
-pub(crate) use base::BaseRoot;
-pub(crate) use common::CommonRoot;
+pub(crate) use base::{
+        BaseConfigs,
+        BaseRoot,
+    };
+pub(crate) use common::{
+        CommonRoot,
+        set_pacman_arch,
+        set_pacman_cachedir,
+    };
 pub(crate) use overlay::{
+        Bind,
         BootstrappingOverlayRoot,
+        OverlayExtras,
         OverlayRoot,
     };
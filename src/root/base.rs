@@ -1,9 +1,14 @@
 use std::{
+        collections::HashMap,
         ffi::OsStr,
         fs::{
             create_dir,
             create_dir_all,
+            OpenOptions,
+            read_to_string,
+            write,
         },
+        io::Write,
         path::{
             Path,
             PathBuf,
@@ -17,11 +22,18 @@ use nix::mount::{
     };
 
 use crate::{
+        config::{
+            Arch as ArchConfig,
+            Makepkg as MakepkgSettings,
+            Pacman as PacmanSettings,
+            PacmanConfig,
+        },
         error::{
             Error,
             Result
         },
         identity::{
+            is_rootless,
             Identity,
             IdentityActual,
         },
@@ -31,19 +43,129 @@ use crate::{
         },
 };
 
-/// The basic root, with bare-minimum packages installed
+/// The parts of the config base roots are bootstrapped with
+#[derive(Clone, Copy)]
+pub(crate) struct BaseConfigs<'a> {
+    pub(crate) arches: &'a HashMap<String, ArchConfig>,
+    pub(crate) pacman: &'a PacmanSettings,
+    pub(crate) makepkg: &'a MakepkgSettings,
+}
+
+/// The basic root, with bare-minimum packages installed, either for the host
+/// arch at `roots/base`, or for a target arch at `roots/base-[arch]`
 #[derive(Clone)]
-pub(crate) struct BaseRoot (MountedFolder);
+pub(crate) struct BaseRoot {
+    folder: MountedFolder,
+    arch: Option<String>,
+    makepkg_conf: PathBuf,
+    /// Lines appended to the makepkg config
+    makepkg_overrides: Vec<String>,
+}
+
+/// Quote a value for bash, as a single word
+fn bash_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
 
 
 impl BaseRoot {
+    const PACMAN_CONF_HOST: &'static str = "/etc/pacman.conf";
+    const MAKEPKG_CONF_HOST: &'static str = "/etc/makepkg.conf";
+
     fn path(&self) -> &Path {
-        &self.0.0
+        &self.folder.0
+    }
+
+    pub(crate) fn path_of(arch: Option<&str>) -> PathBuf {
+        match arch {
+            Some(arch) => PathBuf::from(format!("roots/base-{}", arch)),
+            None => PathBuf::from("roots/base"),
+        }
+    }
+
+    /// The pacman config for roots of the arch, generated next to its base
+    /// root
+    pub(crate) fn pacman_conf_of(arch: Option<&str>) -> PathBuf {
+        let mut path = Self::path_of(arch).into_os_string();
+        path.push(".conf");
+        path.into()
+    }
+
+    fn new_no_init(arch: Option<&str>, configs: BaseConfigs) -> Self {
+        let makepkg = configs.makepkg;
+        let makepkg_conf = arch.and_then(|arch|configs.arches.get(arch))
+            .and_then(|config|config.makepkg_conf.as_deref())
+            .or(makepkg.conf.as_deref())
+            .unwrap_or(Self::MAKEPKG_CONF_HOST);
+        let mut makepkg_overrides = vec![];
+        for (key, value) in [
+            ("CFLAGS", &makepkg.cflags), ("CXXFLAGS", &makepkg.cxxflags),
+            ("LDFLAGS", &makepkg.ldflags), ("MAKEFLAGS", &makepkg.makeflags),
+            ("PKGEXT", &makepkg.pkgext)]
+        {
+            if let Some(value) = value {
+                makepkg_overrides.push(
+                    format!("{}={}", key, bash_quote(value)))
+            }
+        }
+        for (key, values) in [
+            ("BUILDENV", &makepkg.buildenv), ("OPTIONS", &makepkg.options)]
+        {
+            if let Some(values) = values {
+                makepkg_overrides.push(format!("{}=({})", key, values.iter()
+                    .map(|value|bash_quote(value))
+                    .collect::<Vec<_>>().join(" ")))
+            }
+        }
+        // A target arch is always set even if the config is the host one
+        if let Some(arch) = arch {
+            makepkg_overrides.push(format!("CARCH={}", bash_quote(arch)))
+        }
+        Self {
+            folder: MountedFolder(Self::path_of(arch)),
+            arch: arch.map(String::from),
+            makepkg_conf: PathBuf::from(makepkg_conf),
+            makepkg_overrides,
+        }
+    }
+
+    /// Generate the pacman config of the arch from the configured one, or the
+    /// host one, so builds never depend on how the host is configured
+    ///
+    /// Root is expected
+    fn write_pacman_conf(&self, configs: BaseConfigs) -> Result<&Self> {
+        if let Some(repo) = configs.pacman.repos.iter().find(
+            |repo|repo.servers.is_empty() && repo.include.is_none())
+        {
+            log::error!("Extra repo '{}' has neither servers nor include",
+                repo.name);
+            return Err(Error::InvalidConfig)
+        }
+        let config = self.arch.as_ref().and_then(
+            |arch|configs.arches.get(arch));
+        let source = config.and_then(|config|config.pacman_conf.as_deref())
+            .or(configs.pacman.conf.as_deref())
+            .unwrap_or(Self::PACMAN_CONF_HOST);
+        let content = read_to_string(source).map_err(|e|{
+            log::error!("Failed to read pacman config '{}': {}", source, e);
+            Error::IoError(e)
+        })?;
+        let content = PacmanConfig::from_pacman_conf_content(&content)?
+            .for_builder(self.arch(),
+                config.and_then(|config|config.mirrorlist.as_deref()),
+                configs.pacman.siglevel.as_deref(), &configs.pacman.repos);
+        let target = self.pacman_conf();
+        if let Err(e) = write(&target, content) {
+            log::error!("Failed to write pacman config '{}': {}",
+                target.display(), e);
+            return Err(Error::IoError(e))
+        }
+        Ok(self)
     }
 
     /// Root is expected
     fn bind_self(&self) -> Result<&Self> {
-        mount(Some("roots/base"),
+        mount(Some(self.path()),
                 self.path(),
                 None::<&str>,
                 MsFlags::MS_BIND,
@@ -57,12 +179,37 @@ impl BaseRoot {
 
     /// Root is expected
     fn remove(&self) -> Result<&Self> {
-        self.0.remove().and(Ok(self))
+        self.folder.remove().and(Ok(self))
     }
 
     /// Root is expected
     fn umount_recursive(&self) -> Result<&Self> {
-        self.0.umount_recursive().and(Ok(self))
+        self.folder.umount_recursive().and(Ok(self))
+    }
+
+    /// Copy the makepkg config of the arch, with the overrides appended
+    ///
+    /// Root is expected
+    fn makepkg_conf(&self) -> Result<&Self> {
+        let target = self.path().join("etc/makepkg.conf");
+        Self::copy_file(&self.makepkg_conf, &target)?;
+        if self.makepkg_overrides.is_empty() {
+            return Ok(self)
+        }
+        let mut file = OpenOptions::new().append(true).open(&target)
+            .map_err(|e|{
+                log::error!("Failed to open '{}' to append: {}",
+                    target.display(), e);
+                Error::IoError(e)
+            })?;
+        for line in self.makepkg_overrides.iter() {
+            if let Err(e) = writeln!(file, "{}", line) {
+                log::error!("Failed to append '{}' to '{}': {}",
+                    line, target.display(), e);
+                return Err(Error::IoError(e))
+            }
+        }
+        Ok(self)
     }
 
     /// Root is expected
@@ -83,9 +230,13 @@ impl BaseRoot {
         let builder = self.builder(actual_identity)?;
         self.copy_file_same("etc/passwd")?
             .copy_file_same("etc/group")?
-            .copy_file_same("etc/shadow")?
-            .copy_file_same("etc/makepkg.conf")?
-            .create_home(actual_identity)?;
+            .makepkg_conf()?;
+        // The host shadow is not readable by the root in our user namespace,
+        // and the builder never needs to authenticate anyway
+        if ! is_rootless() {
+            self.copy_file_same("etc/shadow")?;
+        }
+        self.create_home(actual_identity)?;
         create_dir_all(&builder)
             .or_else(|e|{
                 log::error!("Failed to create chroot builder dir: {}", e);
@@ -102,36 +253,48 @@ impl BaseRoot {
         Ok(self)
     }
 
-    pub(crate) fn db_only() -> Result<Self> {
-        IdentityActual::as_root(||MountedFolder::remove_all())?;
-        log::info!("Creating base chroot (DB only)");
-        let root = Self(MountedFolder(PathBuf::from("roots/base")));
+    /// Remove all roots, including those left by former runs
+    pub(crate) fn remove_all() -> Result<()> {
+        IdentityActual::as_root(MountedFolder::remove_all)
+    }
+
+    /// Create a base root with only fresh DBs, for the host arch if arch is
+    /// not set
+    pub(crate) fn db_only(arch: Option<&str>, configs: BaseConfigs)
+        -> Result<Self>
+    {
+        let root = Self::new_no_init(arch, configs);
+        log::info!("Creating base chroot '{}' (DB only)",
+            root.path().display());
         IdentityActual::as_root(||{
             root.remove()?
                 .base_layout()?
+                .write_pacman_conf(configs)?
                 .bind_self()?
                 .base_mounts()?
                 .refresh_dbs()?;
             Ok(())
         })?;
-        log::info!("Created base chroot (DB only)");
+        log::info!("Created base chroot '{}' (DB only)", root.path().display());
         Ok(root)
     }
 
     /// Create a base rootfs containing the minimum packages and user setup
     /// This should not be used directly for building packages
-    pub(crate) fn _new<I, S>(actual_identity: &IdentityActual, pkgs: I)
-        -> Result<Self>
+    pub(crate) fn _new<I, S>(
+        actual_identity: &IdentityActual, configs: BaseConfigs, pkgs: I
+    ) -> Result<Self>
     where
         I: IntoIterator<Item = S>,
         S: AsRef<OsStr>
     {
-        IdentityActual::as_root(||MountedFolder::remove_all())?;
+        Self::remove_all()?;
         log::info!("Creating base chroot");
-        let root = Self(MountedFolder(PathBuf::from("roots/base")));
+        let root = Self::new_no_init(None, configs);
         IdentityActual::as_root(||{
             root.remove()?
                 .base_layout()?
+                .write_pacman_conf(configs)?
                 .bind_self()?
                 .base_mounts()?
                 .refresh_dbs()?
@@ -165,7 +328,11 @@ impl BaseRoot {
 
 impl CommonRoot for BaseRoot {
     fn path(&self) -> &Path {
-        self.0.0.as_path()
+        self.folder.0.as_path()
+    }
+
+    fn arch(&self) -> Option<&str> {
+        self.arch.as_deref()
     }
 }
 
@@ -0,0 +1,91 @@
+// Foreign-arch roots run their binaries through a qemu-user interpreter the
+// host registered in binfmt_misc, e.g. with qemu-user-static-binfmt
+
+use std::{
+        fs::read_to_string,
+        path::PathBuf,
+    };
+
+use crate::error::{
+        Error,
+        Result
+    };
+
+pub(crate) struct Interpreter {
+    pub(crate) path: PathBuf,
+    /// Opened by the kernel when registered (flag `F`), so it does not need
+    /// to exist in the root
+    pub(crate) fix_binary: bool,
+}
+
+const BINFMT_MISC: &str = "/proc/sys/fs/binfmt_misc";
+
+/// The qemu name of a pacman arch
+fn qemu_arch(arch: &str) -> &str {
+    match arch {
+        "armv6h" | "armv7h" => "arm",
+        "i486" | "i686" | "pentium4" => "i386",
+        "loong64" => "loongarch64",
+        arch => arch,
+    }
+}
+
+/// The qemu name of the host arch
+fn qemu_arch_host() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86" => "i386",
+        arch => arch,
+    }
+}
+
+/// Whether the host runs binaries of the qemu arch natively, e.g. armv7h on
+/// most aarch64 hosts
+fn host_compatible(qemu_arch: &str) -> bool {
+    matches!((qemu_arch_host(), qemu_arch),
+        ("x86_64", "i386") | ("aarch64", "arm"))
+}
+
+/// The interpreter to run binaries of the arch with, none if they could be
+/// run natively
+pub(crate) fn interpreter(arch: Option<&str>) -> Result<Option<Interpreter>> {
+    let qemu_arch = match arch {
+        Some(arch) => qemu_arch(arch),
+        None => return Ok(None),
+    };
+    if qemu_arch == qemu_arch_host() || host_compatible(qemu_arch) {
+        return Ok(None)
+    }
+    let entry = PathBuf::from(BINFMT_MISC).join(
+        format!("qemu-{}", qemu_arch));
+    let content = match read_to_string(&entry) {
+        Ok(content) => content,
+        Err(e) => {
+            log::error!("No qemu-user interpreter registered in binfmt_misc \
+                for arch '{}' ('{}': {}), install and register one, e.g. \
+                qemu-user-static-binfmt", arch.unwrap_or_default(),
+                entry.display(), e);
+            return Err(Error::BrokenEnvironment)
+        },
+    };
+    let mut enabled = false;
+    let mut path = None;
+    let mut fix_binary = false;
+    for line in content.lines() {
+        if line == "enabled" {
+            enabled = true
+        } else if let Some(interpreter) = line.strip_prefix("interpreter ") {
+            path = Some(PathBuf::from(interpreter))
+        } else if let Some(flags) = line.strip_prefix("flags: ") {
+            fix_binary = flags.contains('F')
+        }
+    }
+    let path = match path {
+        Some(path) if enabled => path,
+        _ => {
+            log::error!("The qemu-user interpreter registered in binfmt_misc \
+                as '{}' is disabled or broken", entry.display());
+            return Err(Error::BrokenEnvironment)
+        },
+    };
+    Ok(Some(Interpreter { path, fix_binary }))
+}
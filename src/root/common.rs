@@ -3,8 +3,10 @@ use std::{
         fs::{
             copy,
             create_dir_all,
+            File,
             remove_file,
         },
+        os::unix::fs::symlink,
         path::{
             Path,
             PathBuf
@@ -19,15 +21,56 @@ use crate::{
             Error,
             Result
         },
-        identity::IdentityActual,
-        root::mount::mount_checked,
+        filesystem::create_dir_allow_existing,
+        identity::{
+            is_rootless,
+            IdentityActual,
+        },
+        root::{
+            binfmt,
+            mount::mount_checked,
+        },
     };
 
+/// The host pacman cache is owned by the real root, so in rootless mode we
+/// use our own one under sources
+pub(crate) fn set_pacman_cachedir(command: &mut Command)
+    -> Result<&mut Command>
+{
+    if ! is_rootless() {
+        return Ok(command)
+    }
+    let cachedir = PathBuf::from("sources/pacman");
+    create_dir_allow_existing(&cachedir)?;
+    match cachedir.canonicalize() {
+        Ok(cachedir) => Ok(command.arg("--cachedir").arg(cachedir)),
+        Err(e) => {
+            log::error!("Failed to canonicalize pacman cachedir: {}", e);
+            Err(Error::IoError(e))
+        },
+    }
+}
+
+/// Pacman would refuse packages of a foreign arch unless told so
+pub(crate) fn set_pacman_arch<'a>(command: &'a mut Command, arch: Option<&str>)
+    -> &'a mut Command
+{
+    match arch {
+        Some(arch) => command.arg("--arch").arg(arch),
+        None => command,
+    }
+}
+
 pub(crate) trait CommonRoot {
     const BUILDER_DIRS: [&'static str; 3] = ["build", "pkgs", "sources"];
     // const MSFLAGS_PROC: MsFlags = MsFlags::MS_NOSUID | MsFlags::MS_NOEXEC | MsFlags::MS_NODEV;
 
     fn path(&self) -> &Path;
+    /// The target arch, not set for the host arch
+    fn arch(&self) -> Option<&str>;
+    fn pacman_conf(&self) -> PathBuf {
+        super::BaseRoot::pacman_conf_of(self.arch())
+    }
     fn path_absolute(&self) -> Result<PathBuf> {
         let path = self.path();
         path.canonicalize().map_err(|e|{
@@ -70,6 +113,17 @@ pub(crate) trait CommonRoot {
 
     fn mount_sys(&self) -> Result<&Self> {
         let path_sys = self.path().join("sys");
+        if is_rootless() {
+            // sysfs could only be mounted by the owner of the net namespace
+            return mount_checked(Some("/sys"),
+                &path_sys,
+                None::<&str>,
+                MsFlags::MS_BIND | MsFlags::MS_REC,
+                None::<&str>,
+                "/sys",
+                path_sys.display()
+            ).and(Ok(self))
+        }
         mount_checked(Some("sys"),
             &path_sys,
             Some("sysfs"),
@@ -82,6 +136,9 @@ pub(crate) trait CommonRoot {
     }
 
     fn mount_dev(&self) -> Result<&Self> {
+        if is_rootless() {
+            return self.mount_dev_rootless()
+        }
         let path_dev = self.path().join("dev");
         mount_checked(Some("udev"),
             &path_dev,
@@ -93,13 +150,63 @@ pub(crate) trait CommonRoot {
         ).and(Ok(self))
     }
 
+    /// devtmpfs can not be mounted in a user namespace, so use a tmpfs with
+    /// only the basic device nodes bound from the host, like bwrap does
+    fn mount_dev_rootless(&self) -> Result<&Self> {
+        let path_dev = self.path().join("dev");
+        mount_checked(Some("tmpfs"),
+            &path_dev,
+            Some("tmpfs"),
+            MsFlags::MS_NOSUID,
+            Some("mode=0755"),
+            "tmpfs",
+            path_dev.display()
+        )?;
+        for subdir in ["pts", "shm"] {
+            create_dir_allow_existing(path_dev.join(subdir))?
+        }
+        for node in ["full", "null", "random", "tty", "urandom", "zero"] {
+            let path_host = PathBuf::from("/dev").join(node);
+            let path_node = path_dev.join(node);
+            if let Err(e) = File::create(&path_node) {
+                log::error!("Failed to create mount point '{}': {}",
+                    path_node.display(), e);
+                return Err(Error::IoError(e))
+            }
+            mount_checked(Some(&path_host),
+                &path_node,
+                None::<&str>,
+                MsFlags::MS_BIND,
+                None::<&str>,
+                path_host.display(),
+                path_node.display()
+            )?
+        }
+        for (link, original) in [
+            ("fd", "/proc/self/fd"), ("stdin", "/proc/self/fd/0"),
+            ("stdout", "/proc/self/fd/1"), ("stderr", "/proc/self/fd/2"),
+            ("ptmx", "pts/ptmx")]
+        {
+            if let Err(e) = symlink(original, path_dev.join(link)) {
+                log::error!("Failed to create symlink '{}' in dev: {}",
+                    link, e);
+                return Err(Error::IoError(e))
+            }
+        }
+        Ok(self)
+    }
+
     fn mount_devpts(&self) -> Result<&Self> {
         let path_devpts = self.path().join("dev/pts");
         mount_checked(Some("devpts"),
             &path_devpts,
             Some("devpts"),
             MsFlags::MS_NOSUID | MsFlags::MS_NOEXEC,
-            Some("mode=0620,gid=5"),
+            Some(if is_rootless() {
+                "newinstance,ptmxmode=0666,mode=0620,gid=5"
+            } else {
+                "mode=0620,gid=5"
+            }),
             "devpts",
             path_devpts.display()
         ).and(Ok(self)) 
@@ -141,7 +248,53 @@ pub(crate) trait CommonRoot {
         ).and(Ok(self))
     }
 
-    /// The minimum mounts needed for execution, like how it's done by pacstrap.
+    /// Bind the qemu-user interpreter into a foreign-arch root at the same
+    /// path, read-only, unless the kernel already holds it open
+    fn mount_interpreter(&self) -> Result<&Self> {
+        let interpreter = match binfmt::interpreter(self.arch())? {
+            Some(interpreter) if ! interpreter.fix_binary => interpreter,
+            _ => return Ok(self),
+        };
+        let path_interpreter = self.path().join(
+            interpreter.path.strip_prefix("/").unwrap_or(&interpreter.path));
+        if let Some(parent) = path_interpreter.parent() {
+            if let Err(e) = create_dir_all(parent) {
+                log::error!("Failed to create dir '{}': {}",
+                    parent.display(), e);
+                return Err(Error::IoError(e))
+            }
+        }
+        if ! path_interpreter.exists() {
+            if let Err(e) = File::create(&path_interpreter) {
+                log::error!("Failed to create mount point '{}': {}",
+                    path_interpreter.display(), e);
+                return Err(Error::IoError(e))
+            }
+        }
+        mount_checked(Some(&interpreter.path),
+            &path_interpreter,
+            None::<&str>,
+            MsFlags::MS_BIND,
+            None::<&str>,
+            interpreter.path.display(),
+            path_interpreter.display()
+        )?;
+        // Files of the real root could not be written in rootless mode anyway
+        if is_rootless() {
+            return Ok(self)
+        }
+        mount_checked(None::<&str>,
+            &path_interpreter,
+            None::<&str>,
+            MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY,
+            None::<&str>,
+            "(remount read-only)",
+            path_interpreter.display()
+        ).and(Ok(self))
+    }
+
+    /// The minimum mounts needed for execution, like how it's done by pacstrap,
+    /// and the interpreter for a foreign arch.
     /// Root is expected.
     fn base_mounts(&self) -> Result<&Self> {
         self.mount_proc()?
@@ -150,21 +303,25 @@ pub(crate) trait CommonRoot {
             .mount_devpts()?
             .mount_devshm()?
             .mount_run()?
-            .mount_tmp()
+            .mount_tmp()?
+            .mount_interpreter()
     }
 
     // Todo: split out common wait child parts
     fn refresh_dbs(&self) -> Result<&Self> {
+        let mut command = Command::new("/usr/bin/pacman");
+        command
+            .env("LANG", "C")
+            .arg("-Sy")
+            .arg("--config")
+            .arg(self.pacman_conf())
+            .arg("--root")
+            .arg(self.path_absolute()?);
+        set_pacman_arch(&mut command, self.arch());
         crate::child::no_output_check(
             crate::logfile::LogFile::new(
                 crate::logfile::LogType::Pacman, "refresh-DB")?
-                .set_command(
-                    Command::new("/usr/bin/pacman")
-                    .env("LANG", "C")
-                    .arg("-Sy")
-                    .arg("--root")
-                    .arg(self.path_absolute()?)
-                )?,
+                .set_command(&mut command)?,
             "refresh pacman DB").and(Ok(self))
     }
 
@@ -178,12 +335,16 @@ pub(crate) trait CommonRoot {
         command
             .env("LANG", "C")
             .arg("-S")
+            .arg("--config")
+            .arg(self.pacman_conf())
             .arg("--root")
             .arg(self.path())
             .arg("--dbpath")
             .arg(self.db_path())
             .arg("--noconfirm")
             .arg("--needed");
+        set_pacman_arch(&mut command, self.arch());
+        set_pacman_cachedir(&mut command)?;
         let mut has_pkg = false;
         for pkg in pkgs {
             has_pkg = true;
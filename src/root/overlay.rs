@@ -1,18 +1,30 @@
 use std::{
         ffi::OsStr,
         fs::{
+            copy,
             create_dir_all,
+            File,
+            read_to_string,
             remove_dir_all,
+            write,
         },
         path::{
+            Component,
             Path,
             PathBuf,
         },
+        process::Command,
 };
 
-use nix::mount::{
-        mount,
-        MsFlags,
+use nix::{
+        mount::{
+            mount,
+            MsFlags,
+        },
+        sys::statvfs::{
+            FsFlags,
+            statvfs,
+        },
 };
 
 use crate::{
@@ -23,23 +35,110 @@ use crate::{
         },
         filesystem::create_dir_all_under_owned_by,
         identity::{
+            is_rootless,
             Identity,
             IdentityActual,
         },
         root:: {
-            common::CommonRoot,
-            mount::MountedFolder,
+            base::BaseRoot,
+            common::{
+                CommonRoot,
+                set_pacman_arch,
+                set_pacman_cachedir,
+            },
+            mount::{
+                mount_checked,
+                MountedFolder,
+            },
         },
 };
 
+/// Overriding cachedirs from command line drops those in config, so they need
+/// to be added back before our local one
+fn set_pacman_cachedirs<'a>(
+    command: &'a mut Command, config: &crate::config::PacmanConfig, local: &Path
+) -> Result<&'a mut Command>
+{
+    if is_rootless() {
+        set_pacman_cachedir(command)?;
+    } else {
+        let mut has_cachedir = false;
+        for line in config.options.lines.iter() {
+            if let Some((key, value)) = line.split_once('=') {
+                if key.trim() == "CacheDir" {
+                    for cachedir in value.split_whitespace() {
+                        command.arg("--cachedir").arg(cachedir);
+                        has_cachedir = true
+                    }
+                }
+            }
+        }
+        if ! has_cachedir {
+            command.arg("--cachedir").arg("/var/cache/pacman/pkg");
+        }
+    }
+    Ok(command.arg("--cachedir").arg(local))
+}
+
+/// A host path bound into the chroot, read-only unless rw
+#[derive(Clone)]
+pub(crate) struct Bind {
+    host: PathBuf,
+    chroot: PathBuf,
+    rw: bool,
+}
+
+impl Bind {
+    /// The chroot path could end with `:ro` (default) or `:rw`
+    pub(crate) fn from_config(host: &str, chroot: &str) -> Result<Self> {
+        let (chroot, rw) = match chroot.rsplit_once(':') {
+            Some((chroot, "ro")) => (chroot, false),
+            Some((chroot, "rw")) => (chroot, true),
+            _ => (chroot, false),
+        };
+        let host = PathBuf::from(host);
+        let chroot = PathBuf::from(chroot);
+        if ! host.is_absolute() {
+            log::error!("Bind source '{}' is not absolute", host.display());
+            return Err(Error::InvalidConfig)
+        }
+        if ! chroot.is_absolute() || chroot.parent().is_none() ||
+            chroot.components().any(|component|
+                component == Component::ParentDir)
+        {
+            log::error!("Bind target '{}' is not an absolute path under the \
+                chroot root", chroot.display());
+            return Err(Error::InvalidConfig)
+        }
+        Ok(Self { host, chroot, rw })
+    }
+
+    pub(crate) fn chroot(&self) -> &Path {
+        &self.chroot
+    }
+}
+
+/// What an overlay root gets on top of its repo packages and home dirs
+#[derive(Clone, Copy)]
+pub(crate) struct OverlayExtras<'a> {
+    /// Installed from the packages in pkgdirs, after the repo packages
+    pub(crate) pkgs_local: &'a [String],
+    pub(crate) pkgdirs: &'a [PathBuf],
+    pub(crate) binds: &'a [Bind],
+    pub(crate) nonet: bool,
+}
+
 pub(crate) struct OverlayRoot {
     parent: PathBuf,
     upper: PathBuf,
     work: PathBuf,
     merged: MountedFolder,
+    arch: Option<String>,
 }
 
 impl OverlayRoot {
+    const LOCAL_REPO: &'static str = "arch_repo_builder_internal_do_not_use";
+
     fn remove(&self) -> Result<&Self> {
         self.merged.remove()?;
         if self.parent.exists() {
@@ -61,7 +160,8 @@ impl OverlayRoot {
             Some("overlay"),
             MsFlags::empty(),
             Some(format!(
-                "lowerdir=roots/base,upperdir={},workdir={}",
+                "lowerdir={},upperdir={},workdir={}",
+                BaseRoot::path_of(self.arch()).display(),
                 self.upper.display(),
                 self.work.display()).as_str()))
             .map_err(|e| {
@@ -130,7 +230,178 @@ impl OverlayRoot {
         Ok(self)
     }
 
-    fn new_no_init(name: &str) -> Self {
+    /// The real path, which must be under the root
+    fn real_under(root: &Path, path: &Path) -> Result<PathBuf> {
+        let real = path.canonicalize().map_err(|e|{
+            log::error!("Failed to canonicalize '{}': {}", path.display(), e);
+            Error::IoError(e)
+        })?;
+        if real.starts_with(root) {
+            Ok(real)
+        } else {
+            log::error!("'{}' escapes the chroot through symlinks, to '{}'",
+                path.display(), real.display());
+            Err(Error::InvalidConfig)
+        }
+    }
+
+    /// The mount point of a bind, created like the source if missing. Neither
+    /// it nor its existing parent could escape the root through symlinks
+    fn bind_target(root: &Path, bind: &Bind, is_dir: bool) -> Result<PathBuf> {
+        let target = root.join(
+            bind.chroot.strip_prefix("/").unwrap_or(&bind.chroot));
+        if target.symlink_metadata().is_ok() {
+            return Self::real_under(root, &target)
+        }
+        let mut existing = target.as_path();
+        while existing.symlink_metadata().is_err() {
+            match existing.parent() {
+                Some(parent) => existing = parent,
+                None => break,
+            }
+        }
+        Self::real_under(root, existing)?;
+        let dir = if is_dir {
+            target.as_path()
+        } else {
+            target.parent().unwrap_or(root)
+        };
+        if let Err(e) = create_dir_all(dir) {
+            log::error!("Failed to create dir '{}': {}", dir.display(), e);
+            return Err(Error::IoError(e))
+        }
+        if ! is_dir {
+            if let Err(e) = File::create(&target) {
+                log::error!("Failed to create mount point '{}': {}",
+                    target.display(), e);
+                return Err(Error::IoError(e))
+            }
+        }
+        Self::real_under(root, &target)
+    }
+
+    /// Bind arbitrary host paths, read-only unless rw. They should be sorted
+    /// by their chroot paths so parents are bound before their children
+    fn bind_custom(&self, binds: &[Bind]) -> Result<&Self> {
+        if binds.is_empty() {
+            return Ok(self)
+        }
+        let root = self.path().canonicalize().map_err(|e|{
+            log::error!("Failed to canonicalize root '{}': {}",
+                self.path().display(), e);
+            Error::IoError(e)
+        })?;
+        for bind in binds {
+            let metadata = bind.host.metadata().map_err(|e|{
+                log::error!("Failed to access bind source '{}': {}",
+                    bind.host.display(), e);
+                Error::IoError(e)
+            })?;
+            let target = Self::bind_target(&root, bind, metadata.is_dir())?;
+            log::info!("Binding '{}' to '{}' ({})", bind.host.display(),
+                bind.chroot.display(), if bind.rw { "rw" } else { "ro" });
+            mount_checked(Some(&bind.host),
+                &target,
+                None::<&str>,
+                MsFlags::MS_BIND,
+                None::<&str>,
+                bind.host.display(),
+                target.display()
+            )?;
+            if bind.rw {
+                continue
+            }
+            // Flags locked from the source mount must be kept, or the
+            // remount is refused in rootless mode
+            let mut flags = MsFlags::MS_BIND | MsFlags::MS_REMOUNT |
+                MsFlags::MS_RDONLY;
+            if let Ok(stat) = statvfs(&bind.host) {
+                for (fs_flag, ms_flag) in [
+                    (FsFlags::ST_NOSUID, MsFlags::MS_NOSUID),
+                    (FsFlags::ST_NODEV, MsFlags::MS_NODEV),
+                    (FsFlags::ST_NOEXEC, MsFlags::MS_NOEXEC),
+                    (FsFlags::ST_NOATIME, MsFlags::MS_NOATIME),
+                    (FsFlags::ST_NODIRATIME, MsFlags::MS_NODIRATIME),
+                    (FsFlags::ST_RELATIME, MsFlags::MS_RELATIME),
+                ] {
+                    if stat.flags().contains(fs_flag) {
+                        flags |= ms_flag
+                    }
+                }
+            }
+            mount_checked(None::<&str>,
+                &target,
+                None::<&str>,
+                flags,
+                None::<&str>,
+                "(remount read-only)",
+                target.display()
+            )?
+        }
+        Ok(self)
+    }
+
+    /// Install our own packages from pkgdirs through a local repo only visible
+    /// to this root, its DB is put into the sync DBs so no refresh is needed
+    fn install_local(&self, pkgs: &[String], pkgdirs: &[PathBuf])
+        -> Result<&Self>
+    {
+        if pkgs.is_empty() {
+            return Ok(self)
+        }
+        let local = self.parent.join("local");
+        create_dir_all(&local).map_err(|e|{
+            log::error!("Failed to create local repo dir: {}", e);
+            Error::IoError(e)
+        })?;
+        let local = local.canonicalize().map_err(|e|{
+            log::error!("Failed to canonicalize local repo dir: {}", e);
+            Error::IoError(e)
+        })?;
+        let db = crate::repo::create_local(&local, Self::LOCAL_REPO, pkgdirs)?;
+        let db_sync = self.db_path().join(
+            format!("sync/{}.db", Self::LOCAL_REPO));
+        if let Err(e) = copy(&db, &db_sync) {
+            log::error!("Failed to copy local repo DB '{}' to '{}': {}",
+                db.display(), db_sync.display(), e);
+            return Err(Error::IoError(e))
+        }
+        let content = read_to_string(self.pacman_conf()).map_err(|e|{
+            log::error!("Failed to open pacman config: {}", e);
+            Error::IoError(e)
+        })?;
+        let config = crate::config::PacmanConfig::
+            from_pacman_conf_content(&content)?;
+        let config_local = self.parent.join("pacman.conf");
+        if let Err(e) = write(&config_local, config.with_cusrepo(
+            Self::LOCAL_REPO, &local.to_string_lossy()))
+        {
+            log::error!("Failed to write pacman config for local repo: {}", e);
+            return Err(Error::IoError(e))
+        }
+        let mut command = Command::new("/usr/bin/pacman");
+        command
+            .env("LANG", "C")
+            .arg("-S")
+            .arg("--config")
+            .arg(&config_local)
+            .arg("--root")
+            .arg(self.path())
+            .arg("--dbpath")
+            .arg(self.db_path())
+            .arg("--noconfirm")
+            .arg("--needed");
+        set_pacman_arch(&mut command, self.arch());
+        set_pacman_cachedirs(&mut command, &config, &local)?;
+        command.args(pkgs);
+        crate::child::no_output_check(
+            crate::logfile::LogFile::new(
+                crate::logfile::LogType::Pacman, "install-local-packages")?
+                .set_command(&mut command)?,
+            "install local pkgs").and(Ok(self))
+    }
+
+    fn new_no_init(name: &str, arch: Option<&str>) -> Self {
         let parent = PathBuf::from(format!("roots/overlay-{}", name));
         let upper = parent.join("upper");
         let work = parent.join("work");
@@ -140,12 +411,13 @@ impl OverlayRoot {
             upper,
             work,
             merged,
+            arch: arch.map(String::from),
         }
     }
 
     fn new_child<I, S, I2, S2>(
-        name: &str, actual_identity: &IdentityActual, pkgs: I, home_dirs: I2,
-        nonet: bool
+        name: &str, arch: Option<&str>, actual_identity: &IdentityActual,
+        pkgs: I, home_dirs: I2, extras: &OverlayExtras
     ) -> Result<(Self, ForkedChild)>
     where
         I: IntoIterator<Item = S>,
@@ -154,16 +426,18 @@ impl OverlayRoot {
         S2: AsRef<str>
     {
         log::info!("Creating overlay chroot '{}'", name);
-        let root = Self::new_no_init(name);
+        let root = Self::new_no_init(name, arch);
         let child = IdentityActual::as_root_child(||{
             root.remove()?
                 .overlay()?
                 .base_mounts()?
                 .install_pkgs(pkgs)?
+                .install_local(extras.pkgs_local, extras.pkgdirs)?
                 .create_home(actual_identity)?
                 .bind_builder(actual_identity)?
-                .bind_homedirs(actual_identity, home_dirs)?;
-            if ! nonet {
+                .bind_homedirs(actual_identity, home_dirs)?
+                .bind_custom(extras.binds)?;
+            if ! extras.nonet {
                 root.resolv()?;
             }
             Ok(())
@@ -175,8 +449,8 @@ impl OverlayRoot {
     /// Different from base, overlay would have upper, work, and merged.
     /// Note that the pkgs here can only come from repos, not as raw pkg files.
     pub(crate) fn _new<I, S, I2, S2>(
-        name: &str, actual_identity: &IdentityActual, pkgs: I, home_dirs: I2,
-        nonet: bool
+        name: &str, arch: Option<&str>, actual_identity: &IdentityActual,
+        pkgs: I, home_dirs: I2, binds: &[Bind], nonet: bool
     ) -> Result<Self>
     where
         I: IntoIterator<Item = S>,
@@ -185,7 +459,7 @@ impl OverlayRoot {
         S2: AsRef<str>
     {
         log::info!("Creating overlay chroot '{}'", name);
-        let root = Self::new_no_init(name);
+        let root = Self::new_no_init(name, arch);
         IdentityActual::as_root(||{
             root.remove()?
                 .overlay()?
@@ -193,7 +467,8 @@ impl OverlayRoot {
                 .install_pkgs(pkgs)?
                 .create_home(actual_identity)?
                 .bind_builder(actual_identity)?
-                .bind_homedirs(actual_identity, home_dirs)?;
+                .bind_homedirs(actual_identity, home_dirs)?
+                .bind_custom(binds)?;
             if ! nonet {
                 root.resolv()?;
             }
@@ -214,6 +489,10 @@ impl CommonRoot for OverlayRoot {
     fn path(&self) -> &Path {
         self.merged.0.as_path()
     }
+
+    fn arch(&self) -> Option<&str> {
+        self.arch.as_deref()
+    }
 }
 
 
@@ -237,8 +516,8 @@ pub(crate) struct BootstrappingOverlayRoot {
 
 impl BootstrappingOverlayRoot {
     pub(crate) fn new<I, S, I2, S2>(
-        name: &str, actual_identity: &IdentityActual, pkgs: I, home_dirs: I2,
-        nonet: bool
+        name: &str, arch: Option<&str>, actual_identity: &IdentityActual,
+        pkgs: I, home_dirs: I2, extras: &OverlayExtras
     ) -> Result<Self>
     where
         I: IntoIterator<Item = S>,
@@ -247,7 +526,7 @@ impl BootstrappingOverlayRoot {
         S2: AsRef<str>
     {
         let (root, child) = OverlayRoot::new_child(
-            name, actual_identity, pkgs, home_dirs, nonet)?;
+            name, arch, actual_identity, pkgs, home_dirs, extras)?;
         Ok(Self {
             root,
             child,
@@ -1,13 +1,41 @@
-// TODO: Use native openpgp implementation
 use std::{
-        fs::read_dir,
-        path::Path,
+        fs::{
+            File,
+            read_dir,
+        },
+        io::BufReader,
+        path::{
+            Path,
+            PathBuf,
+        },
         process::{
             Command,
             Stdio,
         },
     };
 
+use chrono::SubsecRound;
+use pgp::{
+        composed::{
+            Deserializable,
+            SignedSecretKey,
+            SignedSecretSubKey,
+            StandaloneSignature,
+        },
+        crypto::hash::HashAlgorithm,
+        packet::{
+            SignatureConfig,
+            SignatureType,
+            Subpacket,
+            SubpacketData,
+        },
+        ser::Serialize,
+        types::{
+            PublicKeyTrait,
+            SecretKeyTrait,
+        },
+    };
+
 use crate::{
         error::{
             Error,
@@ -16,45 +44,317 @@ use crate::{
         identity::IdentityActual
     };
 
-fn sign_pkg(actual_identity: &IdentityActual, file: &Path, key: &str)
-    -> Result<()>
-{
-    crate::child::output_and_check(
-        actual_identity.set_root_drop_command(
-            Command::new("/usr/bin/gpg")
-                .arg("--detach-sign")
-                .arg("--local-user")
-                .arg(key)
-                .arg(file))
-                .stdin(Stdio::null()),
-        "to sign pkg"
-    )
-}
-
-pub(crate) fn sign_pkgs(actual_identity: &IdentityActual, dir: &Path, key: &str)
-    -> Result<()>
-{
-    let reader = match read_dir(dir) {
-        Ok(reader) => reader,
-        Err(e) => {
-            log::error!("Failed to read temp pkgdir: {}", e);
-            return Err(Error::IoError(e))
-        },
-    };
-    let mut bad = false;
-    for entry in reader {
-        let entry = match entry {
-            Ok(entry) => entry,
+enum SigningKey {
+    Primary (SignedSecretKey),
+    Subkey (SignedSecretSubKey),
+}
+
+/// A secret key loaded from a key file, used to sign without gpg
+pub(crate) struct NativeKey {
+    key: SigningKey,
+    passphrase: String,
+}
+
+fn normalize_key_id(id: &str) -> String {
+    id.trim_start_matches("0x").trim_end_matches('!')
+        .chars().filter(|c|!c.is_whitespace())
+        .collect::<String>().to_uppercase()
+}
+
+fn key_matches(key: &impl PublicKeyTrait, id: &str) -> bool {
+    hex::encode_upper(key.fingerprint().as_bytes()).ends_with(id) ||
+        format!("{:X}", key.key_id()).ends_with(id)
+}
+
+fn subkey_can_sign(subkey: &SignedSecretSubKey) -> bool {
+    subkey.is_signing_key() &&
+        subkey.signatures.iter().any(|sig|sig.key_flags().sign())
+}
+
+impl NativeKey {
+    const PASSPHRASE_ENV: &'static str = "ARB_SIGN_PASSPHRASE";
+
+    fn read_keys(path: &Path) -> Result<Vec<SignedSecretKey>> {
+        let open = ||File::open(path).map_err(|e|{
+            log::error!("Failed to open key file '{}': {}", path.display(), e);
+            Error::IoError(e)
+        });
+        let keys: std::result::Result<Vec<_>, _> =
+            match SignedSecretKey::from_armor_many(BufReader::new(open()?)) {
+                Ok((keys, _)) => keys.collect(),
+                Err(_) => SignedSecretKey::from_bytes_many(
+                    BufReader::new(open()?)).collect(),
+            };
+        match keys {
+            Ok(keys) => Ok(keys),
             Err(e) => {
-                log::error!("Failed to read entry from temp pkgdir: {}", e);
-                bad = true;
+                log::error!("Failed to parse secret keys from '{}': {}",
+                    path.display(), e);
+                Err(e.into())
+            },
+        }
+    }
+
+    /// Pick the key to sign with, like gpg: a subkey if its ID is given
+    /// explicitly and it could sign, otherwise the last signing-capable
+    /// subkey of the matching primary key, or the primary key itself
+    fn pick(keys: Vec<SignedSecretKey>, id: &str) -> Option<SigningKey> {
+        for mut key in keys {
+            if let Some(pos) = key.secret_subkeys.iter().position(
+                |subkey|key_matches(subkey, id))
+            {
+                if ! subkey_can_sign(&key.secret_subkeys[pos]) {
+                    log::error!("Subkey '{}' is not capable of signing", id);
+                    return None
+                }
+                return Some(SigningKey::Subkey(
+                    key.secret_subkeys.swap_remove(pos)))
+            }
+            if ! key_matches(&key, id) {
                 continue
+            }
+            if let Some(pos) = key.secret_subkeys.iter().rposition(
+                subkey_can_sign)
+            {
+                return Some(SigningKey::Subkey(
+                    key.secret_subkeys.swap_remove(pos)))
+            }
+            if key.is_signing_key() {
+                return Some(SigningKey::Primary(key))
+            }
+        }
+        None
+    }
+
+    fn from_file(path: &Path, id: &str) -> Result<Self> {
+        let id = normalize_key_id(id);
+        let key = match Self::pick(Self::read_keys(path)?, &id) {
+            Some(key) => key,
+            None => {
+                log::error!("Key file '{}' does not contain a secret key \
+                    capable of signing for ID '{}'", path.display(), id);
+                return Err(Error::InvalidConfig)
             },
-        }.path();
-        if entry.ends_with(".sig") {
-            continue
+        };
+        let passphrase = std::env::var(Self::PASSPHRASE_ENV)
+            .unwrap_or_default();
+        Ok(Self { key, passphrase })
+    }
+
+    fn sign_with(&self, key: &impl SecretKeyTrait, file: &Path) -> Result<()> {
+        let mut config = SignatureConfig::v4(SignatureType::Binary,
+            key.algorithm(), HashAlgorithm::SHA2_256);
+        config.hashed_subpackets = vec![
+            Subpacket::regular(SubpacketData::SignatureCreationTime(
+                chrono::Utc::now().trunc_subsecs(0))),
+            Subpacket::regular(SubpacketData::IssuerFingerprint(
+                key.fingerprint())),
+        ];
+        config.unhashed_subpackets = vec![
+            Subpacket::regular(SubpacketData::Issuer(key.key_id())),
+        ];
+        let reader = File::open(file).map_err(|e|{
+            log::error!("Failed to open '{}' to sign: {}", file.display(), e);
+            Error::IoError(e)
+        })?;
+        let signature = config.sign(key, ||self.passphrase.clone(),
+            BufReader::new(reader)).map_err(|e|{
+                log::error!("Failed to sign '{}': {}", file.display(), e);
+                Error::from(e)
+            })?;
+        let mut path_sig = file.as_os_str().to_owned();
+        path_sig.push(".sig");
+        let path_sig = PathBuf::from(path_sig);
+        let mut writer = File::create(&path_sig).map_err(|e|{
+            log::error!("Failed to create signature '{}': {}",
+                path_sig.display(), e);
+            Error::IoError(e)
+        })?;
+        StandaloneSignature::new(signature).to_writer(&mut writer).map_err(
+            |e|{
+                log::error!("Failed to write signature '{}': {}",
+                    path_sig.display(), e);
+                Error::from(e)
+            })
+    }
+
+    fn sign_file(&self, file: &Path) -> Result<()> {
+        match &self.key {
+            SigningKey::Primary(key) => self.sign_with(key, file),
+            SigningKey::Subkey(key) => self.sign_with(key, file),
         }
-        if sign_pkg(actual_identity, &entry, key).is_err() { bad = true }
     }
-    if bad { Err(Error::BadChild { pid: None, code: None }) } else { Ok(()) }
-}
\ No newline at end of file
+}
+
+/// How packages and DBs are signed: by spawning gpg with the key ID, or
+/// natively with a secret key loaded from `sign_key`
+pub(crate) enum Signer {
+    Gpg (String),
+    Native (Box<NativeKey>),
+}
+
+impl Signer {
+    pub(crate) fn new(key: &str, key_file: Option<&str>) -> Result<Self> {
+        match key_file {
+            Some(path) => {
+                let native = NativeKey::from_file(Path::new(path), key)?;
+                log::info!("Loaded signing key '{}' from '{}'", key, path);
+                Ok(Self::Native(Box::new(native)))
+            },
+            None => Ok(Self::Gpg(key.into())),
+        }
+    }
+
+    pub(crate) fn sign_file(
+        &self, actual_identity: &IdentityActual, file: &Path
+    ) -> Result<()>
+    {
+        match self {
+            Self::Gpg(key) => crate::child::output_and_check(
+                actual_identity.set_root_drop_command(
+                    Command::new("/usr/bin/gpg")
+                        .arg("--detach-sign")
+                        .arg("--local-user")
+                        .arg(key)
+                        .arg(file))
+                        .stdin(Stdio::null()),
+                "to sign file"
+            ),
+            Self::Native(key) => key.sign_file(file),
+        }
+    }
+
+    pub(crate) fn sign_pkgs(&self, actual_identity: &IdentityActual, dir: &Path)
+        -> Result<()>
+    {
+        let reader = match read_dir(dir) {
+            Ok(reader) => reader,
+            Err(e) => {
+                log::error!("Failed to read temp pkgdir: {}", e);
+                return Err(Error::IoError(e))
+            },
+        };
+        let mut files = vec![];
+        for entry in reader {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    log::error!("Failed to read entry from temp pkgdir: {}", e);
+                    return Err(Error::IoError(e))
+                },
+            }.path();
+            if entry.to_string_lossy().ends_with(".sig") {
+                continue
+            }
+            files.push(entry)
+        }
+        let results: Vec<Result<()>> = std::thread::scope(|scope|{
+            let handles: Vec<_> = files.iter().map(|file|
+                scope.spawn(||self.sign_file(actual_identity, file))
+            ).collect();
+            handles.into_iter().map(|handle|
+                handle.join().unwrap_or_else(|e|
+                    Err(Error::ThreadFailure(Some(e))))
+            ).collect()
+        });
+        results.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pgp::{
+            composed::{
+                KeyType,
+                SecretKeyParamsBuilder,
+                SubkeyParamsBuilder,
+            },
+            crypto::ecc_curve::ECCCurve,
+        };
+
+    use super::*;
+
+    /// A primary key able to sign, with a signing subkey if `subkey`, and an
+    /// encryption subkey
+    fn generate(subkey: bool) -> SignedSecretKey {
+        let mut rng = rand::thread_rng();
+        let mut subkeys = vec![
+            SubkeyParamsBuilder::default()
+                .key_type(KeyType::ECDH(ECCCurve::Curve25519))
+                .can_encrypt(true)
+                .build()
+                .unwrap()
+        ];
+        if subkey {
+            subkeys.push(SubkeyParamsBuilder::default()
+                .key_type(KeyType::EdDSALegacy)
+                .can_sign(true)
+                .build()
+                .unwrap())
+        }
+        SecretKeyParamsBuilder::default()
+            .key_type(KeyType::EdDSALegacy)
+            .can_certify(true)
+            .can_sign(true)
+            .primary_user_id("Tester <tester@example.com>".into())
+            .subkeys(subkeys)
+            .build()
+            .unwrap()
+            .generate(&mut rng)
+            .unwrap()
+            .sign(&mut rng, String::new)
+            .unwrap()
+    }
+
+    fn id(key: &impl PublicKeyTrait) -> String {
+        format!("{:X}", key.key_id())
+    }
+
+    /// Sign a buffer with the key picked for id, returns the signature
+    fn sign(key: &SignedSecretKey, id: &str, content: &[u8])
+        -> StandaloneSignature
+    {
+        let native = NativeKey {
+            key: NativeKey::pick(vec![key.clone()], id).unwrap(),
+            passphrase: String::new(),
+        };
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), content).unwrap();
+        native.sign_file(file.path()).unwrap();
+        let mut path_sig = file.path().as_os_str().to_owned();
+        path_sig.push(".sig");
+        let signature = std::fs::read(&path_sig).unwrap();
+        std::fs::remove_file(&path_sig).unwrap();
+        StandaloneSignature::from_bytes(&signature[..]).unwrap()
+    }
+
+    #[test]
+    fn native_sign_primary() {
+        let key = generate(false);
+        let public = key.public_key();
+        let signature = sign(&key, &id(&key), b"content");
+        signature.verify(&public, b"content").unwrap();
+        assert!(signature.verify(&public, b"contents").is_err());
+    }
+
+    #[test]
+    fn native_sign_subkey() {
+        let key = generate(true);
+        let subkey = key.secret_subkeys.iter().find(|subkey|
+            subkey_can_sign(subkey)).unwrap().public_key();
+        // The signing subkey is preferred over the primary key
+        let signature = sign(&key, &id(&key), b"content");
+        signature.verify(&subkey, b"content").unwrap();
+        assert!(signature.verify(&key.public_key(), b"content").is_err());
+        let signature = sign(&key, &id(&subkey), b"content");
+        signature.verify(&subkey, b"content").unwrap();
+    }
+
+    #[test]
+    fn pick_refuses_non_signing_subkey() {
+        let key = generate(true);
+        let encryption = key.secret_subkeys.iter().find(|subkey|
+            ! subkey_can_sign(subkey)).unwrap();
+        assert!(NativeKey::pick(vec![key.clone()], &id(encryption)).is_none());
+    }
+}
@@ -9,11 +9,14 @@ mod cksums;
 mod clean;
 mod download;
 mod extract;
+mod fileurl;
 pub(crate) mod git;
 mod protocol;
 mod netfile;
+mod mirror;
 mod parse;
 mod proxy;
+mod vcs;
 
 use cksums::{
     IntegFile,
@@ -33,18 +36,35 @@ use protocol::{
 };
 
 pub(crate) use parse::{
-    get_sources,
-    unique_sources
+    push_parsed_source,
+    unique_sources,
+    UniqueSources,
 };
 
 pub(crate) use cache::cache_sources_mt;
 pub(crate) use clean::{
+    cache_paths,
     cleanup,
     remove_unused,
 };
 pub(crate) use extract::extract;
+pub(crate) use mirror::Mirrors;
 pub(crate) use proxy::Proxy;
 
+/// How sources are fetched and cleaned in a run
+#[derive(Clone, Copy)]
+pub(crate) struct FetchSettings<'a> {
+    pub(crate) holdgit: bool,
+    pub(crate) urlcache: bool,
+    pub(crate) holdurl: bool,
+    pub(crate) skipint: bool,
+    pub(crate) noclean: bool,
+    pub(crate) proxy: Option<&'a Proxy>,
+    pub(crate) gmr: Option<&'a git::Gmr>,
+    pub(crate) mirrors: &'a Mirrors,
+    pub(crate) terminal: bool,
+}
+
 #[derive(Clone)]
 pub(crate) struct Source {
     name: String,
@@ -69,12 +89,16 @@ pub(crate) trait MapByDomain {
     {
         let mut map = HashMap::new();
         for source in sources.iter() {
-            let url =
-                url::Url::from_str(source.url())
-                .expect("Failed to parse URL");
-            let domain = xxhash_rust::xxh3::xxh3_64(
-                url.domain().expect("Failed to get domain")
-                .as_bytes());
+            // URLs without a domain, e.g. svn+ssh with IP or bzr lp:, are
+            // all put under the same dummy domain
+            let domain = match url::Url::from_str(source.url()) {
+                Ok(url) => match url.domain() {
+                    Some(domain) => xxhash_rust::xxh3::xxh3_64(
+                        domain.as_bytes()),
+                    None => 0,
+                },
+                Err(_) => 0,
+            };
             if ! map.contains_key(&domain) {
                 map.insert(domain, vec![]);
             }
@@ -87,6 +111,15 @@ pub(crate) trait MapByDomain {
     }
 }
 
+impl Source {
+    /// Whether the source has no integrity checksum at all, i.e. all SKIP
+    fn no_integ(&self) -> bool {
+        self.ck.is_none() && self.md5.is_none() && self.sha1.is_none() &&
+        self.sha224.is_none() && self.sha256.is_none() &&
+        self.sha384.is_none() && self.sha512.is_none() && self.b2.is_none()
+    }
+}
+
 impl MapByDomain for Source {
     fn url(&self) -> &str {
         self.url.as_str()
@@ -44,7 +44,7 @@ impl AurResult {
                 Ok(response) => response,
                 Err(e) => {
                     log::error!("Failed to call AUR: {}", e);
-                    last_error = Error::UreqError(e);
+                    last_error = Error::UreqError(e.into());
                     continue
                 },
             };
@@ -11,13 +11,16 @@ use crate::{
             Error,
             Result
         },
+        report,
         source::{
             cksums::IntegFile,
+            FetchSettings,
+            fileurl,
             git::ToReposMap,
             MapByDomain,
             netfile,
-            Proxy,
             Source,
+            vcs::VcsCache,
         }
     };
 
@@ -53,27 +56,32 @@ fn get_domain_threads_from_map<'a>(
 pub(crate) fn cache_sources_mt(
     netfile_sources: &Vec<Source>,
     git_sources: &Vec<Source>,
+    vcs_sources: &Vec<Source>,
     actual_identity: &crate::identity::IdentityActual,
-    holdgit: bool,
-    skipint: bool,
-    proxy: Option<&Proxy>,
-    gmr: Option<&super::git::Gmr>,
-    terminal: bool
+    fetch: &FetchSettings
 ) -> Result<()>
 {
+    let FetchSettings { holdgit, holdurl, skipint, proxy, gmr, mirrors,
+        terminal, .. } = *fetch;
     let mut netfile_sources_map =
         Source::map_by_domain(netfile_sources);
     let git_sources_map =
         Source::map_by_domain(git_sources);
+    let mut vcs_sources_map =
+        Source::map_by_domain(vcs_sources);
     let mut netfile_threads_map = 
         get_domain_threads_map(&netfile_sources_map)?;
     let mut git_threads_map =
         get_domain_threads_map(&git_sources_map)?;
+    let mut vcs_threads_map =
+        get_domain_threads_map(&vcs_sources_map)?;
     let mut git_repos_map =
         Source::to_repos_map(git_sources_map, "sources/git", gmr)?;
     const MAX_THREADS: usize = 10;
     let mut bad = false;
-    while netfile_sources_map.len() > 0 || git_repos_map.len() > 0 {
+    while netfile_sources_map.len() > 0 || git_repos_map.len() > 0 ||
+        vcs_sources_map.len() > 0
+    {
         for (domain, netfile_sources) in
             netfile_sources_map.iter_mut()
         {
@@ -87,14 +95,25 @@ pub(crate) fn cache_sources_mt(
                     .expect("Failed to get source from sources vec");
                 let integ_files
                     = IntegFile::vec_from_source(&netfile_source);
+                let mirror_urls = mirrors.urls(&netfile_source.url);
                 let proxy_thread = proxy
                     .map(|proxy|proxy.to_owned());
                 let actual_identity_thread = actual_identity.clone();
                 let netfile_thread = thread::spawn(
                 move ||{
-                    netfile::cache_source(&netfile_source, &integ_files,
-                         &actual_identity_thread, skipint,
-                         proxy_thread.as_ref())
+                    let r = if integ_files.is_empty() {
+                        fileurl::cache_source(&netfile_source,
+                            &actual_identity_thread, holdurl,
+                            proxy_thread.as_ref())
+                    } else {
+                        netfile::cache_source(&netfile_source, &integ_files,
+                            &actual_identity_thread, skipint,
+                            proxy_thread.as_ref(), &mirror_urls)
+                    };
+                    if r.is_err() {
+                        report::source_failed(&netfile_source.url)
+                    }
+                    r
                 });
                 netfile_threads.push(netfile_thread);
             }
@@ -120,6 +139,36 @@ pub(crate) fn cache_sources_mt(
                 git_threads.push(git_thread);
             }
         }
+        for (domain, vcs_sources) in
+            vcs_sources_map.iter_mut()
+        {
+            let vcs_threads =
+                get_domain_threads_from_map(domain, &mut vcs_threads_map)?;
+            while vcs_sources.len() > 0 &&
+                vcs_threads.len() < MAX_THREADS
+            {
+                let vcs_source = vcs_sources
+                    .pop()
+                    .expect("Failed to get source from sources vec");
+                let vcs_cache = VcsCache::from_source(&vcs_source)?;
+                if holdgit && vcs_cache.healthy() {
+                    continue
+                }
+                let proxy_thread = proxy
+                    .map(|proxy|proxy.to_owned());
+                let actual_identity_thread = actual_identity.clone();
+                let vcs_thread = thread::spawn(
+                move ||{
+                    let r = vcs_cache.sync(
+                        &actual_identity_thread, proxy_thread.as_ref());
+                    if r.is_err() {
+                        report::source_failed(&vcs_source.url)
+                    }
+                    r
+                });
+                vcs_threads.push(vcs_thread);
+            }
+        }
         if let Err(_) = threading::wait_thread_map(
             &mut netfile_threads_map, "caching netfile sources") {
                 bad = true
@@ -128,10 +177,16 @@ pub(crate) fn cache_sources_mt(
             &mut git_threads_map, "caching git sources") {
                 bad = true
             }
+        if let Err(_) = threading::wait_thread_map(
+            &mut vcs_threads_map, "caching other VCS sources") {
+                bad = true
+            }
         netfile_sources_map.retain(
             |_, sources| sources.len() > 0);
         git_repos_map.retain(
             |_, repos| repos.len() > 0);
+        vcs_sources_map.retain(
+            |_, sources| sources.len() > 0);
     }
     let mut remaining_threads = vec![];
     for mut threads in
@@ -144,6 +199,11 @@ pub(crate) fn cache_sources_mt(
     {
         remaining_threads.append(&mut threads);
     }
+    for mut threads in
+        vcs_threads_map.into_values()
+    {
+        remaining_threads.append(&mut threads);
+    }
     match threading::wait_remaining(remaining_threads, "caching sources") {
         Ok(_) => (),
         Err(_) => bad = true,
@@ -2,6 +2,7 @@ mod ck;
 mod crypto;
 mod integ;
 mod md5;
+mod summer;
 
 const BUFFER_SIZE: usize = 0x400000; // 4M
 
@@ -16,6 +17,7 @@ pub(super) use crypto::{
 };
 pub(super) use md5::Md5sum;
 pub(super) use integ::IntegFile;
+pub(super) use summer::Summer;
 
 
 pub(super) trait Sum {
@@ -1,7 +1,8 @@
 use crc;
 use std::io::Read;
 
-const CKSUM: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_CKSUM);
+pub(super) static CKSUM: crc::Crc<u32> =
+    crc::Crc::<u32>::new(&crc::CRC_32_CKSUM);
 
 #[derive(PartialEq, Clone)]
 pub(crate) struct Cksum (pub(crate) u32);
@@ -26,17 +27,7 @@ impl super::Sum for Cksum {
             digest.update(chunk);
             size_total += size_chunk;
         }
-        let mut size_oct = Vec::<u8>::new();
-        if size_total > 0 {
-            while size_total > 0 {
-                size_oct.push((size_total & 0xFF).try_into().unwrap());
-                size_total >>= 8;
-            }
-        } else {
-            size_oct.push(0);
-        }
-        digest.update(&size_oct);
-        Some(Self(digest.finalize()))
+        Some(finalize(digest, size_total))
     }
 
     fn from_hex(hex: &[u8]) -> Option<Self> {
@@ -44,6 +35,24 @@ impl super::Sum for Cksum {
     }
 }
 
+/// Cksum appends the length in octets after the content
+pub(super) fn finalize(
+    mut digest: crc::Digest<'static, u32>, mut size_total: usize
+) -> Cksum
+{
+    let mut size_oct = Vec::<u8>::new();
+    if size_total > 0 {
+        while size_total > 0 {
+            size_oct.push((size_total & 0xFF).try_into().unwrap());
+            size_total >>= 8;
+        }
+    } else {
+        size_oct.push(0);
+    }
+    digest.update(&size_oct);
+    Cksum(digest.finalize())
+}
+
 impl std::fmt::Display for Cksum {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.0)
@@ -20,17 +20,17 @@ use std::{
     };
 
 #[derive(PartialEq, Clone)]
-pub(crate) struct Sha1sum ([u8; 20]);
+pub(crate) struct Sha1sum (pub(super) [u8; 20]);
 #[derive(PartialEq, Clone)]
-pub(crate) struct Sha224sum ([u8; 28]);
+pub(crate) struct Sha224sum (pub(super) [u8; 28]);
 #[derive(PartialEq, Clone)]
-pub(crate) struct Sha256sum ([u8; 32]);
+pub(crate) struct Sha256sum (pub(super) [u8; 32]);
 #[derive(PartialEq, Clone)]
-pub(crate) struct Sha384sum ([u8; 48]);
+pub(crate) struct Sha384sum (pub(super) [u8; 48]);
 #[derive(PartialEq, Clone)]
-pub(crate) struct Sha512sum ([u8; 64]);
+pub(crate) struct Sha512sum (pub(super) [u8; 64]);
 #[derive(PartialEq, Clone)]
-pub(crate) struct B2sum ([u8; 64]);
+pub(crate) struct B2sum (pub(super) [u8; 64]);
 
 
 fn generic_sum<T: Digest + OutputSizeUser>(file: &mut File)
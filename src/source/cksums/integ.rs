@@ -110,7 +110,9 @@ impl IntegFile {
         return valid;
     }
 
-    pub(crate) fn clone_file_from(&self, source: &Self) -> Result<()> {
+    pub(crate) fn clone_file_from(&self, source: &Self, check: bool)
+        -> Result<()>
+    {
         if let Err(e) = super::super::download::clone_file(
             &source.path, &self.path)
         {
@@ -120,7 +122,7 @@ impl IntegFile {
                         e);
             return Err(e)
         }
-        if self.valid(false) {
+        if ! check || self.valid(false) {
             Ok(())
         } else {
             log::error!("Cloned integ file not healthy");
@@ -144,7 +146,7 @@ impl IntegFile {
             },
         }
         // Failed to move, then do light copy (hard link) or read+write copy
-        if let Err(e) = self.clone_file_from(&source) {
+        if let Err(e) = self.clone_file_from(&source, true) {
             log::error!("Failed to clone '{}' from '{}'",
                 self.path.display(), source.path.display(),);
             return Err(e)
@@ -2,7 +2,7 @@ use std::io::Read;
 use hex::FromHex;
 
 #[derive(PartialEq, Clone)]
-pub(crate) struct Md5sum ([u8; 16]);
+pub(crate) struct Md5sum (pub(super) [u8; 16]);
 
 impl super::Sum for Md5sum {
     fn sum(file: &mut std::fs::File) -> Option<Self> {
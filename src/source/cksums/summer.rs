@@ -0,0 +1,231 @@
+use std::{
+        fs::File,
+        io::Read,
+        path::Path,
+    };
+
+use blake2::Blake2b512;
+use sha1::{
+        Digest,
+        Sha1,
+    };
+use sha2::{
+        Sha224,
+        Sha256,
+        Sha384,
+        Sha512,
+    };
+
+use crate::source::Source;
+
+use super::{
+        ck,
+        B2sum,
+        Md5sum,
+        Sha1sum,
+        Sha224sum,
+        Sha256sum,
+        Sha384sum,
+        Sha512sum,
+    };
+
+/// All integrity checksums of a source computed in one pass, updated while
+/// the data is streamed in
+#[derive(Default)]
+pub(crate) struct Summer {
+    ck: Option<(crc::Digest<'static, u32>, usize)>,
+    md5: Option<md5::Context>,
+    sha1: Option<Sha1>,
+    sha224: Option<Sha224>,
+    sha256: Option<Sha256>,
+    sha384: Option<Sha384>,
+    sha512: Option<Sha512>,
+    b2: Option<Blake2b512>,
+}
+
+fn compare<S: PartialEq + std::fmt::Display>(
+    name: &str, expected: &Option<S>, actual: Option<S>
+) -> bool
+{
+    match (expected, actual) {
+        (Some(expected), Some(actual)) => if expected == &actual {
+            true
+        } else {
+            log::error!("Mismatched {}: expected {}, got {}",
+                name, expected, actual);
+            false
+        },
+        _ => true,
+    }
+}
+
+impl Summer {
+    pub(crate) fn from_source(source: &Source) -> Self {
+        Self {
+            ck: source.ck.as_ref().map(|_|(ck::CKSUM.digest(), 0)),
+            md5: source.md5.as_ref().map(|_|md5::Context::new()),
+            sha1: source.sha1.as_ref().map(|_|Sha1::new()),
+            sha224: source.sha224.as_ref().map(|_|Sha224::new()),
+            sha256: source.sha256.as_ref().map(|_|Sha256::new()),
+            sha384: source.sha384.as_ref().map(|_|Sha384::new()),
+            sha512: source.sha512.as_ref().map(|_|Sha512::new()),
+            b2: source.b2.as_ref().map(|_|Blake2b512::new()),
+        }
+    }
+
+    pub(crate) fn update(&mut self, chunk: &[u8]) {
+        if let Some((digest, size)) = &mut self.ck {
+            digest.update(chunk);
+            *size += chunk.len();
+        }
+        if let Some(context) = &mut self.md5 {
+            context.consume(chunk)
+        }
+        if let Some(hasher) = &mut self.sha1 {
+            hasher.update(chunk)
+        }
+        if let Some(hasher) = &mut self.sha224 {
+            hasher.update(chunk)
+        }
+        if let Some(hasher) = &mut self.sha256 {
+            hasher.update(chunk)
+        }
+        if let Some(hasher) = &mut self.sha384 {
+            hasher.update(chunk)
+        }
+        if let Some(hasher) = &mut self.sha512 {
+            hasher.update(chunk)
+        }
+        if let Some(hasher) = &mut self.b2 {
+            hasher.update(chunk)
+        }
+    }
+
+    /// Feed the whole existing file, e.g. the part downloaded before resuming
+    pub(crate) fn update_from_file(&mut self, path: &Path) -> bool {
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) => {
+                log::error!("Failed to open file '{}': {}", path.display(), e);
+                return false
+            },
+        };
+        let mut buffer = vec![0; super::BUFFER_SIZE];
+        loop {
+            let size_chunk = match file.read(&mut buffer) {
+                Ok(size) => size,
+                Err(e) => {
+                    log::error!("Failed to read file '{}': {}",
+                        path.display(), e);
+                    return false
+                },
+            };
+            if size_chunk == 0 {
+                return true
+            }
+            self.update(&buffer[0..size_chunk])
+        }
+    }
+
+    /// Finish all checksums and compare them against those of the source
+    pub(crate) fn verify(self, source: &Source) -> bool {
+        let mut valid = compare("cksum", &source.ck, self.ck.map(
+            |(digest, size)|ck::finalize(digest, size)));
+        valid &= compare("md5sum", &source.md5, self.md5.map(
+            |context|Md5sum(context.compute().0)));
+        valid &= compare("sha1sum", &source.sha1, self.sha1.map(
+            |hasher|Sha1sum(hasher.finalize().into())));
+        valid &= compare("sha224sum", &source.sha224, self.sha224.map(
+            |hasher|Sha224sum(hasher.finalize().into())));
+        valid &= compare("sha256sum", &source.sha256, self.sha256.map(
+            |hasher|Sha256sum(hasher.finalize().into())));
+        valid &= compare("sha384sum", &source.sha384, self.sha384.map(
+            |hasher|Sha384sum(hasher.finalize().into())));
+        valid &= compare("sha512sum", &source.sha512, self.sha512.map(
+            |hasher|Sha512sum(hasher.finalize().into())));
+        valid &= compare("b2sum", &source.b2, self.b2.map(
+            |hasher|B2sum(hasher.finalize().into())));
+        valid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{
+            Seek,
+            Write,
+        };
+
+    use crate::source::protocol::{
+            NetfileProtocol,
+            Protocol,
+        };
+
+    use super::{
+            super::{
+                Cksum,
+                Sum,
+            },
+            *,
+        };
+
+    fn sum<S: Sum>(file: &mut File) -> Option<S> {
+        file.rewind().unwrap();
+        S::sum(file)
+    }
+
+    /// A source with all checksums of the content, from the file ones
+    fn source(content: &[u8]) -> Source {
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(content).unwrap();
+        Source {
+            name: String::from("file"),
+            protocol: Protocol::Netfile { protocol: NetfileProtocol::Http },
+            url: String::from("http://example.com/file"),
+            hash_url: 0,
+            ck: sum(&mut file),
+            md5: sum(&mut file),
+            sha1: sum(&mut file),
+            sha224: sum(&mut file),
+            sha256: sum(&mut file),
+            sha384: sum(&mut file),
+            sha512: sum(&mut file),
+            b2: sum(&mut file),
+        }
+    }
+
+    fn summed(source: &Source, content: &[u8], chunk: usize) -> Summer {
+        let mut summer = Summer::from_source(source);
+        for chunk in content.chunks(chunk) {
+            summer.update(chunk)
+        }
+        summer
+    }
+
+    #[test]
+    fn summer_matches_sums() {
+        let content: Vec<u8> = (0..100000u32).map(|i|(i % 251) as u8)
+            .collect();
+        for content in [&content[..1], &content[..255], &content[..256],
+            &content[..]]
+        {
+            let source = source(content);
+            for chunk in [1, 7, 4096, content.len()] {
+                assert!(summed(&source, content, chunk).verify(&source));
+            }
+            let mut corrupted = content.to_vec();
+            corrupted[0] ^= 1;
+            assert!(! summed(&source, &corrupted, 4096).verify(&source));
+            assert!(! summed(&source, &content[1..], 4096).verify(&source));
+        }
+    }
+
+    #[test]
+    fn summer_matches_sums_empty() {
+        let source = source(b"");
+        // What `cksum` prints for an empty input
+        assert!(source.ck == Some(Cksum(4294967295)));
+        assert!(Summer::from_source(&source).verify(&source));
+        assert!(! summed(&source, b"\0", 1).verify(&source));
+    }
+}
@@ -1,17 +1,28 @@
 use std::{
+        collections::HashMap,
         fs::{
             read_dir,
             remove_dir_all,
             remove_file,
         },
-        path::Path,
+        path::{
+            Path,
+            PathBuf,
+        },
         thread::{
             self,
             JoinHandle,
         }
     };
 use xxhash_rust::xxh3::xxh3_64;
-use crate::source::Source;
+use crate::source::{
+        cksums::IntegFile,
+        fileurl,
+        Protocol,
+        Source,
+        vcs,
+        VcsProtocol,
+    };
 
 // Used must be already sorted
 pub(crate) fn remove_unused<P: AsRef<Path>>(dir: P, used: &Vec<String>) {
@@ -57,8 +68,14 @@ fn clean_netfile_sources(sources: &Vec<Source>) -> Vec<JoinHandle<()>>{
     let mut sha384_used = vec![];
     let mut sha512_used = vec![];
     let mut b2_used = vec![];
+    let mut url_used = vec![];
     let mut cleaners = vec![];
     for source in sources.iter() {
+        if source.no_integ() {
+            url_used.push(fileurl::cache_name(source));
+            url_used.push(fileurl::validator_name(source));
+            continue
+        }
         if let Some(ck) = &source.ck {
             ck_used.push(ck.to_string());
         }
@@ -108,6 +125,9 @@ fn clean_netfile_sources(sources: &Vec<Source>) -> Vec<JoinHandle<()>>{
     b2_used.sort_unstable();
     cleaners.push(thread::spawn(move ||
         remove_unused("sources/file-b2", &b2_used)));
+    url_used.sort_unstable();
+    cleaners.push(thread::spawn(move ||
+        remove_unused(fileurl::PARENT, &url_used)));
     cleaners
 }
 
@@ -120,11 +140,61 @@ fn clean_git_sources(sources: &Vec<Source>) {
     remove_unused("sources/git", &used);
 }
 
-pub(crate) fn cleanup(netfile_sources: Vec<Source>, git_sources: Vec<Source>)
-    -> Vec<JoinHandle<()>>
+fn clean_vcs_sources(sources: &Vec<Source>) {
+    let mut used_map: HashMap<&str, Vec<String>> = HashMap::new();
+    for parent in vcs::PARENTS {
+        used_map.insert(parent, vec![]);
+    }
+    for source in sources.iter() {
+        if let Protocol::Vcs { protocol } = &source.protocol {
+            if let Some(used) = used_map.get_mut(vcs::parent(protocol)) {
+                used.push(format!("{:016x}", xxh3_64(source.url.as_bytes())))
+            }
+        }
+    }
+    for (parent, mut used) in used_map {
+        used.sort_unstable();
+        remove_unused(parent, &used);
+    }
+}
+
+pub(crate) fn cleanup(
+    netfile_sources: Vec<Source>, git_sources: Vec<Source>,
+    vcs_sources: Vec<Source>
+) -> Vec<JoinHandle<()>>
 {
     let mut cleaners =
         clean_netfile_sources(&netfile_sources);
     cleaners.push(thread::spawn(move||clean_git_sources(&git_sources)));
+    cleaners.push(thread::spawn(move||clean_vcs_sources(&vcs_sources)));
     cleaners
-}
\ No newline at end of file
+}
+
+/// Paths of the existing caches of the sources, e.g. to bundle them
+pub(crate) fn cache_paths(
+    netfile_sources: &[Source], git_sources: &[Source],
+    vcs_sources: &[Source]
+) -> Vec<PathBuf>
+{
+    let mut paths = vec![];
+    for source in netfile_sources.iter() {
+        if source.no_integ() {
+            paths.push(fileurl::cache_path(source));
+            paths.push(PathBuf::from(fileurl::PARENT).join(
+                fileurl::validator_name(source)));
+        } else {
+            paths.extend(IntegFile::vec_from_source(source).into_iter().map(
+                |integ_file|integ_file.path));
+        }
+    }
+    for source in git_sources.iter() {
+        paths.push(vcs::cache_path(&VcsProtocol::Git, &source.url))
+    }
+    for source in vcs_sources.iter() {
+        if let Protocol::Vcs { protocol } = &source.protocol {
+            paths.push(vcs::cache_path(protocol, &source.url))
+        }
+    }
+    paths.retain(|path|path.exists());
+    paths
+}
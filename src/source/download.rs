@@ -11,6 +11,9 @@ pub(crate) use file::{
     file
 };
 pub(crate) use ftp::ftp;
-pub(crate) use http::http;
+pub(crate) use http::{
+    http,
+    Validator,
+};
 pub(crate) use rsync::rsync;
 pub(crate) use scp::scp;
\ No newline at end of file
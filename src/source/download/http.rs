@@ -1,60 +1,336 @@
 use std::{
-        fs::File,
-        io::Read,
+        fs::{
+            File,
+            OpenOptions,
+            remove_file,
+        },
+        io::{
+            Read,
+            Write,
+        },
         path::Path,
     };
 
-use crate::error::{
-        Error,
-        Result,
+use serde::{
+        Deserialize,
+        Serialize,
     };
 
-pub(crate) fn http(url: &str, path: &Path, proxy: Option<&str>)
-    -> Result<()>
-{
-    let mut target = match File::create(path) {
-        Ok(target) => target,
-        Err(e) => {
-            log::error!("Failed to open {} as write-only: {}",
-                        path.display(), e);
-            return Err(Error::IoError(e))
+use crate::{
+        error::{
+            Error,
+            Result,
         },
+        source::cksums::Summer,
+    };
+
+/// Validators of a HTTP response, to revalidate the file later with
+/// conditional requests
+#[derive(Default, Debug, Serialize, Deserialize)]
+pub(crate) struct Validator {
+    pub(crate) etag: Option<String>,
+    pub(crate) last_modified: Option<String>,
+}
+
+impl Validator {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+fn malformed(url: &str, name: &str, value: &str) -> Error {
+    log::error!("Malformed header '{}: {}' in response from '{}'",
+        name, value, url);
+    Error::MalformedHeader(format!("{}: {}", name, value))
+}
+
+/// The start offset of `Content-Range: bytes [start]-[end]/[total]`
+fn content_range_start(url: &str, value: &str) -> Result<u64> {
+    value.strip_prefix("bytes ")
+        .and_then(|range|range.split_once('-'))
+        .and_then(|(start, _)|start.parse().ok())
+        .ok_or_else(||malformed(url, "content-range", value))
+}
+
+/// Drop the partial file and download again without resuming
+fn restart(
+    url: &str, path: &Path, proxy: Option<&str>, validator: Option<&Validator>,
+    summer: &mut Summer
+) -> Result<Option<Validator>>
+{
+    remove_file(path).map_err(|e|{
+        log::error!("Failed to remove partial file '{}': {}",
+            path.display(), e);
+        Error::IoError(e)
+    })?;
+    http(url, path, proxy, validator, false, summer)
+}
+
+/// Download url into path. If validator is set, the request is conditional,
+/// and Ok(None) is returned with nothing written if the remote file is not
+/// modified; otherwise Ok(Some) with the validator of the new file.
+/// If resume is set, an existing partial file is continued with a range
+/// request. The content, including the already downloaded part, is fed into
+/// summer while streaming, so it should be a fresh one.
+pub(crate) fn http(
+    url: &str, path: &Path, proxy: Option<&str>, validator: Option<&Validator>,
+    resume: bool, summer: &mut Summer
+) -> Result<Option<Validator>>
+{
+    let offset = match resume {
+        true => path.metadata().map(|metadata|metadata.len()).unwrap_or(0),
+        false => 0,
     };
-    let response = match proxy {
+    let mut request = match proxy {
         Some(proxy) => {
             let proxy_opt = ureq::Proxy::new(proxy).map_err(|e|
             {
                 log::error!("Failed to create proxy from '{}': {}", proxy, e);
-                Error::UreqError(e)
+                Error::UreqError(e.into())
             })?;
             ureq::AgentBuilder::new().proxy(proxy_opt).build().get(url)
         },
         None => ureq::get(url),
-    }.call().map_err(
-        |e|{
+    };
+    if let Some(validator) = validator {
+        if let Some(etag) = &validator.etag {
+            request = request.set("If-None-Match", etag)
+        }
+        if let Some(last_modified) = &validator.last_modified {
+            request = request.set("If-Modified-Since", last_modified)
+        }
+    }
+    if offset > 0 {
+        log::info!("Resuming download of '{}' into '{}' from {} bytes",
+            url, path.display(), offset);
+        request = request.set("Range", &format!("bytes={}-", offset))
+    }
+    let response = match request.call() {
+        Ok(response) => response,
+        Err(ureq::Error::Status(416, _)) if offset > 0 => {
+            log::warn!("Remote refused to resume '{}' from {} bytes, \
+                restarting from scratch", url, offset);
+            return restart(url, path, proxy, validator, summer)
+        },
+        Err(e) => {
             log::error!("Failed to GET url '{}': {}", url, e);
-            Error::UreqError(e)
-        })?;
-    let len = match response.header("content-length") {
-        Some(len) => len.parse().unwrap(),
-        None => {
-            log::info!("Warning: response does not have 'content-length', limit \
-                max download size to 4GiB");
-            0x100000000
-        }
-    };
-    match std::io::copy(
-        &mut response.into_reader().take(len), &mut target)
-    {
-        Ok(size) => {
-            log::info!("Downloaded {} bytes from '{}' into '{}'",
-                size, url, path.display());
-            Ok(())
+            return Err(Error::UreqError(e.into()))
         },
+    };
+    if response.status() == 304 {
+        log::info!("Remote file '{}' not modified", url);
+        return Ok(None)
+    }
+    let len: Option<u64> = match response.header("content-length") {
+        Some(len) => Some(len.parse().map_err(
+            |_|malformed(url, "content-length", len))?),
+        None => None,
+    };
+    let validator_remote = Validator {
+        etag: response.header("etag").map(|etag|etag.to_string()),
+        last_modified: response.header("last-modified").map(
+            |last_modified|last_modified.to_string()),
+    };
+    let target = if response.status() == 206 {
+        let value = response.header("content-range").unwrap_or_default();
+        let start = content_range_start(url, value)?;
+        if start != offset {
+            log::warn!("Remote '{}' resumed from {} bytes instead of {}, \
+                restarting from scratch", url, start, offset);
+            return restart(url, path, proxy, validator, summer)
+        }
+        if ! summer.update_from_file(path) {
+            return Err(Error::IntegrityError)
+        }
+        OpenOptions::new().append(true).open(path)
+    } else {
+        if offset > 0 {
+            log::warn!("Remote '{}' does not support resuming, restarting \
+                from scratch", url);
+        }
+        File::create(path)
+    };
+    let mut target = match target {
+        Ok(target) => target,
         Err(e) => {
-            log::error!("Failed to copy download '{}' into '{}': {}",
-                        url, path.display(), e);
-            Err(Error::IoError(e))
+            log::error!("Failed to open {} as write-only: {}",
+                        path.display(), e);
+            return Err(Error::IoError(e))
         },
+    };
+    let mut reader = response.into_reader();
+    let mut buffer = vec![0; super::BUFFER_SIZE];
+    let mut size = 0;
+    loop {
+        let size_chunk = match reader.read(&mut buffer) {
+            Ok(size) => size,
+            Err(e) => {
+                log::error!("Failed to read download '{}' after {} bytes: {}",
+                            url, size, e);
+                return Err(Error::IoError(e))
+            },
+        };
+        if size_chunk == 0 {
+            break
+        }
+        let chunk = &buffer[0..size_chunk];
+        if let Err(e) = target.write_all(chunk) {
+            log::error!("Failed to write download '{}' into '{}': {}",
+                        url, path.display(), e);
+            return Err(Error::IoError(e))
+        }
+        summer.update(chunk);
+        size += size_chunk as u64;
+    }
+    if let Some(len) = len {
+        if size != len {
+            log::error!("Download '{}' ended at {} bytes while {} expected",
+                url, size, len);
+            return Err(Error::IoError(std::io::ErrorKind::UnexpectedEof.into()))
+        }
+    }
+    log::info!("Downloaded {} bytes from '{}' into '{}'",
+        size, url, path.display());
+    Ok(Some(validator_remote))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+            io::{
+                BufRead,
+                BufReader,
+                Seek,
+            },
+            net::TcpListener,
+            sync::{
+                Arc,
+                Mutex,
+            },
+        };
+
+    use crate::source::{
+            cksums::{
+                Sha256sum,
+                Sum,
+            },
+            protocol::{
+                NetfileProtocol,
+                Protocol,
+            },
+            Source,
+        };
+
+    use super::*;
+
+    #[test]
+    fn content_range_valid() {
+        assert_eq!(content_range_start("url", "bytes 0-9/10").unwrap(), 0);
+        assert_eq!(content_range_start("url", "bytes 100-199/200").unwrap(),
+            100);
+        assert_eq!(content_range_start("url", "bytes 100-199/*").unwrap(),
+            100);
+    }
+
+    #[test]
+    fn content_range_malformed() {
+        for value in ["", "bytes", "bytes */200", "bytes -199/200",
+            "bytes a-199/200", "items 100-199/200", "100-199/200"]
+        {
+            assert!(matches!(content_range_start("url", value),
+                Err(Error::MalformedHeader(_))), "{}", value);
+        }
+    }
+
+    /// Serve content in the background, a range request is answered from
+    /// `shift` bytes after the requested start, returns the URL and the log
+    /// of the Range headers received
+    fn serve(content: Vec<u8>, shift: usize)
+        -> (String, Arc<Mutex<Vec<Option<String>>>>)
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/file", listener.local_addr().unwrap());
+        let log = Arc::new(Mutex::new(vec![]));
+        let log_thread = log.clone();
+        std::thread::spawn(move||for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => break,
+            };
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut range = None;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let line = line.trim_end();
+                if line.is_empty() {
+                    break
+                }
+                if let Some((key, value)) = line.split_once(':') {
+                    if key.eq_ignore_ascii_case("range") {
+                        range = Some(value.trim().to_string())
+                    }
+                }
+            }
+            let start = range.as_deref()
+                .and_then(|range|range.strip_prefix("bytes="))
+                .and_then(|range|range.strip_suffix('-'))
+                .map(|start|start.parse::<usize>().unwrap() + shift);
+            log_thread.lock().unwrap().push(range);
+            let (head, body) = match start {
+                Some(start) => (format!("206 Partial Content\r\n\
+                    Content-Range: bytes {}-{}/{}", start, content.len() - 1,
+                    content.len()), &content[start..]),
+                None => (String::from("200 OK"), &content[..]),
+            };
+            write!(stream, "HTTP/1.1 {}\r\nContent-Length: {}\r\n\
+                Connection: close\r\n\r\n", head, body.len()).unwrap();
+            stream.write_all(body).unwrap();
+        });
+        (url, log)
+    }
+
+    /// Resume a download of which the first `partial` bytes are already
+    /// there, returns whether the whole content is verified and the log
+    fn resume(partial: usize, shift: usize)
+        -> (bool, Vec<Option<String>>)
+    {
+        let content: Vec<u8> = (0..100000u32).map(|i|(i % 251) as u8)
+            .collect();
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&content).unwrap();
+        file.rewind().unwrap();
+        let source = Source {
+            name: String::from("file"),
+            protocol: Protocol::Netfile { protocol: NetfileProtocol::Http },
+            url: String::from("http://example.com/file"),
+            hash_url: 0,
+            ck: None,
+            md5: None,
+            sha1: None,
+            sha224: None,
+            sha256: Sha256sum::sum(file.as_file_mut()),
+            sha384: None,
+            sha512: None,
+            b2: None,
+        };
+        std::fs::write(file.path(), &content[..partial]).unwrap();
+        let (url, log) = serve(content.clone(), shift);
+        let mut summer = Summer::from_source(&source);
+        http(&url, file.path(), None, None, true, &mut summer).unwrap();
+        assert_eq!(std::fs::read(file.path()).unwrap(), content);
+        let log = log.lock().unwrap().clone();
+        (summer.verify(&source), log)
+    }
+
+    #[test]
+    fn resume_partial() {
+        assert_eq!(resume(1000, 0), (true, vec![Some("bytes=1000-".into())]));
+    }
+
+    #[test]
+    fn resume_wrong_offset() {
+        assert_eq!(resume(1000, 10),
+            (true, vec![Some("bytes=1000-".into()), None]));
     }
-}
\ No newline at end of file
+}
@@ -3,12 +3,9 @@ use std::path::{
         PathBuf,
     };
 
-use xxhash_rust::xxh3::xxh3_64;
-
 use crate::{
         error::Result,
         source::{
-            VcsProtocol,
             Protocol,
             Source,
             IntegFile,
@@ -22,16 +19,14 @@ pub(crate) fn extract<P: AsRef<Path>>(dir: P, sources: &Vec<Source>) -> Result<(
         match &source.protocol {
             Protocol::Netfile { protocol: _ } => {
                 let integ_files = IntegFile::vec_from_source(source);
-                if let Some(integ_file) = integ_files.last() {
-                    original = Some(rel.join(integ_file.get_path()));
-                }
+                original = Some(match integ_files.last() {
+                    Some(integ_file) => rel.join(integ_file.get_path()),
+                    None => rel.join(super::fileurl::cache_path(source)),
+                });
             },
             Protocol::Vcs { protocol } =>
-                if let VcsProtocol::Git = protocol {
-                    original = Some(rel
-                        .join(format!("sources/git/{:016x}",
-                                xxh3_64(source.url.as_bytes()))));
-                },
+                original = Some(rel.join(
+                    super::vcs::cache_path(protocol, &source.url))),
             Protocol::Local => (),
         }
         if let Some(original) = original {
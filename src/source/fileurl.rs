@@ -0,0 +1,177 @@
+// Cache of netfile sources without any integrity checksum, keyed by the hash
+// of their URLs as `sources/file-url/[url hash]`. The HTTP validators of the
+// cached file are stored alongside as `[url hash].validator`, so later runs
+// only need conditional requests to revalidate them
+
+use std::{
+        ffi::OsString,
+        fs::{
+            read_to_string,
+            remove_file,
+            rename,
+            write,
+        },
+        path::{
+            Path,
+            PathBuf,
+        },
+    };
+
+use crate::{
+        error::{
+            Error,
+            Result
+        },
+        identity::IdentityActual,
+        source::{
+            cksums::Summer,
+            download::{
+                self,
+                Validator,
+            },
+            netfile::download_raw,
+            protocol::{
+                NetfileProtocol,
+                Protocol,
+            },
+            Proxy,
+            Source,
+        },
+    };
+
+pub(super) const PARENT: &str = "sources/file-url";
+
+pub(super) fn cache_name(source: &Source) -> String {
+    format!("{:016x}", source.hash_url)
+}
+
+pub(super) fn validator_name(source: &Source) -> String {
+    format!("{:016x}.validator", source.hash_url)
+}
+
+pub(super) fn cache_path(source: &Source) -> PathBuf {
+    PathBuf::from(PARENT).join(cache_name(source))
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn read_validator(path: &Path) -> Option<Validator> {
+    let content = read_to_string(path).ok()?;
+    match serde_json::from_str(&content) {
+        Ok(validator) => Some(validator),
+        Err(e) => {
+            log::warn!("Ignored malformed validator '{}': {}",
+                path.display(), e);
+            None
+        },
+    }
+}
+
+fn write_validator(path: &Path, validator: &Validator) -> Result<()> {
+    if validator.is_empty() {
+        return match remove_file(path) {
+            Ok(_) => Ok(()),
+            Err(e) => if e.kind() == std::io::ErrorKind::NotFound {
+                Ok(())
+            } else {
+                log::error!("Failed to remove outdated validator '{}': {}",
+                    path.display(), e);
+                Err(Error::IoError(e))
+            },
+        }
+    }
+    let content = serde_json::to_string(validator).map_err(|e|{
+        log::error!("Failed to serialize validator: {}", e);
+        Error::ImpossibleLogic
+    })?;
+    write(path, content).map_err(|e|{
+        log::error!("Failed to write validator '{}': {}", path.display(), e);
+        Error::IoError(e)
+    })
+}
+
+/// Cache or revalidate the source, a held or a stale cache is still used if
+/// the remote could not be reached
+pub(super) fn cache_source(
+    source: &Source,
+    actual_identity: &IdentityActual,
+    hold: bool,
+    proxy: Option<&Proxy>
+) -> Result<()>
+{
+    const MAX_TRIES: usize = 3;
+    let protocol =
+        if let Protocol::Netfile{protocol} = &source.protocol{
+            protocol
+        } else {
+            log::error!("Non-netfile source encountered by URL cacher");
+            return Err(Error::ImpossibleLogic)
+        };
+    let url = source.url.as_str();
+    let path = cache_path(source);
+    let path_validator = PathBuf::from(PARENT).join(validator_name(source));
+    let path_temp = with_suffix(&path, ".temp");
+    let cached = path.exists();
+    if cached && hold {
+        log::info!("Holding URL-keyed cache '{}' of '{}'",
+            path.display(), url);
+        return Ok(())
+    }
+    let validator = match cached {
+        true => read_validator(&path_validator),
+        false => None,
+    };
+    let mut proxy_actual = None;
+    let mut max_tries = MAX_TRIES;
+    let mut enable_proxy_at = MAX_TRIES;
+    if let Some(proxy) = proxy {
+        max_tries += proxy.after;
+        enable_proxy_at = proxy.after
+    };
+    for i in 0..max_tries {
+        if i == enable_proxy_at {
+            if i > 0 {
+                log::info!("Failed to download for {} times, using proxy", i);
+            }
+            proxy_actual = proxy.map(|proxy|proxy.url.as_str());
+        }
+        log::info!("Downloading '{}' to '{}', try {} of {}",
+            url, path_temp.display(), i + 1, max_tries);
+        let r = match protocol {
+            NetfileProtocol::Http | NetfileProtocol::Https =>
+                download::http(url, &path_temp, proxy_actual,
+                    validator.as_ref(), false, &mut Summer::default()),
+            _ => download_raw(protocol, url, &path_temp, actual_identity,
+                    proxy_actual).map(|_|Some(Validator::default())),
+        };
+        match r {
+            Ok(None) => {
+                log::info!("URL-keyed cache '{}' of '{}' is still fresh",
+                    path.display(), url);
+                return Ok(())
+            },
+            Ok(Some(validator)) => {
+                rename(&path_temp, &path).map_err(|e|{
+                    log::error!("Failed to move '{}' to '{}': {}",
+                        path_temp.display(), path.display(), e);
+                    Error::IoError(e)
+                })?;
+                return write_validator(&path_validator, &validator)
+            },
+            Err(_) => (),
+        }
+    }
+    let _ = remove_file(&path_temp);
+    if cached {
+        log::warn!("Failed to revalidate '{}', using the stale cache '{}'",
+            url, path.display());
+        Ok(())
+    } else {
+        log::error!("Failed to download netfile source '{}'", url);
+        Err(Error::IntegrityError)
+    }
+}
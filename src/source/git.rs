@@ -433,7 +433,12 @@ impl Repo {
         }
         log::info!("Syncing repo '{}' with '{}' ",
             &self.path.display(), &self.url);
-        Self::sync_raw(&self.repo, &self.url, proxy, refspecs, 3, terminal)
+        let r = Self::sync_raw(
+            &self.repo, &self.url, proxy, refspecs, 3, terminal);
+        if r.is_err() {
+            crate::report::repo_failed(&self.url, &self.path)
+        }
+        r
     }
 
     fn get_branch<'a>(&'a self, branch: &str) -> Result<Branch<'a>> {
@@ -0,0 +1,118 @@
+// URL rewrite rules for netfile sources, as the files are keyed by their
+// checksums, any mirror is safe to try before the upstream URL
+
+use std::collections::HashMap;
+
+use crate::{
+        error::{
+            Error,
+            Result
+        },
+        source::protocol::{
+            NetfileProtocol,
+            Protocol,
+        },
+    };
+
+pub(crate) struct Mirrors {
+    /// Prefix to replace and the prefixes replacing it, longest prefix first
+    rules: Vec<(String, Vec<String>)>,
+}
+
+impl Mirrors {
+    /// Both the prefixes and the mirrors must end with `/`, so a prefix could
+    /// only match whole path components
+    pub(crate) fn from_config(rules: &HashMap<String, Vec<String>>)
+        -> Result<Self>
+    {
+        for (prefix, mirrors) in rules.iter() {
+            for url in std::iter::once(prefix).chain(mirrors.iter()) {
+                if ! url.ends_with('/') {
+                    log::error!("Mirror prefix '{}' does not end with '/'",
+                        url);
+                    return Err(Error::InvalidConfig)
+                }
+            }
+        }
+        let mut rules: Vec<(String, Vec<String>)> = rules.iter().map(
+            |(prefix, mirrors)|(prefix.clone(), mirrors.clone())).collect();
+        rules.sort_unstable_by(|(some, _), (other, _)|
+            other.len().cmp(&some.len()).then(some.cmp(other)));
+        Ok(Self { rules })
+    }
+
+    /// The mirror URLs of the longest prefix matching the original URL, in
+    /// the order they're defined
+    pub(super) fn urls(&self, orig: &str) -> Vec<String> {
+        for (prefix, mirrors) in self.rules.iter() {
+            if let Some(suffix) = orig.strip_prefix(prefix.as_str()) {
+                return mirrors.iter().map(
+                    |mirror|format!("{}{}", mirror, suffix)).collect()
+            }
+        }
+        vec![]
+    }
+}
+
+/// The netfile protocol of a mirror URL, from its scheme
+pub(super) fn protocol(url: &str) -> Option<NetfileProtocol> {
+    let (scheme, _) = url.split_once("://")?;
+    match Protocol::from_raw_string(scheme.as_bytes())? {
+        Protocol::Netfile { protocol } => Some(protocol),
+        _ => {
+            log::warn!("Mirror '{}' is not a netfile URL", url);
+            None
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mirrors(rules: &[(&str, &[&str])]) -> Result<Mirrors> {
+        Mirrors::from_config(&rules.iter().map(|(prefix, mirrors)|(
+            prefix.to_string(),
+            mirrors.iter().map(|mirror|mirror.to_string()).collect()
+        )).collect())
+    }
+
+    #[test]
+    fn mirror_longest_prefix() {
+        let mirrors = mirrors(&[
+            ("https://example.com/", &["https://a.lan/"]),
+            ("https://example.com/pub/", &["https://b.lan/"]),
+        ]).unwrap();
+        assert_eq!(mirrors.urls("https://example.com/pub/foo.tar"),
+            ["https://b.lan/foo.tar"]);
+        assert_eq!(mirrors.urls("https://example.com/other/foo.tar"),
+            ["https://a.lan/other/foo.tar"]);
+    }
+
+    #[test]
+    fn mirror_order_kept() {
+        let mirrors = mirrors(&[("https://example.com/",
+            &["https://c.lan/", "https://a.lan/", "https://b.lan/"])]).unwrap();
+        assert_eq!(mirrors.urls("https://example.com/foo.tar"), [
+            "https://c.lan/foo.tar",
+            "https://a.lan/foo.tar",
+            "https://b.lan/foo.tar",
+        ]);
+    }
+
+    #[test]
+    fn mirror_no_match() {
+        let mirrors = mirrors(&[
+            ("https://github.com/", &["https://a.lan/"])]).unwrap();
+        assert!(mirrors.urls("https://github.company.com/foo.tar").is_empty());
+        assert!(mirrors.urls("https://gitlab.com/foo.tar").is_empty());
+    }
+
+    #[test]
+    fn mirror_prefix_without_slash() {
+        assert!(mirrors(&[("https://github.com", &["https://a.lan/"])])
+            .is_err());
+        assert!(mirrors(&[("https://github.com/", &["https://a.lan"])])
+            .is_err());
+    }
+}
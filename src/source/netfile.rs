@@ -1,10 +1,14 @@
+use std::path::Path;
+
 use crate::{
         error::{
             Error,
             Result
         },
         source::{
+            cksums::Summer,
             download,
+            mirror,
             protocol::{
                 NetfileProtocol,
                 Protocol,
@@ -51,6 +55,15 @@ pub(super) fn push_source(
     sources: &mut Vec<Source>, source: &Source
 ) -> Result<()>
 {
+    // Those without integ are cached by URL, so deduplicated by URL
+    if source.no_integ() {
+        if ! sources.iter().any(|source_cmp|
+            source_cmp.no_integ() && source_cmp.hash_url == source.hash_url)
+        {
+            sources.push(source.clone())
+        }
+        return Ok(())
+    }
     let mut existing = None;
     for source_cmp in sources.iter_mut() {
         if optional_equal(
@@ -97,12 +110,92 @@ pub(super) fn push_source(
         &mut existing.b2, &source.b2)
 }
 
+/// Download with the tool for the protocol, without any check
+pub(super) fn download_raw(
+    protocol: &NetfileProtocol,
+    url: &str,
+    path: &Path,
+    actual_identity: &crate::identity::IdentityActual,
+    proxy: Option<&str>
+) -> Result<()>
+{
+    match protocol {
+        NetfileProtocol::File =>
+            download::file(url, path),
+        NetfileProtocol::Ftp =>
+            download::ftp(actual_identity, url, path),
+        NetfileProtocol::Http | NetfileProtocol::Https =>
+            download::http(url, path, proxy, None, false,
+                &mut Summer::default()).map(|_|()),
+        NetfileProtocol::Rsync =>
+            download::rsync(actual_identity, url, path),
+        NetfileProtocol::Scp =>
+            download::scp(actual_identity, url, path),
+    }
+}
+
+/// Download from one URL once, true if the file is verified and absorbed
+fn download_try(
+    source: &Source,
+    protocol: &NetfileProtocol,
+    url: &str,
+    integ_file: &super::cksums::IntegFile,
+    actual_identity: &crate::identity::IdentityActual,
+    skipint: bool,
+    proxy: Option<&str>
+) -> Result<bool>
+{
+    let integ_file_temp = integ_file.temp()?;
+    // All checksums of the source are computed while streaming for http(s),
+    // partial downloads are kept and resumed in the next try
+    let mut summer = Summer::from_source(source);
+    let downloaded = match protocol {
+        NetfileProtocol::Http | NetfileProtocol::Https =>
+            download::http(url, &integ_file_temp.path, proxy,
+                None, true, &mut summer).is_ok(),
+        _ => download_raw(protocol, url, &integ_file_temp.path,
+                actual_identity, proxy).is_ok() &&
+            summer.update_from_file(&integ_file_temp.path),
+    };
+    if ! downloaded {
+        return Ok(false)
+    }
+    if skipint {
+        log::warn!("Integrity check skipped for downloaded '{}'",
+            integ_file_temp.path.display());
+    } else if ! summer.verify(source) {
+        log::error!("Downloaded '{}' from '{}' is corrupted, removing it",
+            integ_file_temp.path.display(), url);
+        let _ = std::fs::remove_file(&integ_file_temp.path);
+        return Ok(false)
+    }
+    Ok(integ_file.absorb(integ_file_temp).is_ok())
+}
+
+/// Remove the partial download kept for resuming, so that a different URL
+/// would not resume on top of it
+fn remove_temp(integ_file: &super::cksums::IntegFile) -> Result<()> {
+    let integ_file_temp = integ_file.temp()?;
+    match std::fs::remove_file(&integ_file_temp.path) {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => {
+            log::error!("Failed to remove partial download '{}': {}",
+                integ_file_temp.path.display(), e);
+            Err(e.into())
+        },
+    }
+}
+
+/// Try the mirrors once each in order, then the upstream URL. Mirrors are only
+/// tried if what they serve could be verified
 pub(super) fn download_source(
     source: &Source,
     integ_file: &super::cksums::IntegFile,
     actual_identity: &crate::identity::IdentityActual,
     skipint: bool,
-    proxy: Option<&Proxy>
+    proxy: Option<&Proxy>,
+    mirrors: &[String]
 ) -> Result<()>
 {
     const MAX_TRIES: usize = 3;
@@ -113,6 +206,30 @@ pub(super) fn download_source(
             log::error!("Non-netfile source encountered by netfile cacher");
             return Err(Error::ImpossibleLogic)
         };
+    let mirrors = if ! mirrors.is_empty() && (skipint || source.no_integ()) {
+        log::warn!("Mirrors skipped for '{}' as its integrity could not be \
+            verified", source.url);
+        &[]
+    } else {
+        mirrors
+    };
+    for mirror in mirrors.iter() {
+        let protocol = match mirror::protocol(mirror) {
+            Some(protocol) => protocol,
+            None => continue,
+        };
+        log::info!("Downloading '{}' to '{}' from mirror '{}'",
+            source.url, integ_file.path.display(), mirror);
+        remove_temp(integ_file)?;
+        if download_try(source, &protocol, mirror, integ_file,
+            actual_identity, skipint, None)?
+        {
+            return Ok(())
+        }
+    }
+    if ! mirrors.is_empty() {
+        remove_temp(integ_file)?
+    }
     let url = source.url.as_str();
     let mut proxy_actual = None;
     let mut max_tries = MAX_TRIES;
@@ -129,28 +246,12 @@ pub(super) fn download_source(
             proxy_actual = proxy.and_then(
                 |proxy|Some(proxy.url.as_str()));
         }
-        let integ_file_temp = integ_file.temp()?;
         log::info!("Downloading '{}' to '{}', try {} of {}",
-            source.url, integ_file_temp.path.display(), i + 1, max_tries);
-        if match &protocol {
-            NetfileProtocol::File =>
-                download::file(url, &integ_file_temp.path),
-            NetfileProtocol::Ftp =>
-                download::ftp(actual_identity, url, &integ_file_temp.path),
-            NetfileProtocol::Http =>
-                download::http(url, &integ_file_temp.path, proxy_actual),
-            NetfileProtocol::Https =>
-                download::http(url, &integ_file_temp.path, proxy_actual),
-            NetfileProtocol::Rsync =>
-                download::rsync(actual_identity, url, &integ_file_temp.path),
-            NetfileProtocol::Scp =>
-                download::scp(actual_identity, url, &integ_file_temp.path),
-        }.is_ok() &&
-            integ_file_temp.valid(skipint)
+            source.url, integ_file.path.display(), i + 1, max_tries);
+        if download_try(source, protocol, url, integ_file, actual_identity,
+            skipint, proxy_actual)?
         {
-            if integ_file.absorb(integ_file_temp).is_ok() {
-                return Ok(())
-            }
+            return Ok(())
         }
     }
     log::error!("Failed to download netfile source '{}'", source.url);
@@ -162,7 +263,8 @@ pub(super) fn cache_source(
     integ_files: &Vec<super::cksums::IntegFile>,
     actual_identity: &crate::identity::IdentityActual,
     skipint: bool,
-    proxy: Option<&Proxy>
+    proxy: Option<&Proxy>,
+    mirrors: &[String]
 ) -> Result<()>
 {
     assert!(integ_files.len() > 0, "No integ files");
@@ -187,12 +289,20 @@ pub(super) fn cache_source(
         return Ok(())
     }
     let mut bad_count = 0;
+    // A fresh download is verified against all checksums of the source, so
+    // the other integ files cloned from it need no more check
+    let mut verified = false;
     while let Some(bad_file) = bad_files.pop() {
         let r = match good_files.last() {
             Some(good_file) =>
-                bad_file.clone_file_from(good_file),
-            None => download_source(
-                source, bad_file, actual_identity, skipint, proxy),
+                bad_file.clone_file_from(good_file, ! verified),
+            None => {
+                let r = download_source(
+                    source, bad_file, actual_identity, skipint, proxy,
+                    mirrors);
+                verified = r.is_ok();
+                r
+            },
         };
         match r {
             Ok(_) => good_files.push(bad_file),
@@ -0,0 +1,254 @@
+// Caches of VCS sources other than git, maintained with the VCS tools
+// themselves, in the same layout makepkg would create under SRCDEST, so they
+// could be linked into build dirs and taken as-is by makepkg with HOLDVER
+
+use std::{
+        fs::{
+            create_dir_all,
+            remove_file,
+        },
+        path::PathBuf,
+        process::Command,
+        str::FromStr,
+    };
+
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::{
+        error::{
+            Error,
+            Result
+        },
+        filesystem::remove_dir_all_try_best,
+        identity::IdentityActual,
+        source::{
+            Proxy,
+            Protocol,
+            Source,
+            VcsProtocol,
+        },
+    };
+
+pub(super) const PARENTS: [&str; 4] = [
+    "sources/bzr", "sources/fossil", "sources/hg", "sources/svn"];
+
+pub(super) fn parent(protocol: &VcsProtocol) -> &'static str {
+    match protocol {
+        VcsProtocol::Bzr => "sources/bzr",
+        VcsProtocol::Fossil => "sources/fossil",
+        VcsProtocol::Git => "sources/git",
+        VcsProtocol::Hg => "sources/hg",
+        VcsProtocol::Svn => "sources/svn",
+    }
+}
+
+/// The cache path of a VCS source, `sources/[vcs]/[url hash]`
+pub(super) fn cache_path(protocol: &VcsProtocol, url: &str) -> PathBuf {
+    PathBuf::from(parent(protocol))
+        .join(format!("{:016x}", xxh3_64(url.as_bytes())))
+}
+
+pub(super) fn push_source(sources: &mut Vec<Source>, source: &Source) {
+    for source_cmp in sources.iter() {
+        if source.hash_url == source_cmp.hash_url {
+            return
+        }
+    }
+    sources.push(source.clone())
+}
+
+pub(super) struct VcsCache {
+    protocol: VcsProtocol,
+    url: String,
+    path: PathBuf,
+}
+
+impl VcsCache {
+    pub(super) fn from_source(source: &Source) -> Result<Self> {
+        match &source.protocol {
+            Protocol::Vcs { protocol: VcsProtocol::Git } | Protocol::Netfile {
+                protocol: _ } | Protocol::Local => {
+                log::error!("Source '{}' ('{}') is not a non-git VCS source",
+                    source.name, source.url);
+                Err(Error::ImpossibleLogic)
+            },
+            Protocol::Vcs { protocol } => Ok(Self {
+                protocol: protocol.clone(),
+                url: source.url.clone(),
+                path: cache_path(protocol, &source.url),
+            }),
+        }
+    }
+
+    /// Whether the cache looks like one created by the VCS tool, fossil
+    /// repos are single files while the others are dirs
+    pub(super) fn healthy(&self) -> bool {
+        match self.protocol {
+            VcsProtocol::Bzr => self.path.join(".bzr").is_dir(),
+            VcsProtocol::Fossil => self.path.is_file(),
+            VcsProtocol::Git => false,
+            VcsProtocol::Hg => self.path.join(".hg").is_dir(),
+            VcsProtocol::Svn => self.path.join(".svn").is_dir(),
+        }
+    }
+
+    fn remove(&self) -> Result<()> {
+        if self.path.is_dir() {
+            remove_dir_all_try_best(&self.path)
+        } else if self.path.exists() || self.path.is_symlink() {
+            remove_file(&self.path).map_err(|e|{
+                log::error!("Failed to remove broken cache '{}': {}",
+                    self.path.display(), e);
+                Error::IoError(e)
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Same commands as makepkg's download_[vcs]() when the cache is missing
+    fn command_clone(&self) -> Result<Command> {
+        let mut command;
+        match self.protocol {
+            VcsProtocol::Bzr => {
+                command = Command::new("/usr/bin/bzr");
+                command.arg("branch").arg(&self.url).arg(&self.path)
+                    .arg("--no-tree").arg("--use-existing-dir");
+            },
+            VcsProtocol::Fossil => {
+                command = Command::new("/usr/bin/fossil");
+                command.arg("clone").arg(&self.url).arg(&self.path);
+            },
+            VcsProtocol::Git => {
+                log::error!("Git source '{}' should be cached by git2",
+                    self.url);
+                return Err(Error::ImpossibleLogic)
+            },
+            VcsProtocol::Hg => {
+                command = Command::new("/usr/bin/hg");
+                command.arg("clone").arg("-U").arg(&self.url).arg(&self.path);
+            },
+            VcsProtocol::Svn => {
+                let config_dir = self.path.join(".makepkg");
+                create_dir_all(&config_dir).map_err(|e|{
+                    log::error!("Failed to create svn config dir '{}': {}",
+                        config_dir.display(), e);
+                    Error::IoError(e)
+                })?;
+                command = Command::new("/usr/bin/svn");
+                command.arg("checkout")
+                    .arg("--config-dir").arg(self.path.join(".makepkg"))
+                    .arg(&self.url).arg(&self.path);
+            },
+        }
+        Ok(command)
+    }
+
+    /// Same commands as makepkg's download_[vcs]() when the cache exists
+    fn command_update(&self) -> Result<Command> {
+        let mut command;
+        match self.protocol {
+            VcsProtocol::Bzr => {
+                command = Command::new("/usr/bin/bzr");
+                command.arg("pull").arg("-d").arg(&self.path).arg(&self.url);
+            },
+            VcsProtocol::Fossil => {
+                command = Command::new("/usr/bin/fossil");
+                command.arg("pull").arg(&self.url).arg("-R").arg(&self.path);
+            },
+            VcsProtocol::Git => {
+                log::error!("Git source '{}' should be cached by git2",
+                    self.url);
+                return Err(Error::ImpossibleLogic)
+            },
+            VcsProtocol::Hg => {
+                command = Command::new("/usr/bin/hg");
+                command.arg("pull").arg("-R").arg(&self.path);
+            },
+            VcsProtocol::Svn => {
+                command = Command::new("/usr/bin/svn");
+                command.arg("update")
+                    .arg("--config-dir").arg(self.path.join(".makepkg"))
+                    .arg(&self.path);
+            },
+        }
+        Ok(command)
+    }
+
+    /// svn ignores the proxy environment variables and only takes the proxy
+    /// from its servers config
+    fn set_proxy(&self, command: &mut Command, proxy: &str) {
+        if let VcsProtocol::Svn = self.protocol {
+            let url = match url::Url::from_str(proxy) {
+                Ok(url) => url,
+                Err(e) => {
+                    log::warn!("Failed to parse proxy '{}' for svn: {}",
+                        proxy, e);
+                    return
+                },
+            };
+            if let Some(host) = url.host_str() {
+                command.arg("--config-option").arg(
+                    format!("servers:global:http-proxy-host={}", host));
+            }
+            if let Some(port) = url.port_or_known_default() {
+                command.arg("--config-option").arg(
+                    format!("servers:global:http-proxy-port={}", port));
+            }
+        } else {
+            command
+                .env("http_proxy", proxy)
+                .env("https_proxy", proxy)
+                .env("HTTP_PROXY", proxy)
+                .env("HTTPS_PROXY", proxy);
+        }
+    }
+
+    pub(super) fn sync(
+        &self, actual_identity: &IdentityActual, proxy: Option<&Proxy>
+    ) -> Result<()>
+    {
+        const MAX_TRIES: usize = 3;
+        let mut proxy_actual = None;
+        let mut r = Ok(());
+        let mut max_tries = MAX_TRIES;
+        let mut enable_proxy_at = MAX_TRIES;
+        if let Some(proxy) = proxy {
+            max_tries += proxy.after;
+            enable_proxy_at = proxy.after
+        };
+        for i in 0..max_tries {
+            if i == enable_proxy_at {
+                if i > 0 {
+                    log::info!("Failed to sync for {} times, using proxy", i);
+                }
+                proxy_actual = proxy.map(|proxy|proxy.url.as_str());
+            }
+            let mut command = if self.healthy() {
+                log::info!("Updating {:?} cache '{}' from '{}', try {} of {}",
+                    self.protocol, self.path.display(), self.url,
+                    i + 1, max_tries);
+                self.command_update()?
+            } else {
+                self.remove()?;
+                log::info!("Cloning {:?} source '{}' to '{}', try {} of {}",
+                    self.protocol, self.url, self.path.display(),
+                    i + 1, max_tries);
+                self.command_clone()?
+            };
+            actual_identity.set_root_drop_command(&mut command);
+            if let Some(proxy) = proxy_actual {
+                self.set_proxy(&mut command, proxy)
+            }
+            let job = format!("sync {:?} cache '{}' from '{}'",
+                self.protocol, self.path.display(), self.url);
+            r = crate::child::output_and_check(&mut command, &job);
+            if r.is_ok() {
+                return r
+            }
+        }
+        log::error!("Failed to sync {:?} cache '{}' from '{}' after {} tries",
+            self.protocol, self.path.display(), self.url, max_tries);
+        r
+    }
+}
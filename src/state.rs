@@ -0,0 +1,382 @@
+// Persistent state of runs and builds, appended as JSON lines so history is
+// never rewritten and could be inspected with plain tools like jq
+
+use std::{
+        fs::{
+            File,
+            OpenOptions,
+            remove_file,
+        },
+        io::{
+            BufRead,
+            BufReader,
+            Write,
+        },
+        path::{
+            Path,
+            PathBuf,
+        },
+    };
+
+use serde::{
+        Deserialize,
+        Serialize,
+    };
+
+use crate::{
+        error::{
+            Error,
+            Result
+        },
+        filesystem::create_dir_allow_existing,
+        pkgbuild::PKGBUILD,
+    };
+
+pub(crate) fn now() -> Result<String> {
+    time::OffsetDateTime::now_utc().format(
+        &time::format_description::well_known::Rfc3339
+    ).map_err(|e|{
+        log::error!("Failed to format time: {}", e);
+        Error::TimeError(e.into())
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Outcome {
+    Success,
+    Failure,
+    /// Not built at all as one of its dependencies failed
+    Skipped,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct BuildRecord {
+    pub(crate) run: String,
+    pub(crate) base: String,
+    /// The target arch, not set for the host arch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) arch: Option<String>,
+    pub(crate) pkgid: String,
+    pub(crate) commit: String,
+    pub(crate) dephash: String,
+    pub(crate) pkgver: Option<String>,
+    /// The hash of env and makepkg args, not set if neither is
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) options: Option<String>,
+    pub(crate) start: Option<String>,
+    pub(crate) end: String,
+    pub(crate) tries: usize,
+    pub(crate) log: Option<PathBuf>,
+    pub(crate) result: Outcome,
+    /// Why the PKGBUILD was built, compared to its last successful build
+    pub(crate) reason: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct RunRecord {
+    pub(crate) run: String,
+    pub(crate) start: String,
+    pub(crate) end: String,
+    pub(crate) built: usize,
+    pub(crate) failed: usize,
+    #[serde(default)]
+    pub(crate) skipped: usize,
+    pub(crate) result: Outcome,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub(crate) enum Record {
+    Build (BuildRecord),
+    Run (RunRecord),
+}
+
+/// A component of the pkgid that changed since the last successful build
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct Change {
+    pub(crate) component: &'static str,
+    pub(crate) old: Option<String>,
+    pub(crate) new: Option<String>,
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} -> {}", self.component,
+            self.old.as_deref().unwrap_or("(none)"),
+            self.new.as_deref().unwrap_or("(none)"))
+    }
+}
+
+/// The state DB at `state/builds.jsonl`, one record per line
+pub(crate) struct StateDb {
+    file: File,
+    run: String,
+    start: String,
+    builds: Vec<BuildRecord>,
+    built: usize,
+    failed: usize,
+    skipped: usize,
+}
+
+impl StateDb {
+    const DIR: &'static str = "state";
+    const PATH: &'static str = "state/builds.jsonl";
+
+    fn read_builds(path: &Path) -> Result<Vec<BuildRecord>> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) => if e.kind() == std::io::ErrorKind::NotFound {
+                return Ok(vec![])
+            } else {
+                log::error!("Failed to open state DB '{}': {}",
+                    path.display(), e);
+                return Err(Error::IoError(e))
+            },
+        };
+        let mut builds = vec![];
+        for (id, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e|{
+                log::error!("Failed to read state DB '{}': {}",
+                    path.display(), e);
+                Error::IoError(e)
+            })?;
+            if line.trim().is_empty() {
+                continue
+            }
+            match serde_json::from_str(&line) {
+                Ok(Record::Build(build)) => builds.push(build),
+                Ok(Record::Run(_)) => (),
+                Err(e) => log::warn!("Ignored malformed line {} of state DB \
+                    '{}': {}", id + 1, path.display(), e),
+            }
+        }
+        Ok(builds)
+    }
+
+    pub(crate) fn open() -> Result<Self> {
+        create_dir_allow_existing(Self::DIR)?;
+        let path = PathBuf::from(Self::PATH);
+        let builds = Self::read_builds(&path)?;
+        let file = OpenOptions::new().create(true).append(true)
+            .open(&path).map_err(|e|{
+                log::error!("Failed to open state DB '{}' to append: {}",
+                    path.display(), e);
+                Error::IoError(e)
+            })?;
+        let start = now()?;
+        Ok(Self {
+            file,
+            run: start.clone(),
+            start,
+            builds,
+            built: 0,
+            failed: 0,
+            skipped: 0,
+        })
+    }
+
+    fn append(&mut self, record: &Record) -> Result<()> {
+        let mut line = serde_json::to_string(record).map_err(|e|{
+            log::error!("Failed to serialize state record: {}", e);
+            Error::ImpossibleLogic
+        })?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes()).map_err(|e|{
+            log::error!("Failed to append to state DB: {}", e);
+            Error::IoError(e)
+        })
+    }
+
+    pub(crate) fn run(&self) -> &str {
+        &self.run
+    }
+
+    pub(crate) fn start(&self) -> &str {
+        &self.start
+    }
+
+    /// Builds recorded in this run, in the order they finished
+    pub(crate) fn run_builds(&self)
+        -> impl DoubleEndedIterator<Item = &BuildRecord>
+    {
+        self.builds.iter().filter(|build|build.run == self.run)
+    }
+
+    /// The last successful build of a PKGBUILD for the arch, in any run
+    pub(crate) fn last_success(&self, base: &str, arch: Option<&str>)
+        -> Option<&BuildRecord>
+    {
+        self.builds.iter().rev().find(|build|
+            build.base == base && build.arch.as_deref() == arch &&
+            build.result == Outcome::Success)
+    }
+
+    /// The last successful build of the PKGBUILD and the components of the
+    /// pkgid changed since then
+    pub(crate) fn changes(&self, pkgbuild: &PKGBUILD)
+        -> Option<(&BuildRecord, Vec<Change>)>
+    {
+        let last = self.last_success(&pkgbuild.base, pkgbuild.arch())?;
+        let commit = pkgbuild.commit().to_string();
+        let dephash = pkgbuild.dephash_hex();
+        let pkgver = pkgbuild.pkgver();
+        let options = pkgbuild.options_hex();
+        let mut changes = vec![];
+        if last.commit != commit {
+            changes.push(Change { component: "commit",
+                old: Some(last.commit.clone()), new: Some(commit) })
+        }
+        if last.dephash != dephash {
+            changes.push(Change { component: "dephash",
+                old: Some(last.dephash.clone()), new: Some(dephash) })
+        }
+        if last.pkgver.as_deref() != pkgver {
+            changes.push(Change { component: "pkgver",
+                old: last.pkgver.clone(), new: pkgver.map(String::from) })
+        }
+        if last.options != options {
+            changes.push(Change { component: "options",
+                old: last.options.clone(), new: options })
+        }
+        Some((last, changes))
+    }
+
+    /// Why the PKGBUILD needs build, compared to its last successful build
+    pub(crate) fn reason(&self, pkgbuild: &PKGBUILD) -> String {
+        let (last, changes) = match self.changes(pkgbuild) {
+            Some(changes) => changes,
+            None => return String::from("no previous successful build"),
+        };
+        if changes.is_empty() {
+            format!("packages of '{}' missing", last.pkgid)
+        } else {
+            changes.iter().map(|change|change.to_string())
+                .collect::<Vec<_>>().join(", ")
+        }
+    }
+
+    /// Record the final outcome of a PKGBUILD's build in this run, reason is
+    /// deduced from the last successful build if not set
+    pub(crate) fn record_build(
+        &mut self, pkgbuild: &PKGBUILD, start: Option<String>, tries: usize,
+        log: Option<&Path>, result: Outcome, reason: Option<String>
+    ) -> Result<()>
+    {
+        let build = BuildRecord {
+            run: self.run.clone(),
+            base: pkgbuild.base.clone(),
+            arch: pkgbuild.arch().map(String::from),
+            pkgid: pkgbuild.pkgid.clone(),
+            commit: pkgbuild.commit().to_string(),
+            dephash: pkgbuild.dephash_hex(),
+            pkgver: pkgbuild.pkgver().map(|pkgver|pkgver.to_string()),
+            options: pkgbuild.options_hex(),
+            start,
+            end: now()?,
+            tries,
+            log: log.map(|log|log.to_owned()),
+            result,
+            reason: reason.unwrap_or_else(||self.reason(pkgbuild)),
+        };
+        match result {
+            Outcome::Success => self.built += 1,
+            Outcome::Failure => self.failed += 1,
+            Outcome::Skipped => self.skipped += 1,
+        }
+        self.append(&Record::Build(build.clone()))?;
+        self.builds.push(build);
+        Ok(())
+    }
+
+    /// Record the run itself, should be called once at the end
+    pub(crate) fn finish_run(&mut self, result: Outcome) -> Result<()> {
+        let run = RunRecord {
+            run: self.run.clone(),
+            start: self.start.clone(),
+            end: now()?,
+            built: self.built,
+            failed: self.failed,
+            skipped: self.skipped,
+            result,
+        };
+        self.append(&Record::Run(run))
+    }
+}
+
+/// A marker of a pkgid that failed all tries, at `state/failed/[pkgid]`. As
+/// the pkgid contains the commit, dephash, options and pkgver, the marker no
+/// longer applies once any of them changes.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct FailureMarker {
+    pub(crate) time: String,
+    pub(crate) tries: usize,
+    pub(crate) log: Option<PathBuf>,
+}
+
+impl FailureMarker {
+    const DIR: &'static str = "state/failed";
+
+    fn path(pkgid: &str) -> PathBuf {
+        PathBuf::from(Self::DIR).join(pkgid)
+    }
+
+    pub(crate) fn read(pkgid: &str) -> Option<Self> {
+        let content = std::fs::read_to_string(Self::path(pkgid)).ok()?;
+        match serde_json::from_str(&content) {
+            Ok(marker) => Some(marker),
+            Err(e) => {
+                log::warn!("Failure marker of '{}' is malformed, still \
+                    respecting it: {}", pkgid, e);
+                Some(Self { time: String::new(), tries: 0, log: None })
+            },
+        }
+    }
+
+    pub(crate) fn mark(pkgid: &str, tries: usize, log: Option<&Path>)
+        -> Result<()>
+    {
+        create_dir_allow_existing(Self::DIR)?;
+        let marker = Self {
+            time: now()?,
+            tries,
+            log: log.map(|log|log.to_owned()),
+        };
+        let content = serde_json::to_string(&marker).map_err(|e|{
+            log::error!("Failed to serialize failure marker: {}", e);
+            Error::ImpossibleLogic
+        })?;
+        let path = Self::path(pkgid);
+        std::fs::write(&path, content).map_err(|e|{
+            log::error!("Failed to write failure marker '{}': {}",
+                path.display(), e);
+            Error::IoError(e)
+        })?;
+        log::warn!("Marked '{}' as failed, it would be skipped until its \
+            inputs change or --retry-failed is set", pkgid);
+        Ok(())
+    }
+
+    pub(crate) fn clear(pkgid: &str) -> Result<()> {
+        let path = Self::path(pkgid);
+        match remove_file(&path) {
+            Ok(_) => {
+                log::info!("Cleared failure marker of '{}'", pkgid);
+                Ok(())
+            },
+            Err(e) => if e.kind() == std::io::ErrorKind::NotFound {
+                Ok(())
+            } else {
+                log::error!("Failed to remove failure marker '{}': {}",
+                    path.display(), e);
+                Err(Error::IoError(e))
+            },
+        }
+    }
+
+    /// Remove markers of pkgids not in the config anymore, used must be
+    /// already sorted
+    pub(crate) fn remove_unused(used: &Vec<String>) {
+        crate::source::remove_unused(Self::DIR, used)
+    }
+}